@@ -1,48 +1,232 @@
 // Copyright 2019-2022 ChainSafe Systems
 // SPDX-License-Identifier: Apache-2.0, MIT
 
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Seek};
+use std::sync::Mutex;
+
 use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use cid::Cid;
 use fvm_shared::blockstore::{Blockstore, Buffered};
+use fvm_shared::encoding::DAG_CBOR;
+use fvm_shared::IPLD_RAW;
+
+/// Stats for a [`BufferedBlockstore`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BufferedStats {
+    /// Number of blocks written to the buffer.
+    pub puts: usize,
+    /// Number of reads served from the buffer.
+    pub hits: usize,
+    /// Number of reads passed through to the base store.
+    pub misses: usize,
+    /// Number of blocks written to the base store on flush.
+    pub flushed: usize,
+    /// Bytes written to the base store on flush.
+    pub flushed_bytes: usize,
+    /// Number of buffered blocks dropped on flush because they weren't reachable from the root.
+    pub discarded: usize,
+    /// Number of blocks written to the base store because the buffer exceeded its memory limit.
+    pub spilled: usize,
+}
 
-// TODO: figure out where to put this.
-const DAG_CBOR: u64 = 0x71;
+/// The blocks buffered by a [`BufferedBlockstore`] or [`SyncBufferedBlockstore`], and their
+/// bookkeeping.
+#[derive(Debug)]
+struct Buffer {
+    blocks: HashMap<Cid, Vec<u8>>,
+    /// The total size of the buffered blocks.
+    size: usize,
+    /// The maximum size of the buffer before it's spilled into the base store.
+    limit: usize,
+    /// The blocks first buffered inside each open transaction, innermost last.
+    transactions: Vec<Vec<Cid>>,
+    stats: BufferedStats,
+}
 
-// NOTE: This code doesn't currently work. It was taken from the ipld/blockstore crate but now lives here because that "blockstore" is really the actor store.
-// TODO:
-// 1. Finish converting it to a true blockstore.
-// 2. Add bulk put methods to the blockstore.
+impl Buffer {
+    fn new(limit: usize) -> Self {
+        Self {
+            blocks: HashMap::new(),
+            size: 0,
+            limit,
+            transactions: Vec::new(),
+            stats: Default::default(),
+        }
+    }
 
-// TODO: replace HashMap with DashMap like in forest?
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::{Cursor, Read, Seek};
+    /// Returns the buffered block, if any, recording a hit or a miss.
+    fn get(&mut self, k: &Cid) -> Option<Vec<u8>> {
+        let block = self.blocks.get(k).cloned();
+        match block {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        block
+    }
+
+    fn begin_transaction(&mut self) {
+        self.transactions.push(Vec::new());
+    }
+
+    fn end_transaction(&mut self, revert: bool) -> Result<()> {
+        let added = self
+            .transactions
+            .pop()
+            .ok_or_else(|| anyhow!("no transaction to end"))?;
+        if revert {
+            for k in &added {
+                if let Some(block) = self.blocks.remove(k) {
+                    self.size -= block.len();
+                }
+            }
+            self.stats.discarded += added.len();
+        } else if let Some(outer) = self.transactions.last_mut() {
+            outer.extend(added);
+        }
+        Ok(())
+    }
+
+    fn put_many_keyed<BS, D, I>(&mut self, base: &BS, blocks: I) -> Result<()>
+    where
+        BS: Blockstore,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        for (k, block) in blocks {
+            let block = block.as_ref();
+            self.stats.puts += 1;
+            // Blocks are content-addressed, so there's no need to replace existing ones.
+            if let Entry::Vacant(e) = self.blocks.entry(k) {
+                self.size += block.len();
+                e.insert(block.into());
+                if let Some(added) = self.transactions.last_mut() {
+                    added.push(k);
+                }
+            }
+        }
+        // Spilled blocks can't be discarded, so we only spill outside of transactions.
+        if self.size > self.limit && self.transactions.is_empty() {
+            self.spill(base)?;
+        }
+        Ok(())
+    }
+
+    /// Writes all buffered blocks to the base store, emptying the buffer.
+    fn spill<BS: Blockstore>(&mut self, base: &BS) -> Result<()> {
+        let blocks = std::mem::take(&mut self.blocks);
+        self.size = 0;
+        self.stats.spilled += blocks.len();
+        base.put_many_keyed(blocks)
+    }
+
+    fn flush<BS: Blockstore>(&mut self, base: &BS, root: &Cid) -> Result<()> {
+        if !self.transactions.is_empty() {
+            return Err(anyhow!("cannot flush a blockstore with open transactions"));
+        }
+        if !self.blocks.contains_key(root) && !base.has(root)? {
+            return Err(anyhow!("root {} not found", root));
+        }
+
+        let mut blocks = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![*root];
+        while let Some(cid) = stack.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            let block = match self.blocks.get(&cid) {
+                Some(block) => block,
+                None => continue,
+            };
+            match cid.codec() {
+                DAG_CBOR => scan_for_links(&mut Cursor::new(block), |link| {
+                    stack.push(link);
+                    Ok(())
+                })?,
+                // Raw blocks can't link to other blocks.
+                IPLD_RAW => {}
+                // Neither can blocks with codecs we don't understand, as far as we're concerned.
+                _ => {}
+            }
+            blocks.push((cid, &block[..]));
+        }
+
+        base.put_many_keyed(blocks.iter().copied())?;
+
+        self.stats.flushed += blocks.len();
+        self.stats.flushed_bytes += blocks.iter().map(|(_, b)| b.len()).sum::<usize>();
+        self.stats.discarded += self.blocks.len() - blocks.len();
 
-// TODO: This is going to live in the kernel so it should be a Blockstore, not an ActorStore.
+        self.blocks.clear();
+        self.size = 0;
+
+        Ok(())
+    }
+}
 
 /// Wrapper around `Blockstore` to limit and have control over when values are written.
-/// This type is not threadsafe and can only be used in synchronous contexts.
+///
+/// Writes are buffered in memory until [`Buffered::flush`] is called, which writes the blocks
+/// reachable from the given root to the base store, and drops the rest. If the buffer grows past
+/// its memory limit, all buffered blocks are "spilled" into the base store, reachable or not.
+///
+/// Only DAG-CBOR blocks are scanned for links. Raw blocks are opaque bytes, and blocks with any
+/// other codec are treated the same way (as the kernel does when it scans for links): they're
+/// written if they're reachable, but can't link to other blocks.
+///
+/// Blocks written inside a transaction (see [`BufferedBlockstore::begin_transaction`]) can be
+/// discarded by reverting it. The buffer isn't spilled while a transaction is open.
+///
+/// This type can be moved between threads (if the base store can), but can't be shared between
+/// them: see [`SyncBufferedBlockstore`] for that.
 #[derive(Debug)]
 pub struct BufferedBlockstore<BS> {
     base: BS,
-    write: RefCell<HashMap<Cid, Vec<u8>>>,
+    buffer: RefCell<Buffer>,
 }
 
 impl<BS> BufferedBlockstore<BS>
 where
     BS: Blockstore,
 {
+    /// Creates a buffered blockstore with an unbounded write buffer.
     pub fn new(base: BS) -> Self {
+        Self::with_limit(base, usize::MAX)
+    }
+
+    /// Creates a buffered blockstore that spills its write buffer into the base store once it
+    /// holds more than `limit` bytes.
+    pub fn with_limit(base: BS, limit: usize) -> Self {
         Self {
             base,
-            write: Default::default(),
+            buffer: RefCell::new(Buffer::new(limit)),
         }
     }
 
     pub fn consume(self) -> BS {
         self.base
     }
+
+    /// Returns the stats recorded since this blockstore was created.
+    pub fn stats(&self) -> BufferedStats {
+        self.buffer.borrow().stats
+    }
+
+    /// Begins a transaction: the blocks buffered from now on are discarded if the transaction is
+    /// reverted. Transactions can be nested.
+    pub fn begin_transaction(&self) {
+        self.buffer.borrow_mut().begin_transaction()
+    }
+
+    /// Ends the innermost transaction, discarding the blocks it buffered if `revert` is set.
+    /// Otherwise, they become part of the enclosing transaction, if any.
+    pub fn end_transaction(&self, revert: bool) -> Result<()> {
+        self.buffer.borrow_mut().end_transaction(revert)
+    }
 }
 
 impl<BS> Buffered for BufferedBlockstore<BS>
@@ -50,17 +234,77 @@ where
     BS: Blockstore,
 {
     /// Flushes the buffered cache based on the root node.
-    /// This will recursively traverse the cache and write all data connected by links to this
-    /// root Cid.
+    ///
+    /// This writes all buffered blocks reachable from the root to the base store in a single bulk
+    /// put, then empties the write buffer. Links to blocks that aren't buffered aren't followed:
+    /// those blocks are either already in the base store, or aren't blocks at all (e.g., Filecoin
+    /// commitments).
+    ///
+    /// Fails if the root is neither buffered nor in the base store.
     fn flush(&self, root: &Cid) -> Result<()> {
-        let mut buffer = Vec::new();
-        let mut s = self.write.borrow_mut();
-        copy_rec(&self.base, &s, *root, &mut buffer)?;
+        self.buffer.borrow_mut().flush(&self.base, root)
+    }
+}
+
+/// A [`BufferedBlockstore`] that can be shared between threads (if the base store can).
+///
+/// All threads share a single write buffer, so flushing and reverting affect the blocks written
+/// by every thread: [`Buffered::flush`] drops all buffered blocks that aren't reachable from the
+/// given root, and reverting a transaction drops all blocks buffered since it began. Callers must
+/// not flush or revert while other threads are writing to the store, or their blocks may be lost.
+/// Instead, wait for the writers to finish, and flush a root linking to everything they wrote
+/// that should be kept. Spilling the buffer is safe, as it doesn't drop any blocks.
+#[derive(Debug)]
+pub struct SyncBufferedBlockstore<BS> {
+    base: BS,
+    buffer: Mutex<Buffer>,
+}
 
-        self.base.put_many_keyed(buffer)?;
-        *s = Default::default();
+impl<BS> SyncBufferedBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    /// Creates a buffered blockstore with an unbounded write buffer.
+    pub fn new(base: BS) -> Self {
+        Self::with_limit(base, usize::MAX)
+    }
 
-        Ok(())
+    /// Creates a buffered blockstore that spills its write buffer into the base store once it
+    /// holds more than `limit` bytes.
+    pub fn with_limit(base: BS, limit: usize) -> Self {
+        Self {
+            base,
+            buffer: Mutex::new(Buffer::new(limit)),
+        }
+    }
+
+    pub fn consume(self) -> BS {
+        self.base
+    }
+
+    /// Returns the stats recorded since this blockstore was created.
+    pub fn stats(&self) -> BufferedStats {
+        self.buffer.lock().unwrap().stats
+    }
+
+    /// See [`BufferedBlockstore::begin_transaction`].
+    pub fn begin_transaction(&self) {
+        self.buffer.lock().unwrap().begin_transaction()
+    }
+
+    /// See [`BufferedBlockstore::end_transaction`].
+    pub fn end_transaction(&self, revert: bool) -> Result<()> {
+        self.buffer.lock().unwrap().end_transaction(revert)
+    }
+}
+
+impl<BS> Buffered for SyncBufferedBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    /// See [`BufferedBlockstore`]'s implementation.
+    fn flush(&self, root: &Cid) -> Result<()> {
+        self.buffer.lock().unwrap().flush(&self.base, root)
     }
 }
 
@@ -121,7 +365,7 @@ fn cbor_read_header_buf<B: Read>(br: &mut B, scratch: &mut [u8]) -> anyhow::Resu
 /// Given a CBOR serialized IPLD buffer, read through all of it and return all the Links.
 /// This function is useful because it is quite a bit more fast than doing this recursively on a
 /// deserialized IPLD object.
-fn scan_for_links<B: Read + Seek, F>(buf: &mut B, mut callback: F) -> Result<()>
+pub(crate) fn scan_for_links<B: Read + Seek, F>(buf: &mut B, mut callback: F) -> Result<()>
 where
     F: FnMut(Cid) -> anyhow::Result<()>,
 {
@@ -145,6 +389,9 @@ where
                     if maj != 2 {
                         return Err(anyhow!("expected cbor type byte string in input"));
                     }
+                    if extra == 0 {
+                        return Err(anyhow!("cid in cbor input is empty"));
+                    }
                     if extra > 100 {
                         return Err(anyhow!("string in cbor input too long"));
                     }
@@ -172,65 +419,57 @@ where
     Ok(())
 }
 
-/// Copies the IPLD DAG under `root` from the cache to the base store.
-fn copy_rec<'a, BS>(
-    base: &BS,
-    cache: &'a HashMap<Cid, Vec<u8>>,
-    root: Cid,
-    buffer: &mut Vec<(Cid, &'a [u8])>,
-) -> Result<()>
+impl<BS> Blockstore for BufferedBlockstore<BS>
 where
     BS: Blockstore,
 {
-    // TODO: Make this non-recursive.
-    // Skip identity and Filecoin commitment Cids
-    if root.codec() != DAG_CBOR {
-        return Ok(());
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.buffer.borrow_mut().get(cid) {
+            return Ok(Some(data));
+        }
+        self.base.get(cid)
     }
 
-    let block = &*cache
-        .get(&root)
-        .ok_or_else(|| anyhow!("Invalid link ({}) in flushing buffered store", root))?;
-
-    scan_for_links(&mut Cursor::new(block), |link| {
-        if link.codec() != DAG_CBOR {
-            return Ok(());
-        }
+    fn put_keyed(&self, cid: &Cid, buf: &[u8]) -> Result<()> {
+        self.put_many_keyed(std::iter::once((*cid, buf)))
+    }
 
-        // DB reads are expensive. So we check if it exists in the cache.
-        // If it doesnt exist in the DB, which is likely, we proceed with using the cache.
-        if !cache.contains_key(&link) {
-            return Ok(());
+    fn has(&self, k: &Cid) -> Result<bool> {
+        if self.buffer.borrow().blocks.contains_key(k) {
+            Ok(true)
+        } else {
+            Ok(self.base.has(k)?)
         }
+    }
 
-        // Recursively find more links under the links we're iterating over.
-        copy_rec(base, cache, link, buffer)
-    })?;
-
-    buffer.push((root, block));
-
-    Ok(())
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        self.buffer.borrow_mut().put_many_keyed(&self.base, blocks)
+    }
 }
 
-impl<BS> Blockstore for BufferedBlockstore<BS>
+impl<BS> Blockstore for SyncBufferedBlockstore<BS>
 where
     BS: Blockstore,
 {
     fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
-        Ok(if let Some(data) = self.write.borrow().get(cid) {
-            Some(data.clone())
-        } else {
-            self.base.get(cid)?
-        })
+        // Don't hold the lock while reading from the base store.
+        if let Some(data) = self.buffer.lock().unwrap().get(cid) {
+            return Ok(Some(data));
+        }
+        self.base.get(cid)
     }
 
     fn put_keyed(&self, cid: &Cid, buf: &[u8]) -> Result<()> {
-        self.write.borrow_mut().insert(*cid, Vec::from(buf));
-        Ok(())
+        self.put_many_keyed(std::iter::once((*cid, buf)))
     }
 
     fn has(&self, k: &Cid) -> Result<bool> {
-        if self.write.borrow().contains_key(k) {
+        if self.buffer.lock().unwrap().blocks.contains_key(k) {
             Ok(true)
         } else {
             Ok(self.base.has(k)?)
@@ -243,10 +482,10 @@ where
         D: AsRef<[u8]>,
         I: IntoIterator<Item = (Cid, D)>,
     {
-        self.write
-            .borrow_mut()
-            .extend(blocks.into_iter().map(|(k, v)| (k, v.as_ref().into())));
-        Ok(())
+        self.buffer
+            .lock()
+            .unwrap()
+            .put_many_keyed(&self.base, blocks)
     }
 }
 
@@ -259,8 +498,6 @@ mod tests {
 
     use super::*;
 
-    const RAW: u64 = 0x55;
-
     #[test]
     fn basic_buffered_store() {
         let mem = MemoryBlockstore::default();
@@ -273,7 +510,18 @@ mod tests {
         buf_store.flush(&cid).unwrap();
         assert_eq!(buf_store.get_cbor::<u8>(&cid).unwrap(), Some(8));
         assert_eq!(mem.get_cbor::<u8>(&cid).unwrap(), Some(8));
-        assert!(buf_store.write.borrow().get(&cid).is_none());
+        assert!(buf_store.buffer.borrow().blocks.get(&cid).is_none());
+        assert_eq!(
+            buf_store.stats(),
+            BufferedStats {
+                puts: 1,
+                hits: 1,
+                misses: 1,
+                flushed: 1,
+                flushed_bytes: 1,
+                ..Default::default()
+            }
+        );
     }
 
     #[test]
@@ -285,7 +533,9 @@ mod tests {
         let arr_cid = buf_store
             .put_cbor(&(str_val.clone(), value), Code::Blake2b256)
             .unwrap();
-        let identity_cid = Cid::new_v1(RAW, Code::Identity.digest(&[0u8]));
+        let identity_cid = Cid::new_v1(IPLD_RAW, Code::Identity.digest(&[0u8]));
+        let raw_cid = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(b"raw"));
+        buf_store.put_keyed(&raw_cid, b"raw").unwrap();
 
         // Create map to insert into store
         let sealed_comm_cid = commcid::commitment_to_cid(
@@ -306,6 +556,7 @@ mod tests {
             sealed: Cid,
             unsealed: Cid,
             identity: Cid,
+            raw: Cid,
             value: String,
         }
         let obj = TestObject {
@@ -313,20 +564,23 @@ mod tests {
             sealed: sealed_comm_cid,
             unsealed: unsealed_comm_cid,
             identity: identity_cid,
+            raw: raw_cid,
             value: str_val.clone(),
         };
         let obj_cid = buf_store.put_cbor(&obj, Code::Blake2b256).unwrap();
 
+        // Link to the object twice; it should only be written once.
         let root_cid = buf_store
-            .put_cbor(&(obj_cid, 1u8), Code::Blake2b256)
+            .put_cbor(&(obj_cid, 1u8, obj_cid), Code::Blake2b256)
             .unwrap();
 
         // Make sure a block not connected to the root does not get written
         let unconnected = buf_store.put_cbor(&27u8, Code::Blake2b256).unwrap();
 
         assert_eq!(mem.get_cbor::<TestObject>(&obj_cid).unwrap(), None);
-        assert_eq!(mem.get_cbor::<(Cid, u8)>(&root_cid).unwrap(), None);
+        assert_eq!(mem.get_cbor::<(Cid, u8, Cid)>(&root_cid).unwrap(), None);
         assert_eq!(mem.get_cbor::<(String, u8)>(&arr_cid).unwrap(), None);
+        assert_eq!(mem.get(&raw_cid).unwrap(), None);
         assert_eq!(buf_store.get_cbor::<u8>(&unconnected).unwrap(), Some(27u8));
 
         // Flush and assert changes
@@ -337,13 +591,186 @@ mod tests {
         );
         assert_eq!(mem.get_cbor::<TestObject>(&obj_cid).unwrap(), Some(obj));
         assert_eq!(
-            mem.get_cbor::<(Cid, u8)>(&root_cid).unwrap(),
-            Some((obj_cid, 1)),
+            mem.get_cbor::<(Cid, u8, Cid)>(&root_cid).unwrap(),
+            Some((obj_cid, 1, obj_cid)),
         );
+        assert_eq!(mem.get(&raw_cid).unwrap().as_deref(), Some(&b"raw"[..]));
         assert_eq!(buf_store.get_cbor::<u8>(&identity_cid).unwrap(), None);
         assert_eq!(buf_store.get(&unsealed_comm_cid).unwrap(), None);
         assert_eq!(buf_store.get(&sealed_comm_cid).unwrap(), None);
         assert_eq!(mem.get_cbor::<u8>(&unconnected).unwrap(), None);
         assert_eq!(buf_store.get_cbor::<u8>(&unconnected).unwrap(), None);
+
+        let stats = buf_store.stats();
+        assert_eq!(stats.flushed, 4);
+        assert_eq!(stats.discarded, 1);
+    }
+
+    #[test]
+    fn buffered_store_flush_unknown_root() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = BufferedBlockstore::new(&mem);
+        let buffered = buf_store.put_cbor(&1u8, Code::Blake2b256).unwrap();
+
+        let unknown = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(b"unknown"));
+        assert!(buf_store.flush(&unknown).is_err());
+        // Nothing was flushed or dropped.
+        assert_eq!(buf_store.get_cbor::<u8>(&buffered).unwrap(), Some(1));
+        assert_eq!(mem.get(&buffered).unwrap(), None);
+
+        // A root that's already in the base store is fine.
+        let base = mem.put_cbor(&2u8, Code::Blake2b256).unwrap();
+        buf_store.flush(&base).unwrap();
+        assert_eq!(mem.get(&buffered).unwrap(), None);
+        assert_eq!(buf_store.stats().discarded, 1);
+    }
+
+    #[test]
+    fn buffered_store_flush_raw_root() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = BufferedBlockstore::new(&mem);
+
+        // Raw blocks aren't scanned for links, even if they happen to be valid CBOR.
+        let linked = buf_store.put_cbor(&1u8, Code::Blake2b256).unwrap();
+        let data = fvm_shared::encoding::to_vec(&linked).unwrap();
+        let raw = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(&data));
+        buf_store.put_keyed(&raw, &data).unwrap();
+
+        buf_store.flush(&raw).unwrap();
+        assert_eq!(mem.get(&raw).unwrap(), Some(data));
+        assert_eq!(mem.get(&linked).unwrap(), None);
+    }
+
+    #[test]
+    fn buffered_store_spills() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = BufferedBlockstore::with_limit(&mem, 64);
+
+        let small = buf_store.put_cbor(&1u8, Code::Blake2b256).unwrap();
+        assert_eq!(mem.get(&small).unwrap(), None);
+
+        // Going over the limit writes everything buffered so far, reachable or not.
+        let big = buf_store
+            .put_cbor(&vec![0u8; 64], Code::Blake2b256)
+            .unwrap();
+        assert_eq!(mem.get_cbor::<u8>(&small).unwrap(), Some(1));
+        assert_eq!(mem.get_cbor::<Vec<u8>>(&big).unwrap(), Some(vec![0u8; 64]));
+        assert!(buf_store.buffer.borrow().blocks.is_empty());
+        assert_eq!(buf_store.stats().spilled, 2);
+    }
+
+    #[test]
+    fn buffered_store_transactions() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = BufferedBlockstore::with_limit(&mem, 64);
+
+        let kept = buf_store.put_cbor(&1u8, Code::Blake2b256).unwrap();
+        buf_store.begin_transaction();
+        let outer = buf_store.put_cbor(&2u8, Code::Blake2b256).unwrap();
+
+        // Committed into the outer transaction.
+        buf_store.begin_transaction();
+        let inner = buf_store.put_cbor(&3u8, Code::Blake2b256).unwrap();
+        buf_store.end_transaction(false).unwrap();
+
+        // Blocks buffered before the transaction aren't discarded, even if they're written again.
+        // And nothing is spilled, even over the limit.
+        buf_store.begin_transaction();
+        buf_store.put_cbor(&1u8, Code::Blake2b256).unwrap();
+        let big = buf_store
+            .put_cbor(&vec![0u8; 64], Code::Blake2b256)
+            .unwrap();
+        assert_eq!(mem.get(&big).unwrap(), None);
+        assert!(buf_store.flush(&kept).is_err());
+        buf_store.end_transaction(true).unwrap();
+
+        buf_store.end_transaction(true).unwrap();
+        assert!(buf_store.end_transaction(true).is_err());
+
+        assert_eq!(buf_store.get_cbor::<u8>(&kept).unwrap(), Some(1));
+        for cid in [outer, inner, big] {
+            assert!(!buf_store.has(&cid).unwrap());
+        }
+        assert_eq!(buf_store.stats().spilled, 0);
+    }
+
+    #[test]
+    fn sync_buffered_store() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = SyncBufferedBlockstore::with_limit(&mem, 64);
+
+        // Write from several threads inside a transaction, then revert another one.
+        buf_store.begin_transaction();
+        let (kept, unreachable) = std::thread::scope(|s| {
+            let kept = s.spawn(|| buf_store.put_cbor(&(1u8, 2u8), Code::Blake2b256).unwrap());
+            let unreachable = s.spawn(|| buf_store.put_cbor(&3u8, Code::Blake2b256).unwrap());
+            (kept.join().unwrap(), unreachable.join().unwrap())
+        });
+        buf_store.end_transaction(false).unwrap();
+        buf_store.begin_transaction();
+        let discarded = buf_store.put_cbor(&4u8, Code::Blake2b256).unwrap();
+        buf_store.end_transaction(true).unwrap();
+        assert!(!buf_store.has(&discarded).unwrap());
+
+        let root = std::thread::scope(|s| {
+            s.spawn(|| buf_store.put_cbor(&(kept, 5u8), Code::Blake2b256).unwrap())
+                .join()
+                .unwrap()
+        });
+        assert_eq!(mem.get(&root).unwrap(), None);
+        buf_store.flush(&root).unwrap();
+        assert_eq!(mem.get_cbor::<(Cid, u8)>(&root).unwrap(), Some((kept, 5)));
+        assert_eq!(mem.get_cbor::<(u8, u8)>(&kept).unwrap(), Some((1, 2)));
+        assert_eq!(mem.get(&unreachable).unwrap(), None);
+
+        let stats = buf_store.stats();
+        assert_eq!((stats.puts, stats.flushed, stats.discarded), (4, 2, 2));
+    }
+
+    #[test]
+    fn sync_buffered_store_concurrent_writers() {
+        let mem = MemoryBlockstore::default();
+        // Small enough to spill while the writers are running.
+        let buf_store = SyncBufferedBlockstore::with_limit(&mem, 256);
+        let barrier = std::sync::Barrier::new(4);
+
+        // Each writer builds a chain of blocks, returning its head.
+        let heads: Vec<Cid> = std::thread::scope(|s| {
+            let writers: Vec<_> = (0..4u8)
+                .map(|i| {
+                    let (buf_store, barrier) = (&buf_store, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        let mut head = buf_store.put_cbor(&(i, 0u8), Code::Blake2b256).unwrap();
+                        for j in 1..50u8 {
+                            head = buf_store.put_cbor(&(head, i, j), Code::Blake2b256).unwrap();
+                            assert!(buf_store.has(&head).unwrap());
+                        }
+                        head
+                    })
+                })
+                .collect();
+            writers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        // Once the writers are done, flushing a root linking to their chains keeps all blocks.
+        let root = buf_store.put_cbor(&heads, Code::Blake2b256).unwrap();
+        buf_store.flush(&root).unwrap();
+        assert_eq!(
+            mem.get_cbor::<Vec<Cid>>(&root).unwrap(),
+            Some(heads.clone())
+        );
+        for (i, mut head) in (0..4u8).zip(heads) {
+            for j in (1..50u8).rev() {
+                let (prev, writer, n) = mem.get_cbor::<(Cid, u8, u8)>(&head).unwrap().unwrap();
+                assert_eq!((writer, n), (i, j));
+                head = prev;
+            }
+            assert_eq!(mem.get_cbor::<(u8, u8)>(&head).unwrap(), Some((i, 0)));
+        }
+
+        let stats = buf_store.stats();
+        assert_eq!((stats.puts, stats.discarded), (4 * 50 + 1, 0));
+        assert!(stats.spilled > 0);
     }
 }
@@ -1,4 +1,6 @@
-//! Private blockstores for use in the FVM.
+//! Blockstores used by the FVM: the write buffer machines keep their state tree in, and a version
+//! of it that can be shared between threads.
 
 mod buffered;
-pub use buffered::BufferedBlockstore;
+pub(crate) use buffered::scan_for_links;
+pub use buffered::{BufferedBlockstore, BufferedStats, SyncBufferedBlockstore};
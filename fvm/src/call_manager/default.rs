@@ -7,12 +7,16 @@ use fvm_shared::encoding::{RawBytes, DAG_CBOR};
 use fvm_shared::error::ExitCode;
 use fvm_shared::{ActorID, MethodNum, METHOD_SEND};
 use num_traits::Zero;
+use wasmtime::Val;
 
-use super::{Backtrace, CallManager, InvocationResult, NO_DATA_BLOCK_ID};
+use super::trace::{CallResult, ExecutionEvent, ExecutionTracer, GasTrace};
+use super::{
+    Backtrace, CallManager, ExecutionLimiter, FinishRet, InvocationResult, NO_DATA_BLOCK_ID,
+};
 use crate::call_manager::backtrace::Frame;
-use crate::gas::GasTracker;
+use crate::gas::{GasCharge, GasProfiler, GasTracker};
 use crate::kernel::{ClassifyResult, ExecutionError, Kernel, Result};
-use crate::machine::Machine;
+use crate::machine::{Machine, GAS_COUNTER};
 use crate::syscalls::error::Abort;
 use crate::{account_actor, syscall_error};
 
@@ -51,6 +55,12 @@ pub struct InnerDefaultCallManager<M> {
     call_stack_depth: u32,
     /// The current chain of errors, if any.
     backtrace: Backtrace,
+    /// The execution trace, if tracing is enabled.
+    exec_trace: Option<ExecutionTracer>,
+    /// The gas profile, if gas profiling is enabled.
+    gas_profile: Option<GasProfiler>,
+    /// Limits the resources used by the invocations on the call stack.
+    limiter: ExecutionLimiter,
 }
 
 #[doc(hidden)]
@@ -76,6 +86,9 @@ where
     type Machine = M;
 
     fn new(machine: M, gas_limit: i64, origin: Address, nonce: u64) -> Self {
+        let exec_trace = machine.config().tracing.then(ExecutionTracer::default);
+        let gas_profile = machine.config().gas_profiling.then(GasProfiler::default);
+        let limiter = ExecutionLimiter::new(machine.config());
         DefaultCallManager(Some(InnerDefaultCallManager {
             machine,
             gas_tracker: GasTracker::new(gas_limit, 0),
@@ -84,6 +97,9 @@ where
             num_actors_created: 0,
             call_stack_depth: 0,
             backtrace: Backtrace::default(),
+            exec_trace,
+            gas_profile,
+            limiter,
         }))
     }
 
@@ -122,12 +138,20 @@ where
         res
     }
 
-    fn finish(mut self) -> (i64, Backtrace, Self::Machine) {
+    fn finish(mut self) -> (FinishRet, Self::Machine) {
         let gas_used = self.gas_tracker.gas_used().max(0);
 
         let inner = self.0.take().expect("call manager is poisoned");
         // TODO: Having to check against zero here is fishy, but this is what lotus does.
-        (gas_used, inner.backtrace, inner.machine)
+        (
+            FinishRet {
+                gas_used,
+                backtrace: inner.backtrace,
+                exec_trace: inner.exec_trace.map(ExecutionTracer::finish),
+                gas_profile: inner.gas_profile.map(GasProfiler::finish),
+            },
+            inner.machine,
+        )
     }
 
     // Accessor methods so the trait can implement some common methods by default.
@@ -148,6 +172,10 @@ where
         &mut self.gas_tracker
     }
 
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter {
+        &mut self.limiter
+    }
+
     // Other accessor methods
 
     fn origin(&self) -> Address {
@@ -165,6 +193,25 @@ where
         self.num_actors_created += 1;
         ret
     }
+
+    // Tracing
+
+    fn charge_gas(&mut self, charge: GasCharge) -> Result<()> {
+        if let Some(trace) = &mut self.exec_trace {
+            trace.record(ExecutionEvent::GasCharge(GasTrace::from(&charge)));
+        }
+        if let Some(profile) = &mut self.gas_profile {
+            profile.record(&charge);
+        }
+        self.gas_tracker.charge_gas(charge)?;
+        Ok(())
+    }
+
+    fn trace(&mut self, event: ExecutionEvent) {
+        if let Some(trace) = &mut self.exec_trace {
+            trace.record(event);
+        }
+    }
 }
 
 impl<M> DefaultCallManager<M>
@@ -240,7 +287,8 @@ where
         self.send_resolved::<K>(from, to, method, params, value)
     }
 
-    /// Send with resolved addresses.
+    /// Send with resolved addresses, recording the call in the execution trace and gas profile (if
+    /// enabled).
     fn send_resolved<K>(
         &mut self,
         from: ActorID,
@@ -249,6 +297,37 @@ where
         params: &RawBytes,
         value: &TokenAmount,
     ) -> Result<InvocationResult>
+    where
+        K: Kernel<CallManager = Self>,
+    {
+        if let Some(trace) = &mut self.exec_trace {
+            trace.enter_call(from, to, method, params, value);
+        }
+        if let Some(profile) = &mut self.gas_profile {
+            profile.enter_call(to, method);
+        }
+
+        let result = self.send_resolved_untraced::<K>(from, to, method, params, value);
+
+        if let Some(trace) = &mut self.exec_trace {
+            trace.exit_call(CallResult::from(&result));
+        }
+        if let Some(profile) = &mut self.gas_profile {
+            profile.exit_call();
+        }
+
+        result
+    }
+
+    /// Send with resolved addresses.
+    fn send_resolved_untraced<K>(
+        &mut self,
+        from: ActorID,
+        to: ActorID,
+        method: MethodNum,
+        params: &RawBytes,
+        value: &TokenAmount,
+    ) -> Result<InvocationResult>
     where
         K: Kernel<CallManager = Self>,
     {
@@ -261,6 +340,34 @@ where
         // Charge the method gas. Not sure why this comes second, but it does.
         self.charge_gas(self.price_list().on_method_invocation(value, method))?;
 
+        // This is a cheap operation as it doesn't actually clone the struct,
+        // it returns a referenced copy.
+        let engine = self.engine().clone();
+
+        // Meter the execution of user-deployed actors. Built-in actors aren't metered, and don't
+        // pay for the resources they use.
+        let metered = !self.machine.builtin_actors().contains_left(&state.code);
+
+        // Load the code before transferring any funds. Built-in actors are loaded up-front, but
+        // user-deployed actors are only instrumented and compiled the first time they're invoked
+        // (compilation was paid for when the actor was created). This is a no-op if the code has
+        // already been loaded.
+        let module = if method == METHOD_SEND {
+            state.code
+        } else if metered {
+            let costs = &self.price_list().wasm_instruction_costs;
+            engine
+                .preload_metered(self.blockstore(), &state.code, costs)
+                .context("failed to load actor code")
+                .or_fatal()?
+        } else {
+            engine
+                .preload(self.blockstore(), std::iter::once(&state.code))
+                .context("failed to load actor code")
+                .or_fatal()?;
+            state.code
+        };
+
         // Transfer, if necessary.
         if !value.is_zero() {
             self.machine.transfer(from, to, value)?;
@@ -272,14 +379,19 @@ where
             return Ok(InvocationResult::Return(Default::default()));
         }
 
-        // This is a cheap operation as it doesn't actually clone the struct,
-        // it returns a referenced copy.
-        let engine = self.engine().clone();
+        // Metered actors may execute instructions for all the gas left. Their syscalls and callees
+        // are charged separately, so this is only an upper bound: the gas tracker catches up at
+        // every syscall, and when the actor returns.
+        let gas_remaining = (self.gas_tracker.gas_available() - self.gas_tracker.gas_used()).max(0);
+
+        // Account for the new instance. Its memory and tables are accounted for as they're
+        // allocated.
+        self.limiter.enter()?;
 
         log::trace!("calling {} -> {}::{}", from, to, method);
-        self.map_mut(|cm| {
+        let ret = self.map_mut(|cm| {
             // Make the kernel.
-            let mut kernel = K::new(cm, from, to, method, value.clone());
+            let mut kernel = K::new(cm, from, to, method, value.clone(), metered);
 
             // Store parameters, if any.
             let param_id = if params.len() > 0 {
@@ -293,17 +405,56 @@ where
             };
 
             // Make a store.
-            let mut store = engine.new_store(kernel);
-
-            // Instantiate the module.
-            let instance = match engine
-                .get_instance(&mut store, &state.code)
-                .and_then(|i| i.context("actor code not found"))
-                .or_fatal()
-            {
-                Ok(ret) => ret,
-                Err(err) => return (Err(err), store.into_data().kernel.take()),
+            let mut store = match engine.new_store(kernel) {
+                Ok(store) => store,
+                Err((err, kernel)) => return (Err(ExecutionError::Fatal(err)), kernel.take()),
             };
+            store.data_mut().metered = metered;
+
+            // Instantiate the module. This fails cleanly if the instance's initial memory or
+            // tables exceed the limits.
+            let instance = match engine.get_instance(&mut store, &module) {
+                Ok(Some(instance)) => instance,
+                Ok(None) => {
+                    let err = ExecutionError::Fatal(anyhow::anyhow!("actor code not found"));
+                    return (Err(err), store.into_data().kernel.take());
+                }
+                Err(err) => {
+                    let mut data = store.into_data();
+                    let err = match data.kernel.limiter_mut().take_denied() {
+                        _ if data.out_of_gas => ExecutionError::OutOfGas,
+                        Some(reason) => syscall_error!(LimitExceeded;
+                            "failed to instantiate actor: {}", reason)
+                        .into(),
+                        None => ExecutionError::Fatal(err),
+                    };
+                    return (Err(err), data.kernel.take());
+                }
+            };
+
+            // Hand the gas left to the metered actor's gas counter.
+            if metered {
+                let counter = instance
+                    .get_global(&mut store, GAS_COUNTER)
+                    .ok_or_else(|| anyhow::anyhow!("actor code has no gas counter"))
+                    .and_then(|counter| {
+                        counter.set(&mut store, Val::I64(gas_remaining))?;
+                        Ok(counter)
+                    });
+                match counter {
+                    Ok(counter) => {
+                        let data = store.data_mut();
+                        data.gas_counter = Some(counter);
+                        data.gas_counter_charged = gas_remaining;
+                    }
+                    Err(err) => {
+                        return (
+                            Err(ExecutionError::Fatal(err)),
+                            store.into_data().kernel.take(),
+                        )
+                    }
+                }
+            }
 
             // From this point on, there are no more syscall errors, only aborts.
             let result: std::result::Result<RawBytes, Abort> = (|| {
@@ -314,12 +465,23 @@ where
                     .map_err(Abort::Fatal)?;
 
                 // Invoke it.
-                let return_block_id = invoke.call(&mut store, (param_id,))?;
+                let res = invoke.call(&mut store, (param_id,));
+
+                // Charge for the instructions executed since the last syscall. If the actor ran
+                // out of gas, this will fail with an out of gas error.
+                let gas_counter = store.data().gas_counter;
+                let gas_counter = gas_counter.and_then(|counter| counter.get(&mut store).i64());
+                store
+                    .data_mut()
+                    .charge_for_execution(gas_counter)
+                    .map_err(|e| Abort::from_error(ExitCode::SysErrIllegalActor, e))?;
+
+                let return_block_id = res?;
 
                 // Extract the return value, if there is one.
                 let return_value: RawBytes = if return_block_id > NO_DATA_BLOCK_ID {
                     let (code, ret) = store
-                        .data()
+                        .data_mut()
                         .kernel
                         .block_get(return_block_id)
                         .map_err(|e| Abort::from_error(ExitCode::SysErrIllegalActor, e))?;
@@ -329,6 +491,13 @@ where
                     RawBytes::default()
                 };
 
+                // Write out the new blocks reachable from the actor's state and return value.
+                store
+                    .data_mut()
+                    .kernel
+                    .block_flush(return_block_id)
+                    .map_err(|e| Abort::from_error(ExitCode::SysErrIllegalActor, e))?;
+
                 Ok(return_value)
             })();
 
@@ -389,7 +558,10 @@ where
             }
 
             (ret, cm)
-        })
+        });
+
+        self.limiter.exit();
+        ret
     }
 
     fn map_mut<F, T>(&mut self, f: F) -> T
@@ -399,3 +571,91 @@ where
         replace_with::replace_with_and_return(self, || DefaultCallManager(None), f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_tree::ActorState;
+    use crate::test_utils::*;
+    use crate::EMPTY_ARR_CID;
+
+    const ACTOR_ID: ActorID = 1000;
+
+    /// An actor that loops forever.
+    const LOOP_ACTOR: &str = r#"
+        (module
+          (memory (export "memory") 1 1)
+          (func (export "invoke") (param i32) (result i32)
+            (loop (br 0))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn code_is_loaded_before_transfer() {
+        let mut machine = new_machine(test_config(), &[]);
+        let missing = ActorState::new(code_cid(b"missing"), *EMPTY_ARR_CID, Zero::zero(), 0);
+        machine
+            .state_tree_mut()
+            .set_actor_id(ACTOR_ID, missing)
+            .unwrap();
+
+        let mut cm = new_call_manager(machine);
+        let res = cm.send::<TestKernel>(
+            SENDER_ID,
+            Address::new_id(ACTOR_ID),
+            2,
+            &RawBytes::default(),
+            &TokenAmount::from(1),
+        );
+        assert!(matches!(res, Err(ExecutionError::Fatal(_))));
+
+        let (_, machine) = cm.finish();
+        let actor = machine
+            .state_tree()
+            .get_actor_id(ACTOR_ID)
+            .unwrap()
+            .unwrap();
+        assert!(actor.balance.is_zero());
+    }
+
+    #[test]
+    fn infinite_loop_runs_out_of_gas() {
+        let wasm = wasm(LOOP_ACTOR);
+        let machine = new_machine(test_config(), &[(ACTOR_ID, &wasm)]);
+        let gas_limit = 10_000_000;
+        let mut cm = TestCallManager::new(machine, gas_limit, Address::new_id(SENDER_ID), 0);
+        let res = cm.send::<TestKernel>(
+            SENDER_ID,
+            Address::new_id(ACTOR_ID),
+            2,
+            &RawBytes::default(),
+            &TokenAmount::zero(),
+        );
+        assert!(matches!(res, Err(ExecutionError::OutOfGas)));
+
+        let (ret, _) = cm.finish();
+        assert_eq!(ret.gas_used, gas_limit);
+    }
+
+    #[test]
+    fn memory_is_charged_to_user_actors() {
+        // The gas used by invoking an actor with the given number of memory pages.
+        let gas_used = |pages| {
+            let wasm = wasm(&NOOP_ACTOR.replace("1 1", &format!("{0} {0}", pages)));
+            let mut cm = new_call_manager(new_machine(test_config(), &[(ACTOR_ID, &wasm)]));
+            cm.send::<TestKernel>(
+                SENDER_ID,
+                Address::new_id(ACTOR_ID),
+                2,
+                &RawBytes::default(),
+                &TokenAmount::zero(),
+            )
+            .unwrap();
+            cm.finish().0.gas_used
+        };
+
+        let cm = new_call_manager(new_machine(test_config(), &[]));
+        let page_cost = cm.price_list().wasm_memory_page_cost;
+        assert_eq!(gas_used(3) - gas_used(1), 2 * page_cost);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::kernel::Result;
+use crate::{syscall_error, Config};
+
+/// The size of a wasm page.
+pub const WASM_PAGE_SIZE: usize = 65536;
+
+/// Limits the resources (linear memory, table elements, and instances) used by actor invocations,
+/// both per invocation and across the whole call stack.
+pub struct ExecutionLimiter {
+    /// The maximum linear memory of a single invocation, in bytes.
+    max_memory_bytes: usize,
+    /// The maximum number of table elements of a single invocation.
+    max_table_elements: usize,
+    /// The maximum linear memory across the call stack, in bytes.
+    max_stack_memory_bytes: usize,
+    /// The maximum number of table elements across the call stack.
+    max_stack_table_elements: usize,
+    /// The maximum number of instances across the call stack.
+    max_instances: usize,
+
+    /// The resources used by each invocation on the call stack, innermost last.
+    frames: Vec<Usage>,
+    /// The resources used across the call stack.
+    total: Usage,
+    /// Why the last request was denied, if it was.
+    denied: Option<String>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Usage {
+    memory_bytes: usize,
+    table_elements: usize,
+}
+
+impl ExecutionLimiter {
+    pub fn new(config: &Config) -> Self {
+        ExecutionLimiter {
+            max_memory_bytes: config.max_pages.saturating_mul(WASM_PAGE_SIZE),
+            max_table_elements: config.max_table_elements as usize,
+            max_stack_memory_bytes: config.max_stack_pages.saturating_mul(WASM_PAGE_SIZE),
+            max_stack_table_elements: config.max_stack_table_elements,
+            max_instances: config.max_instances,
+            frames: Vec::new(),
+            total: Usage::default(),
+            denied: None,
+        }
+    }
+
+    /// Enter a new invocation, failing if the call stack already has the maximum number of
+    /// instances. Must be paired with a call to `exit`, if successful.
+    pub fn enter(&mut self) -> Result<()> {
+        if self.frames.len() >= self.max_instances {
+            return Err(syscall_error!(LimitExceeded;
+                "call stack exceeds {} instances", self.max_instances)
+            .into());
+        }
+        self.frames.push(Usage::default());
+        self.denied = None;
+        Ok(())
+    }
+
+    /// Exit the current invocation, releasing its resources.
+    pub fn exit(&mut self) {
+        let frame = self.frames.pop().expect("unbalanced call to exit");
+        self.total.memory_bytes -= frame.memory_bytes;
+        self.total.table_elements -= frame.table_elements;
+    }
+
+    /// Returns (and clears) the reason the last memory or table growth request was denied.
+    pub fn take_denied(&mut self) -> Option<String> {
+        self.denied.take()
+    }
+
+    /// Requests growing the current invocation's linear memory from `current` to `desired` bytes.
+    /// Returns false if the request is denied.
+    pub fn grow_memory(&mut self, current: usize, desired: usize) -> bool {
+        let delta = desired.saturating_sub(current);
+        if desired > self.max_memory_bytes {
+            return self.deny(format!(
+                "memory of {} bytes exceeds the limit of {} bytes",
+                desired, self.max_memory_bytes
+            ));
+        }
+        if self.total.memory_bytes + delta > self.max_stack_memory_bytes {
+            return self.deny(format!(
+                "call stack memory exceeds the limit of {} bytes",
+                self.max_stack_memory_bytes
+            ));
+        }
+        self.total.memory_bytes += delta;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.memory_bytes += delta;
+        }
+        true
+    }
+
+    /// Requests growing the current invocation's table from `current` to `desired` elements.
+    /// Returns false if the request is denied.
+    pub fn grow_table(&mut self, current: u32, desired: u32) -> bool {
+        let (current, desired) = (current as usize, desired as usize);
+        let delta = desired.saturating_sub(current);
+        if desired > self.max_table_elements {
+            return self.deny(format!(
+                "table of {} elements exceeds the limit of {} elements",
+                desired, self.max_table_elements
+            ));
+        }
+        if self.total.table_elements + delta > self.max_stack_table_elements {
+            return self.deny(format!(
+                "call stack tables exceed the limit of {} elements",
+                self.max_stack_table_elements
+            ));
+        }
+        self.total.table_elements += delta;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.table_elements += delta;
+        }
+        true
+    }
+
+    fn deny(&mut self, reason: String) -> bool {
+        log::trace!("resource request denied: {}", reason);
+        self.denied = Some(reason);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_memory_across_the_stack() {
+        let mut limiter = ExecutionLimiter::new(&Config {
+            max_pages: 2,
+            max_stack_pages: 3,
+            ..Default::default()
+        });
+
+        limiter.enter().unwrap();
+        assert!(!limiter.grow_memory(0, 3 * WASM_PAGE_SIZE));
+        assert!(limiter.take_denied().is_some());
+        assert!(limiter.grow_memory(0, 2 * WASM_PAGE_SIZE));
+
+        limiter.enter().unwrap();
+        assert!(limiter.grow_memory(0, WASM_PAGE_SIZE));
+        assert!(!limiter.grow_memory(WASM_PAGE_SIZE, 2 * WASM_PAGE_SIZE));
+        limiter.exit();
+
+        // The inner invocation's memory has been released.
+        limiter.enter().unwrap();
+        assert!(limiter.grow_memory(0, WASM_PAGE_SIZE));
+        limiter.exit();
+        limiter.exit();
+        assert_eq!(limiter.total.memory_bytes, 0);
+    }
+
+    #[test]
+    fn limits_instances() {
+        let mut limiter = ExecutionLimiter::new(&Config {
+            max_instances: 1,
+            ..Default::default()
+        });
+        limiter.enter().unwrap();
+        assert!(limiter.enter().is_err());
+        limiter.exit();
+        limiter.enter().unwrap();
+    }
+}
@@ -4,7 +4,7 @@ use fvm_shared::encoding::RawBytes;
 use fvm_shared::error::ExitCode;
 use fvm_shared::{ActorID, MethodNum};
 
-use crate::gas::{GasCharge, GasTracker, PriceList};
+use crate::gas::{GasCharge, GasProfile, GasTracker, PriceList};
 use crate::kernel::Result;
 use crate::machine::{Machine, MachineContext};
 use crate::state_tree::StateTree;
@@ -14,6 +14,10 @@ pub mod backtrace;
 pub use backtrace::Backtrace;
 mod default;
 pub use default::DefaultCallManager;
+mod limiter;
+pub use limiter::{ExecutionLimiter, WASM_PAGE_SIZE};
+pub mod trace;
+pub use trace::{ExecutionEvent, ExecutionTrace};
 
 /// BlockID representing nil parameters or return data.
 pub const NO_DATA_BLOCK_ID: u32 = 0;
@@ -39,8 +43,9 @@ pub trait CallManager: 'static {
         f: impl FnOnce(&mut Self) -> Result<InvocationResult>,
     ) -> Result<InvocationResult>;
 
-    /// Finishes execution, returning the gas used and the machine.
-    fn finish(self) -> (i64, backtrace::Backtrace, Self::Machine);
+    /// Finishes execution, returning the gas used, backtrace, execution trace and gas profile (if
+    /// enabled) along with the machine.
+    fn finish(self) -> (FinishRet, Self::Machine);
 
     /// Returns a reference to the machine.
     fn machine(&self) -> &Self::Machine;
@@ -52,6 +57,9 @@ pub trait CallManager: 'static {
     /// Returns a mutable reference to the gas tracker.
     fn gas_tracker_mut(&mut self) -> &mut GasTracker;
 
+    /// Returns a mutable reference to the resource limiter.
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter;
+
     /// Getter for origin actor.
     fn origin(&self) -> Address;
 
@@ -63,7 +71,7 @@ pub trait CallManager: 'static {
 
     /// Returns the current price list.
     fn price_list(&self) -> &PriceList {
-        &self.machine().context().price_list
+        &self.machine().context().network.price_list
     }
 
     /// Returns the machine context.
@@ -96,6 +104,21 @@ pub trait CallManager: 'static {
         self.gas_tracker_mut().charge_gas(charge)?;
         Ok(())
     }
+
+    /// Record an event in the execution trace. This is a no-op unless tracing is enabled.
+    fn trace(&mut self, event: ExecutionEvent);
+}
+
+/// The result of finishing a call stack.
+pub struct FinishRet {
+    /// The gas used by the call stack.
+    pub gas_used: i64,
+    /// The backtrace of the last failure, if any.
+    pub backtrace: Backtrace,
+    /// The execution trace, if tracing was enabled.
+    pub exec_trace: Option<ExecutionTrace>,
+    /// The gas profile, if gas profiling was enabled.
+    pub gas_profile: Option<GasProfile>,
 }
 
 /// The result of a method invocation.
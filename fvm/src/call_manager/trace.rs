@@ -0,0 +1,210 @@
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::encoding::RawBytes;
+use fvm_shared::error::{ErrorNumber, ExitCode};
+use fvm_shared::{ActorID, MethodNum};
+
+use super::InvocationResult;
+use crate::gas::GasCharge;
+use crate::kernel::{ExecutionError, Result, SyscallError};
+
+/// An execution trace records everything that happened while applying a single message: every
+/// call, syscall, and gas charge, nested by call depth.
+///
+/// Execution traces are only recorded when [`Config::tracing`](crate::Config::tracing) is
+/// enabled.
+#[derive(Debug, Default, Clone)]
+pub struct ExecutionTrace {
+    /// Events recorded outside of any actor invocation (e.g., message inclusion gas), along with
+    /// the top-level call.
+    pub events: Vec<ExecutionEvent>,
+}
+
+/// A single event in an execution trace.
+#[derive(Debug, Clone)]
+pub enum ExecutionEvent {
+    /// Gas was charged.
+    GasCharge(GasTrace),
+    /// An actor made a syscall. Syscalls are recorded when they return so any events caused by the
+    /// syscall (gas charges, sends, etc.) precede the syscall itself.
+    Syscall(SyscallTrace),
+    /// A send to another actor, including the events recorded during the invocation.
+    Call(CallTrace),
+}
+
+/// A gas charge.
+#[derive(Debug, Clone)]
+pub struct GasTrace {
+    /// The name of the charge.
+    pub name: String,
+    /// The compute gas charged.
+    pub compute_gas: i64,
+    /// The storage gas charged.
+    pub storage_gas: i64,
+}
+
+impl From<&GasCharge<'_>> for GasTrace {
+    fn from(charge: &GasCharge<'_>) -> Self {
+        GasTrace {
+            name: charge.name.to_owned(),
+            compute_gas: charge.compute_gas,
+            storage_gas: charge.storage_gas,
+        }
+    }
+}
+
+/// A syscall made by an actor.
+#[derive(Debug, Clone)]
+pub struct SyscallTrace {
+    /// The syscall "module".
+    pub module: &'static str,
+    /// The syscall function name.
+    pub function: &'static str,
+    /// The error returned to the actor, if any.
+    pub error: Option<ErrorNumber>,
+}
+
+/// A send (and, unless it was a bare value transfer, an actor invocation).
+#[derive(Debug, Clone)]
+pub struct CallTrace {
+    /// The sending actor.
+    pub from: ActorID,
+    /// The receiving actor.
+    pub to: ActorID,
+    /// The method that was invoked.
+    pub method: MethodNum,
+    /// The parameters passed to the method.
+    pub params: RawBytes,
+    /// The value transferred.
+    pub value: TokenAmount,
+    /// The call depth, starting at 0 for the top-level call.
+    pub depth: u32,
+    /// Events recorded during this call, in order.
+    pub events: Vec<ExecutionEvent>,
+    /// How the call ended.
+    pub result: CallResult,
+}
+
+/// The outcome of a call.
+#[derive(Debug, Clone)]
+pub enum CallResult {
+    /// The actor returned successfully.
+    Return(RawBytes),
+    /// The actor aborted with an exit code.
+    Abort(ExitCode),
+    /// The call failed before or while invoking the actor.
+    Error(SyscallError),
+    /// The call ran out of gas.
+    OutOfGas,
+    /// The call failed with a fatal error.
+    Fatal(String),
+}
+
+impl From<&Result<InvocationResult>> for CallResult {
+    fn from(res: &Result<InvocationResult>) -> Self {
+        match res {
+            Ok(InvocationResult::Return(data)) => CallResult::Return(data.clone()),
+            Ok(InvocationResult::Failure(code)) => CallResult::Abort(*code),
+            Err(ExecutionError::Syscall(e)) => CallResult::Error(e.clone()),
+            Err(ExecutionError::OutOfGas) => CallResult::OutOfGas,
+            Err(ExecutionError::Fatal(e)) => CallResult::Fatal(format!("{:#}", e)),
+        }
+    }
+}
+
+/// Builds an [`ExecutionTrace`] as calls are entered and exited.
+#[derive(Default)]
+pub(crate) struct ExecutionTracer {
+    trace: ExecutionTrace,
+    /// Calls that have been entered but not yet exited, innermost last.
+    stack: Vec<CallTrace>,
+}
+
+impl ExecutionTracer {
+    /// Record an event in the current call (or at the top level if there is no current call).
+    pub fn record(&mut self, event: ExecutionEvent) {
+        match self.stack.last_mut() {
+            Some(call) => call.events.push(event),
+            None => self.trace.events.push(event),
+        }
+    }
+
+    /// Enter a new call. All events up to the matching `exit_call` will be nested within it.
+    pub fn enter_call(
+        &mut self,
+        from: ActorID,
+        to: ActorID,
+        method: MethodNum,
+        params: &RawBytes,
+        value: &TokenAmount,
+    ) {
+        let depth = self.stack.len() as u32;
+        self.stack.push(CallTrace {
+            from,
+            to,
+            method,
+            params: params.clone(),
+            value: value.clone(),
+            depth,
+            events: Vec::new(),
+            result: CallResult::Return(RawBytes::default()),
+        })
+    }
+
+    /// Exit the current call, recording its result.
+    pub fn exit_call(&mut self, result: CallResult) {
+        if let Some(mut call) = self.stack.pop() {
+            call.result = result;
+            self.record(ExecutionEvent::Call(call));
+        }
+    }
+
+    /// Finish tracing, returning the trace.
+    pub fn finish(self) -> ExecutionTrace {
+        debug_assert!(self.stack.is_empty(), "unbalanced calls in execution trace");
+        self.trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nests_events_by_call() {
+        let mut tracer = ExecutionTracer::default();
+        let gas = |name: &str| {
+            ExecutionEvent::GasCharge(GasTrace {
+                name: String::from(name),
+                compute_gas: 1,
+                storage_gas: 0,
+            })
+        };
+
+        tracer.record(gas("top"));
+        tracer.enter_call(100, 101, 2, &RawBytes::default(), &TokenAmount::from(0));
+        tracer.record(gas("outer"));
+        tracer.enter_call(101, 102, 3, &RawBytes::default(), &TokenAmount::from(0));
+        tracer.record(gas("inner"));
+        tracer.exit_call(CallResult::Abort(ExitCode::ErrForbidden));
+        tracer.exit_call(CallResult::Return(RawBytes::default()));
+
+        let trace = tracer.finish();
+        assert_eq!(trace.events.len(), 2);
+        let outer = match &trace.events[1] {
+            ExecutionEvent::Call(call) => call,
+            _ => panic!("expected a call"),
+        };
+        assert_eq!((outer.to, outer.depth), (101, 0));
+        assert_eq!(outer.events.len(), 2);
+        let inner = match &outer.events[1] {
+            ExecutionEvent::Call(call) => call,
+            _ => panic!("expected a call"),
+        };
+        assert_eq!((inner.to, inner.depth), (102, 1));
+        assert!(matches!(
+            inner.result,
+            CallResult::Abort(ExitCode::ErrForbidden)
+        ));
+        assert!(matches!(&inner.events[0], ExecutionEvent::GasCharge(g) if g.name == "inner"));
+    }
+}
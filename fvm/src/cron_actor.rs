@@ -0,0 +1,9 @@
+//! This module contains the minimal logic for the FVM to run the cron actor at the end of each
+//! tipset.
+
+use fvm_shared::{ActorID, MethodNum};
+
+pub const CRON_ACTOR_ID: ActorID = 3;
+
+/// The cron actor method invoked (implicitly) at the end of every tipset.
+pub const EPOCH_TICK_METHOD: MethodNum = 2;
@@ -14,8 +14,8 @@ use fvm_shared::ActorID;
 use num_traits::Zero;
 
 use super::{ApplyFailure, ApplyKind, ApplyRet, Executor};
-use crate::call_manager::{backtrace, CallManager, InvocationResult};
-use crate::gas::{GasCharge, GasOutputs};
+use crate::call_manager::{backtrace, CallManager, ExecutionTrace, FinishRet, InvocationResult};
+use crate::gas::{GasCharge, GasOutputs, GasProfile};
 use crate::kernel::{ClassifyResult, Context as _, ExecutionError, Kernel};
 use crate::machine::{Machine, BURNT_FUNDS_ACTOR_ADDR, REWARD_ACTOR_ADDR};
 
@@ -55,21 +55,76 @@ where
         msg: Message,
         apply_kind: ApplyKind,
         raw_length: usize,
+    ) -> anyhow::Result<ApplyRet> {
+        self.apply_message(msg, apply_kind, raw_length, false)
+    }
+
+    fn execute_message_read_only(
+        &mut self,
+        msg: Message,
+        apply_kind: ApplyKind,
+        raw_length: usize,
+    ) -> anyhow::Result<ApplyRet> {
+        // Everything (including gas payments and the blocks written by actors) happens inside a
+        // transaction that we always revert.
+        self.begin_transaction();
+        let res = self.apply_message(msg, apply_kind, raw_length, true);
+        self.end_transaction(true)?;
+        res
+    }
+}
+
+impl<K> DefaultExecutor<K>
+where
+    K: Kernel,
+{
+    pub fn new(m: <K::CallManager as CallManager>::Machine) -> Self {
+        Self(Some(m))
+    }
+
+    /// Flush the state-tree to the underlying blockstore.
+    pub fn flush(&mut self) -> anyhow::Result<Cid> {
+        let k = (&mut **self).flush()?;
+        Ok(k)
+    }
+
+    /// Consume consumes the executor and returns the Machine. If the Machine had
+    /// been poisoned during execution, the Option will be None.
+    pub fn consume(self) -> Option<<K::CallManager as CallManager>::Machine> {
+        self.0
+    }
+
+    /// Applies a message. If `read_only` is set, the sender's nonce and balance are neither
+    /// checked nor updated (the caller is expected to revert all other state changes).
+    fn apply_message(
+        &mut self,
+        msg: Message,
+        apply_kind: ApplyKind,
+        raw_length: usize,
+        read_only: bool,
     ) -> anyhow::Result<ApplyRet> {
         // Validate if the message was correct, charge for it, and extract some preliminary data.
         let (sender_id, gas_cost, inclusion_cost) =
-            match self.preflight_message(&msg, apply_kind, raw_length)? {
+            match self.preflight_message(&msg, apply_kind, raw_length, read_only)? {
                 Ok(res) => res,
                 Err(apply_ret) => return Ok(apply_ret),
             };
 
         // Apply the message.
-        let (res, gas_used, mut backtrace) = self.map_machine(|machine| {
+        let (
+            res,
+            FinishRet {
+                gas_used,
+                mut backtrace,
+                exec_trace,
+                gas_profile,
+            },
+        ) = self.map_machine(|machine| {
             let mut cm = K::CallManager::new(machine, msg.gas_limit, msg.from, msg.sequence);
             // This error is fatal because it should have already been acounted for inside
             // preflight_message.
             if let Err(e) = cm.charge_gas(inclusion_cost) {
-                return (Err(e), cm.finish().2);
+                return (Err(e), cm.finish().1);
             }
 
             let result = cm.with_transaction(|cm| {
@@ -79,13 +134,13 @@ where
 
                 // Charge for including the result (before we end the transaction).
                 if let InvocationResult::Return(data) = &ret {
-                    cm.charge_gas(cm.context().price_list.on_chain_return_value(data.len()))?;
+                    cm.charge_gas(cm.price_list().on_chain_return_value(data.len()))?;
                 }
 
                 Ok(ret)
             });
-            let (gas_used, backtrace, machine) = cm.finish();
-            (Ok((result, gas_used, backtrace)), machine)
+            let (finish_ret, machine) = cm.finish();
+            (Ok((result, finish_ret)), machine)
         })?;
 
         // Extract the exit code and build the result of the message application.
@@ -119,6 +174,8 @@ where
                     ErrorNumber::AssertionFailed => ExitCode::SysErrIllegalArgument,
                     ErrorNumber::InsufficientFunds => ExitCode::SysErrInsufficientFunds,
                     ErrorNumber::NotFound => ExitCode::SysErrInvalidReceiver,
+                    // The message exceeded the call depth, or the instance or memory limits.
+                    ErrorNumber::LimitExceeded => ExitCode::SysErrForbidden,
                     code => {
                         return Err(anyhow!(
                             "unexpected syscall error when processing message: {} ({})",
@@ -154,36 +211,24 @@ where
         };
 
         match apply_kind {
-            ApplyKind::Explicit => self.finish_message(msg, receipt, failure_info, gas_cost),
+            ApplyKind::Explicit => self.finish_message(
+                msg,
+                receipt,
+                failure_info,
+                exec_trace,
+                gas_profile,
+                gas_cost,
+            ),
             ApplyKind::Implicit => Ok(ApplyRet {
                 msg_receipt: receipt,
                 failure_info,
                 penalty: TokenAmount::zero(),
                 miner_tip: TokenAmount::zero(),
+                exec_trace,
+                gas_profile,
             }),
         }
     }
-}
-
-impl<K> DefaultExecutor<K>
-where
-    K: Kernel,
-{
-    pub fn new(m: <K::CallManager as CallManager>::Machine) -> Self {
-        Self(Some(m))
-    }
-
-    /// Flush the state-tree to the underlying blockstore.
-    pub fn flush(&mut self) -> anyhow::Result<Cid> {
-        let k = (&mut **self).flush()?;
-        Ok(k)
-    }
-
-    /// Consume consumes the executor and returns the Machine. If the Machine had
-    /// been poisoned during execution, the Option will be None.
-    pub fn consume(self) -> Option<<K::CallManager as CallManager>::Machine> {
-        self.0
-    }
 
     // TODO: The return type here is very strange because we have three cases:
     // 1. Continue (return actor ID & gas).
@@ -196,13 +241,14 @@ where
         msg: &Message,
         apply_kind: ApplyKind,
         raw_length: usize,
+        read_only: bool,
     ) -> Result<StdResult<(ActorID, TokenAmount, GasCharge<'static>), ApplyRet>> {
         // TODO sanity check on message, copied from Forest, needs adaptation.
         msg.check().or_fatal()?;
 
         // TODO I don't like having price lists _inside_ the FVM, but passing
         //  these across the boundary is also a no-go.
-        let pl = &self.context().price_list;
+        let pl = &self.context().network.price_list;
 
         let (inclusion_cost, miner_penalty_amount) = match apply_kind {
             ApplyKind::Implicit => (GasCharge::new("none", 0, 0), Default::default()),
@@ -274,6 +320,14 @@ where
             )));
         };
 
+        // Compute the maximum gas cost of the message.
+        let gas_cost: TokenAmount = msg.gas_fee_cap.clone() * msg.gas_limit;
+
+        // Read-only messages don't care about the sender's nonce or balance.
+        if read_only {
+            return Ok(Ok((sender_id, gas_cost, inclusion_cost)));
+        }
+
         // Check sequence is correct
         if msg.sequence != sender.sequence {
             return Ok(Err(ApplyRet::prevalidation_fail(
@@ -287,7 +341,6 @@ where
         };
 
         // Ensure from actor has enough balance to cover the gas cost of the message.
-        let gas_cost: TokenAmount = msg.gas_fee_cap.clone() * msg.gas_limit;
         if sender.balance < gas_cost {
             return Ok(Err(ApplyRet::prevalidation_fail(
                 ExitCode::SysErrSenderStateInvalid,
@@ -314,6 +367,8 @@ where
         msg: Message,
         receipt: Receipt,
         failure_info: Option<ApplyFailure>,
+        exec_trace: Option<ExecutionTrace>,
+        gas_profile: Option<GasProfile>,
         gas_cost: BigInt,
     ) -> anyhow::Result<ApplyRet> {
         // NOTE: we don't support old network versions in the FVM, so we always burn.
@@ -367,6 +422,8 @@ where
             failure_info,
             penalty: miner_penalty,
             miner_tip,
+            exec_trace,
+            gas_profile,
         })
     }
 
@@ -386,3 +443,192 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::{Code, MultihashDigest};
+    use fvm_shared::blockstore::Blockstore;
+    use fvm_shared::encoding::DAG_CBOR;
+
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Config;
+
+    const ACTOR_ID: ActorID = 1000;
+
+    /// An actor that calls itself, aborting with `ErrForbidden` if the call fails (e.g., because
+    /// the call stack is too deep), and with the callee's exit code if the callee aborts.
+    const RECURSIVE_ACTOR: &str = r#"
+        (module
+          (import "send" "send"
+            (func $send (param i32 i32 i32 i64 i32 i64 i64) (result i32)))
+          (import "vm" "abort" (func $abort (param i32 i32 i32) (result i32)))
+          (memory (export "memory") 1 1)
+          ;; The actor's own address, f01000.
+          (data (i32.const 0) "\00\e8\07")
+          (func (export "invoke") (param i32) (result i32)
+            ;; The exit code and return block are written at 16.
+            (if (call $send (i32.const 16) (i32.const 0) (i32.const 3)
+                  (i64.const 2) (i32.const 0) (i64.const 0) (i64.const 0))
+              (then (drop (call $abort (i32.const 18) (i32.const 0) (i32.const 0)))))
+            (if (i32.load (i32.const 16))
+              (then (drop (call $abort (i32.load (i32.const 16)) (i32.const 0) (i32.const 0)))))
+            (i32.const 0)))
+    "#;
+
+    /// An actor that sets its state to a new block, `[1, 2]`, then aborts with
+    /// `ErrIllegalArgument`.
+    const ABORTING_ACTOR: &str = r#"
+        (module
+          (import "ipld" "create" (func $create (param i32 i64 i32 i32) (result i32)))
+          (import "ipld" "cid" (func $cid (param i32 i32 i64 i32 i32 i32) (result i32)))
+          (import "self" "set_root" (func $set_root (param i32) (result i32)))
+          (import "vm" "abort" (func $abort (param i32 i32 i32) (result i32)))
+          (memory (export "memory") 1 1)
+          (data (i32.const 0) "\82\01\02")
+          (func (export "invoke") (param i32) (result i32)
+            (drop (call $create (i32.const 16) (i64.const 0x71) (i32.const 0) (i32.const 3)))
+            (drop (call $cid (i32.const 20) (i32.load (i32.const 16))
+              (i64.const 0xb220) (i32.const 32) (i32.const 32) (i32.const 64)))
+            (drop (call $set_root (i32.const 32)))
+            (drop (call $abort (i32.const 16) (i32.const 0) (i32.const 0)))
+            (i32.const 0)))
+    "#;
+
+    /// An actor that, on method 2, sets its state to a new block, `[1, 2]`, and calls itself with
+    /// method 3. On method 3, it opens its state, aborting with `ErrNotFound` if it can't.
+    const REENTRANT_ACTOR: &str = r#"
+        (module
+          (import "ipld" "create" (func $create (param i32 i64 i32 i32) (result i32)))
+          (import "ipld" "cid" (func $cid (param i32 i32 i64 i32 i32 i32) (result i32)))
+          (import "ipld" "open" (func $open (param i32 i32) (result i32)))
+          (import "self" "root" (func $root (param i32 i32 i32) (result i32)))
+          (import "self" "set_root" (func $set_root (param i32) (result i32)))
+          (import "message" "method_number" (func $method_number (param i32) (result i32)))
+          (import "send" "send"
+            (func $send (param i32 i32 i32 i64 i32 i64 i64) (result i32)))
+          (import "vm" "abort" (func $abort (param i32 i32 i32) (result i32)))
+          (memory (export "memory") 1 1)
+          ;; The actor's own address, f01000, and its new state.
+          (data (i32.const 0) "\00\e8\07")
+          (data (i32.const 4) "\82\01\02")
+          (func (export "invoke") (param i32) (result i32)
+            (drop (call $method_number (i32.const 16)))
+            (if (i64.eq (i64.load (i32.const 16)) (i64.const 3))
+              (then
+                ;; Read the state root into 64, and open it.
+                (drop (call $root (i32.const 16) (i32.const 64) (i32.const 64)))
+                (if (call $open (i32.const 16) (i32.const 64))
+                  (then (drop (call $abort (i32.const 17) (i32.const 0) (i32.const 0)))))
+                (return (i32.const 0))))
+            ;; Set the state root (its CID is written at 64), and call method 3.
+            (drop (call $create (i32.const 16) (i64.const 0x71) (i32.const 4) (i32.const 3)))
+            (drop (call $cid (i32.const 20) (i32.load (i32.const 16))
+              (i64.const 0xb220) (i32.const 32) (i32.const 64) (i32.const 64)))
+            (drop (call $set_root (i32.const 64)))
+            (if (call $send (i32.const 16) (i32.const 0) (i32.const 3)
+                  (i64.const 3) (i32.const 0) (i64.const 0) (i64.const 0))
+              (then (drop (call $abort (i32.const 18) (i32.const 0) (i32.const 0)))))
+            (if (i32.load (i32.const 16))
+              (then (drop (call $abort (i32.load (i32.const 16)) (i32.const 0) (i32.const 0)))))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn read_only_execution_reverts_blocks() {
+        let state = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&[0x82, 1, 2]));
+        let wasm = wasm(SET_ROOT_ACTOR);
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[(ACTOR_ID, &wasm)]));
+        let root = executor.flush().unwrap();
+
+        let ret = executor
+            .execute_message_read_only(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::Ok);
+        assert!(!executor.blockstore().has(&state).unwrap());
+        assert_eq!(executor.flush().unwrap(), root);
+
+        // Applying the same message does write the block.
+        let ret = executor
+            .execute_message(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::Ok);
+        assert_ne!(executor.flush().unwrap(), root);
+        assert!(executor.blockstore().has(&state).unwrap());
+    }
+
+    #[test]
+    fn limit_exceeded_produces_receipt() {
+        let config = Config {
+            max_instances: 0,
+            ..test_config()
+        };
+        let wasm = wasm(SET_ROOT_ACTOR);
+        let mut executor = TestExecutor::new(new_machine(config, &[(ACTOR_ID, &wasm)]));
+
+        let ret = executor
+            .execute_message(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::SysErrForbidden);
+        assert!(ret.msg_receipt.gas_used > 0);
+        let sender = executor
+            .state_tree()
+            .get_actor_id(SENDER_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sender.sequence, 1);
+    }
+
+    #[test]
+    fn nested_limits_are_enforced() {
+        let wasm = wasm(RECURSIVE_ACTOR);
+        for config in [
+            Config {
+                max_call_depth: 2,
+                ..test_config()
+            },
+            Config {
+                max_instances: 3,
+                ..test_config()
+            },
+        ] {
+            let mut executor = TestExecutor::new(new_machine(config, &[(ACTOR_ID, &wasm)]));
+            let ret = executor
+                .execute_message(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+                .unwrap();
+            assert_eq!(ret.msg_receipt.exit_code, ExitCode::ErrForbidden);
+        }
+    }
+
+    #[test]
+    fn aborting_drops_blocks() {
+        let state = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&[0x82, 1, 2]));
+        let wasm = wasm(ABORTING_ACTOR);
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[(ACTOR_ID, &wasm)]));
+
+        let ret = executor
+            .execute_message(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::ErrIllegalArgument);
+        assert!(!executor.blockstore().has(&state).unwrap());
+        let actor = executor
+            .state_tree()
+            .get_actor_id(ACTOR_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(actor.state, *crate::EMPTY_ARR_CID);
+    }
+
+    #[test]
+    fn reentrant_calls_open_written_state() {
+        let state = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&[0x82, 1, 2]));
+        let wasm = wasm(REENTRANT_ACTOR);
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[(ACTOR_ID, &wasm)]));
+
+        let ret = executor
+            .execute_message(message(ACTOR_ID, 2), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::Ok);
+        assert!(executor.blockstore().has(&state).unwrap());
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::anyhow;
+use fvm_shared::bigint::BigInt;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::BLOCK_GAS_LIMIT;
+use num_traits::Zero;
+
+use super::{ApplyKind, ApplyRet, DefaultExecutor, Executor};
+use crate::gas::GasOutputs;
+use crate::machine::Machine;
+use crate::Kernel;
+
+/// The gas premium suggested when the message doesn't specify one (0.1 nanoFIL).
+pub const DEFAULT_GAS_PREMIUM: u64 = 100_000;
+
+/// The number of blocks a message is expected to wait for inclusion, by default. The suggested
+/// fee cap covers the maximum base fee increase over this many blocks.
+pub const DEFAULT_MAX_QUEUE_BLOCKS: u32 = 20;
+
+/// The base fee can change by at most 1/8th per epoch.
+const BASE_FEE_MAX_CHANGE_DENOM: u32 = 8;
+
+/// Estimates the gas limit and fees for messages by executing them in read-only mode against the
+/// executor's current state.
+pub struct GasEstimator<'a, K: Kernel> {
+    executor: &'a mut DefaultExecutor<K>,
+    /// The gas premium to suggest for messages without one.
+    pub gas_premium: TokenAmount,
+    /// The number of blocks the message may wait before it's included.
+    pub max_queue_blocks: u32,
+    /// The largest gas limit to try.
+    pub max_gas_limit: i64,
+}
+
+/// A gas estimate for a message.
+#[derive(Clone, Debug)]
+pub struct GasEstimate {
+    /// The minimal gas limit with which the message succeeds.
+    pub gas_limit: i64,
+    /// The suggested fee cap.
+    pub gas_fee_cap: TokenAmount,
+    /// The suggested gas premium.
+    pub gas_premium: TokenAmount,
+    /// The amount the sender would pay if the message were included at the current base fee.
+    pub estimated_cost: TokenAmount,
+    /// The result of applying the message with the estimated gas limit and fees.
+    pub apply_ret: ApplyRet,
+}
+
+impl<'a, K> GasEstimator<'a, K>
+where
+    K: Kernel,
+{
+    pub fn new(executor: &'a mut DefaultExecutor<K>) -> Self {
+        GasEstimator {
+            executor,
+            gas_premium: TokenAmount::from(DEFAULT_GAS_PREMIUM),
+            max_queue_blocks: DEFAULT_MAX_QUEUE_BLOCKS,
+            max_gas_limit: BLOCK_GAS_LIMIT,
+        }
+    }
+
+    /// Suggests a fee cap that covers the given premium and the maximum base fee after
+    /// `max_queue_blocks` epochs.
+    pub fn fee_cap(&self, gas_premium: &TokenAmount) -> TokenAmount {
+        let base_fee = &self.executor.context().base_fee;
+        let num = BigInt::from(BASE_FEE_MAX_CHANGE_DENOM + 1).pow(self.max_queue_blocks);
+        let denom = BigInt::from(BASE_FEE_MAX_CHANGE_DENOM).pow(self.max_queue_blocks);
+        base_fee * num / denom + gas_premium
+    }
+
+    /// Estimates the minimal gas limit and suggests fees for a message. The message's own fee cap
+    /// and premium are kept if they're non-zero, and its gas limit and nonce are ignored.
+    ///
+    /// Returns an error if the message fails with the maximum gas limit.
+    pub fn estimate(&mut self, msg: &Message, raw_length: usize) -> anyhow::Result<GasEstimate> {
+        let mut msg = msg.clone();
+        if msg.gas_premium.is_zero() {
+            msg.gas_premium = self.gas_premium.clone();
+        }
+        if msg.gas_fee_cap.is_zero() {
+            msg.gas_fee_cap = self.fee_cap(&msg.gas_premium);
+        }
+
+        // Make sure the message succeeds at all.
+        let max_ret = self.apply_with_limit(&mut msg, self.max_gas_limit, raw_length)?;
+        if !max_ret.msg_receipt.exit_code.is_success() {
+            return Err(match max_ret.failure_info {
+                Some(info) => anyhow!(
+                    "message failed with the maximum gas limit ({}): {}",
+                    max_ret.msg_receipt.exit_code,
+                    info
+                ),
+                None => anyhow!(
+                    "message failed with the maximum gas limit ({})",
+                    max_ret.msg_receipt.exit_code
+                ),
+            });
+        }
+
+        // The gas used is independent of the gas limit (unless we run out), so it's almost always
+        // the answer. Check it first and fall back on a binary search.
+        let mut lo = max_ret.msg_receipt.gas_used;
+        let lo_ret = self.apply_with_limit(&mut msg, lo, raw_length)?;
+        let (gas_limit, apply_ret) = if lo_ret.msg_receipt.exit_code.is_success() {
+            (lo, lo_ret)
+        } else {
+            // Invariant: the message fails with `lo`, and succeeds with `hi`.
+            let mut hi = self.max_gas_limit;
+            let mut hi_ret = max_ret;
+            while hi - lo > 1 {
+                let mid = lo + (hi - lo) / 2;
+                let ret = self.apply_with_limit(&mut msg, mid, raw_length)?;
+                if ret.msg_receipt.exit_code.is_success() {
+                    hi = mid;
+                    hi_ret = ret;
+                } else {
+                    lo = mid;
+                }
+            }
+            (hi, hi_ret)
+        };
+
+        let outputs = GasOutputs::compute(
+            apply_ret.msg_receipt.gas_used,
+            gas_limit,
+            &self.executor.context().base_fee,
+            &msg.gas_fee_cap,
+            &msg.gas_premium,
+        );
+
+        Ok(GasEstimate {
+            gas_limit,
+            gas_fee_cap: msg.gas_fee_cap,
+            gas_premium: msg.gas_premium,
+            estimated_cost: outputs.base_fee_burn
+                + outputs.over_estimation_burn
+                + outputs.miner_tip,
+            apply_ret,
+        })
+    }
+
+    fn apply_with_limit(
+        &mut self,
+        msg: &mut Message,
+        gas_limit: i64,
+        raw_length: usize,
+    ) -> anyhow::Result<ApplyRet> {
+        msg.gas_limit = gas_limit;
+        self.executor
+            .execute_message_read_only(msg.clone(), ApplyKind::Explicit, raw_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_shared::error::ExitCode;
+    use fvm_shared::METHOD_SEND;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn estimates_minimal_gas_limit() {
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[]));
+        let mut msg = Message {
+            value: TokenAmount::from(1),
+            gas_fee_cap: Zero::zero(),
+            gas_premium: Zero::zero(),
+            ..message(2, METHOD_SEND)
+        };
+
+        let mut estimator = GasEstimator::new(&mut executor);
+        let estimate = estimator.estimate(&msg, 100).unwrap();
+        assert_eq!(estimate.gas_premium, TokenAmount::from(DEFAULT_GAS_PREMIUM));
+        assert_eq!(
+            estimate.gas_fee_cap,
+            estimator.fee_cap(&estimate.gas_premium)
+        );
+        assert!(estimate.gas_fee_cap > TokenAmount::from(100) + &estimate.gas_premium);
+        assert_eq!(estimate.gas_limit, estimate.apply_ret.msg_receipt.gas_used);
+
+        // The estimate is just enough.
+        msg.gas_fee_cap = estimate.gas_fee_cap;
+        msg.gas_premium = estimate.gas_premium;
+        msg.gas_limit = estimate.gas_limit - 1;
+        let ret = executor
+            .execute_message(msg.clone(), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::SysErrOutOfGas);
+
+        msg.sequence += 1;
+        msg.gas_limit = estimate.gas_limit;
+        let ret = executor
+            .execute_message(msg, ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::Ok);
+        assert_eq!(ret.msg_receipt.gas_used, estimate.gas_limit);
+    }
+
+    #[test]
+    fn fails_if_message_cannot_succeed() {
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[]));
+        // The sender can't afford the transfer.
+        let msg = Message {
+            value: TokenAmount::from(10u128.pow(25)),
+            ..message(2, METHOD_SEND)
+        };
+        assert!(GasEstimator::new(&mut executor)
+            .estimate(&msg, 100)
+            .is_err());
+    }
+}
@@ -1,16 +1,23 @@
 mod default;
+mod estimator;
+mod tipset;
+mod validation;
 
 use std::fmt::Display;
 
 pub use default::DefaultExecutor;
+pub use estimator::{GasEstimate, GasEstimator, DEFAULT_GAS_PREMIUM, DEFAULT_MAX_QUEUE_BLOCKS};
 use fvm_shared::bigint::{BigInt, Sign};
 use fvm_shared::encoding::RawBytes;
 use fvm_shared::error::ExitCode;
 use fvm_shared::message::Message;
 use fvm_shared::receipt::Receipt;
 use num_traits::Zero;
+pub use tipset::{ApplyTipsetRet, BlockMessages, ChainMessage};
+pub use validation::{MessageVerdict, UnvalidatedMessage, ValidationError};
 
-use crate::call_manager::Backtrace;
+use crate::call_manager::{Backtrace, ExecutionTrace};
+use crate::gas::GasProfile;
 use crate::Kernel;
 
 pub trait Executor {
@@ -23,6 +30,18 @@ pub trait Executor {
         _: ApplyKind,
         raw_length: usize,
     ) -> anyhow::Result<ApplyRet>;
+
+    /// Executes a message against the current state without committing any state changes (e.g.,
+    /// for `StateCall` and gas estimation).
+    ///
+    /// The sender's nonce and balance are not checked, and the sender is not charged for gas. The
+    /// returned receipt reflects the gas that _would_ have been used.
+    fn execute_message_read_only(
+        &mut self,
+        msg: Message,
+        _: ApplyKind,
+        raw_length: usize,
+    ) -> anyhow::Result<ApplyRet>;
 }
 
 /// A description of some failure encountered when applying a message.
@@ -60,6 +79,10 @@ pub struct ApplyRet {
     pub miner_tip: BigInt,
     /// Additional failure information for debugging, if any.
     pub failure_info: Option<ApplyFailure>,
+    /// The execution trace, if tracing is enabled.
+    pub exec_trace: Option<ExecutionTrace>,
+    /// The gas profile, if gas profiling is enabled.
+    pub gas_profile: Option<GasProfile>,
 }
 
 impl ApplyRet {
@@ -78,6 +101,8 @@ impl ApplyRet {
             penalty: miner_penalty,
             failure_info: Some(ApplyFailure::PreValidation(message.into())),
             miner_tip: BigInt::zero(),
+            exec_trace: None,
+            gas_profile: None,
         }
     }
 
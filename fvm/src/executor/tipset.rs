@@ -0,0 +1,306 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Context as _};
+use cid::Cid;
+use fvm_ipld_amt::Amt;
+use fvm_shared::address::Address;
+use fvm_shared::blockstore::Buffered;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::encoding::{Cbor, RawBytes};
+use fvm_shared::message::Message;
+use fvm_shared::receipt::Receipt;
+use fvm_shared::BLOCK_GAS_LIMIT;
+use num_traits::Zero;
+
+use super::{ApplyKind, ApplyRet, DefaultExecutor, Executor};
+use crate::account_actor::SYSTEM_ACTOR_ID;
+use crate::call_manager::CallManager;
+use crate::cron_actor::{CRON_ACTOR_ID, EPOCH_TICK_METHOD};
+use crate::machine::Machine;
+use crate::reward_actor::{AwardBlockRewardParams, AWARD_BLOCK_REWARD_METHOD, REWARD_ACTOR_ID};
+use crate::Kernel;
+
+/// The gas limit of the implicit reward message.
+const REWARD_GAS_LIMIT: i64 = 1 << 30;
+
+/// A message included in a block.
+#[derive(Clone, Debug)]
+pub struct ChainMessage {
+    /// The message's CID. For secp256k1 messages, this is the CID of the _signed_ message.
+    pub cid: Cid,
+    /// The message.
+    pub message: Message,
+    /// The length of the message as included on chain (including the signature, if any).
+    pub raw_length: usize,
+}
+
+/// The messages of a single block in a tipset.
+#[derive(Clone, Debug)]
+pub struct BlockMessages {
+    /// The block's miner, who will be rewarded for the block.
+    pub miner: Address,
+    /// The number of times the miner won the election for this block.
+    pub win_count: i64,
+    /// The block's messages, in order.
+    pub messages: Vec<ChainMessage>,
+}
+
+/// The result of applying a tipset.
+#[derive(Clone, Debug)]
+pub struct ApplyTipsetRet {
+    /// The results of all applied messages in order, excluding duplicates.
+    pub message_rets: Vec<(Cid, ApplyRet)>,
+    /// The results of the implicit reward messages, one per block.
+    pub reward_rets: Vec<ApplyRet>,
+    /// The result of the implicit cron message.
+    pub cron_ret: ApplyRet,
+    /// The root of the AMT of message receipts (in the same order as `message_rets`).
+    pub receipts_root: Cid,
+    /// The final state root.
+    pub state_root: Cid,
+}
+
+impl ApplyTipsetRet {
+    /// Returns the receipts of all applied messages, in order.
+    pub fn receipts(&self) -> impl Iterator<Item = &Receipt> {
+        self.message_rets.iter().map(|(_, ret)| &ret.msg_receipt)
+    }
+}
+
+impl<K> DefaultExecutor<K>
+where
+    K: Kernel,
+    <<K::CallManager as CallManager>::Machine as Machine>::Blockstore: Buffered,
+{
+    /// Applies the messages of all blocks in a tipset in order, skipping messages that have
+    /// already been applied (by CID). After each block, the block's miner is rewarded with an
+    /// implicit message to the reward actor. Finally, cron is run with an implicit message to the
+    /// cron actor.
+    ///
+    /// The receipts AMT and the state tree are flushed to the blockstore.
+    pub fn apply_tipset(&mut self, blocks: &[BlockMessages]) -> anyhow::Result<ApplyTipsetRet> {
+        let epoch = self.context().epoch;
+
+        let mut seen = HashSet::new();
+        let mut message_rets = Vec::new();
+        let mut reward_rets = Vec::with_capacity(blocks.len());
+
+        for block in blocks {
+            let mut penalty = TokenAmount::zero();
+            let mut gas_reward = TokenAmount::zero();
+
+            for msg in &block.messages {
+                if !seen.insert(msg.cid) {
+                    continue;
+                }
+                let ret =
+                    self.execute_message(msg.message.clone(), ApplyKind::Explicit, msg.raw_length)?;
+                penalty += &ret.penalty;
+                gas_reward += &ret.miner_tip;
+                message_rets.push((msg.cid, ret));
+            }
+
+            let params = RawBytes::serialize(AwardBlockRewardParams {
+                miner: block.miner,
+                penalty,
+                gas_reward,
+                win_count: block.win_count,
+            })?;
+            let ret = self
+                .apply_implicit(Message {
+                    version: 0,
+                    from: Address::new_id(SYSTEM_ACTOR_ID),
+                    to: Address::new_id(REWARD_ACTOR_ID),
+                    sequence: epoch as u64,
+                    value: TokenAmount::zero(),
+                    method_num: AWARD_BLOCK_REWARD_METHOD,
+                    params,
+                    gas_limit: REWARD_GAS_LIMIT,
+                    gas_fee_cap: TokenAmount::zero(),
+                    gas_premium: TokenAmount::zero(),
+                })
+                .with_context(|| format!("failed to reward miner {}", block.miner))?;
+            reward_rets.push(ret);
+        }
+
+        let cron_ret = self
+            .apply_implicit(Message {
+                version: 0,
+                from: Address::new_id(SYSTEM_ACTOR_ID),
+                to: Address::new_id(CRON_ACTOR_ID),
+                sequence: epoch as u64,
+                value: TokenAmount::zero(),
+                method_num: EPOCH_TICK_METHOD,
+                params: RawBytes::default(),
+                gas_limit: BLOCK_GAS_LIMIT * 10000,
+                gas_fee_cap: TokenAmount::zero(),
+                gas_premium: TokenAmount::zero(),
+            })
+            .context("failed to run cron")?;
+
+        // Flushing only writes the blocks reachable from the given root, and drops the rest. So
+        // the state tree has to be flushed before building the receipts AMT.
+        let state_root = self.flush()?;
+
+        let receipts_root = Amt::new_from_iter(
+            self.blockstore(),
+            message_rets.iter().map(|(_, ret)| ret.msg_receipt.clone()),
+        )
+        .context("failed to build the receipts AMT")?;
+        self.blockstore()
+            .flush(&receipts_root)
+            .context("failed to flush the receipts AMT")?;
+
+        Ok(ApplyTipsetRet {
+            message_rets,
+            reward_rets,
+            cron_ret,
+            receipts_root,
+            state_root,
+        })
+    }
+
+    /// Applies an implicit message, failing if it doesn't succeed.
+    fn apply_implicit(&mut self, msg: Message) -> anyhow::Result<ApplyRet> {
+        let raw_length = msg.marshal_cbor()?.len();
+        let ret = self.execute_message(msg, ApplyKind::Implicit, raw_length)?;
+        if !ret.msg_receipt.exit_code.is_success() {
+            return Err(match &ret.failure_info {
+                Some(info) => anyhow!(
+                    "implicit message failed ({}): {}",
+                    ret.msg_receipt.exit_code,
+                    info
+                ),
+                None => anyhow!("implicit message failed ({})", ret.msg_receipt.exit_code),
+            });
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_shared::blockstore::CborStore;
+    use fvm_shared::METHOD_SEND;
+
+    use super::*;
+    use crate::state_tree::StateTree;
+    use crate::test_utils::*;
+
+    fn chain_message(sequence: u64) -> ChainMessage {
+        let message = Message {
+            sequence,
+            value: TokenAmount::from(1),
+            ..message(99, METHOD_SEND)
+        };
+        ChainMessage {
+            cid: message.cid().unwrap(),
+            raw_length: message.marshal_cbor().unwrap().len(),
+            message,
+        }
+    }
+
+    #[test]
+    fn apply_tipset() {
+        let noop = wasm(NOOP_ACTOR);
+        let machine = new_machine(
+            test_config(),
+            &[(REWARD_ACTOR_ID, &noop), (CRON_ACTOR_ID, &noop)],
+        );
+        let mut executor = TestExecutor::new(machine);
+
+        // The second block includes the first message again.
+        let blocks = [
+            BlockMessages {
+                miner: Address::new_id(1000),
+                win_count: 1,
+                messages: vec![chain_message(0), chain_message(1)],
+            },
+            BlockMessages {
+                miner: Address::new_id(1001),
+                win_count: 2,
+                messages: vec![chain_message(0), chain_message(2)],
+            },
+        ];
+        let ret = executor.apply_tipset(&blocks).unwrap();
+
+        let cids: Vec<_> = ret.message_rets.iter().map(|(cid, _)| *cid).collect();
+        assert_eq!(
+            cids,
+            [
+                blocks[0].messages[0].cid,
+                blocks[0].messages[1].cid,
+                blocks[1].messages[1].cid
+            ]
+        );
+        assert!(ret.receipts().all(|r| r.exit_code.is_success()));
+        assert_eq!(ret.reward_rets.len(), 2);
+        assert!(ret.cron_ret.msg_receipt.exit_code.is_success());
+
+        let receipts: Amt<Receipt, _> =
+            Amt::load(&ret.receipts_root, executor.blockstore()).unwrap();
+        assert_eq!(receipts.count(), 3);
+        for (i, receipt) in ret.receipts().enumerate() {
+            assert_eq!(receipts.get(i as u64).unwrap(), Some(receipt));
+        }
+
+        let sender = executor
+            .state_tree()
+            .get_actor_id(SENDER_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sender.sequence, 3);
+        let reward = executor
+            .state_tree()
+            .get_actor_id(REWARD_ACTOR_ID)
+            .unwrap()
+            .unwrap();
+        let tips = ret
+            .message_rets
+            .iter()
+            .fold(TokenAmount::zero(), |acc, (_, r)| acc + &r.miner_tip);
+        assert!(!tips.is_zero());
+        assert_eq!(reward.balance, tips);
+
+        assert_eq!(executor.flush().unwrap(), ret.state_root);
+    }
+
+    #[test]
+    fn apply_tipset_writes_actor_state() {
+        let noop = wasm(NOOP_ACTOR);
+        let set_root = wasm(SET_ROOT_ACTOR);
+        let machine = new_machine(
+            test_config(),
+            &[(REWARD_ACTOR_ID, &noop), (CRON_ACTOR_ID, &set_root)],
+        );
+        let mut executor = TestExecutor::new(machine);
+        let blocks = [BlockMessages {
+            miner: Address::new_id(1000),
+            win_count: 1,
+            messages: vec![chain_message(0)],
+        }];
+        let ret = executor.apply_tipset(&blocks).unwrap();
+
+        // Both the state written by cron and the receipts made it to the base store.
+        let bs = executor.consume().unwrap().consume().consume();
+        let st = StateTree::new_from_root(&bs, &ret.state_root).unwrap();
+        let cron = st.get_actor_id(CRON_ACTOR_ID).unwrap().unwrap();
+        assert_eq!(bs.get_cbor::<(u8, u8)>(&cron.state).unwrap(), Some((1, 2)));
+        let receipts: Amt<Receipt, _> = Amt::load(&ret.receipts_root, &bs).unwrap();
+        assert_eq!(receipts.count(), 1);
+    }
+
+    #[test]
+    fn apply_tipset_fails_if_cron_fails() {
+        // There's no cron actor.
+        let noop = wasm(NOOP_ACTOR);
+        let machine = new_machine(test_config(), &[(REWARD_ACTOR_ID, &noop)]);
+        let mut executor = TestExecutor::new(machine);
+        let blocks = [BlockMessages {
+            miner: Address::new_id(1000),
+            win_count: 1,
+            messages: vec![],
+        }];
+        assert!(executor.apply_tipset(&blocks).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use fvm_shared::actor::builtin::Type;
+use fvm_shared::address::{Address, Protocol};
+use fvm_shared::blockstore::CborStore;
+use fvm_shared::crypto::signature::ops::verify_bls_aggregate;
+use fvm_shared::crypto::signature::Signature;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use super::DefaultExecutor;
+use crate::account_actor;
+use crate::machine::Machine;
+use crate::Kernel;
+
+/// A message to be pre-validated.
+#[derive(Clone, Debug)]
+pub struct UnvalidatedMessage {
+    /// The message.
+    pub message: Message,
+    /// The message's signature. This is `None` for BLS messages covered by the block's aggregate
+    /// signature.
+    pub signature: Option<Signature>,
+    /// The length of the message as included on chain (including the signature, if any).
+    pub raw_length: usize,
+}
+
+/// The reason a message failed pre-validation.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    #[error("invalid message: {0}")]
+    InvalidMessage(String),
+    #[error("inclusion gas {required} exceeds the gas limit {limit}")]
+    InsufficientGas { required: i64, limit: i64 },
+    #[error("sender {0} not found")]
+    SenderNotFound(Address),
+    #[error("sender {0} is not an account actor")]
+    SenderNotAccount(Address),
+    #[error("invalid sequence: expected {expected}, got {actual}")]
+    InvalidSequence { expected: u64, actual: u64 },
+    #[error("sender balance {balance} is less than the maximum gas cost {gas_cost}")]
+    InsufficientBalance {
+        balance: TokenAmount,
+        gas_cost: TokenAmount,
+    },
+    #[error("message has no signature and isn't covered by a BLS aggregate signature")]
+    MissingSignature,
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+/// The pre-validation verdict for a single message.
+pub type MessageVerdict = std::result::Result<(), ValidationError>;
+
+/// The result of checking a message against the state tree: the sender's key address and current
+/// sequence or, on failure, the error along with the sender's key address (if known).
+type CheckedMessage = std::result::Result<(Address, u64), (Option<Address>, ValidationError)>;
+
+impl<K> DefaultExecutor<K>
+where
+    K: Kernel,
+{
+    /// Pre-validates a batch of messages (e.g., the messages of a block) against the current state,
+    /// returning a verdict for each message, in order.
+    ///
+    /// Messages are checked for:
+    ///
+    /// 1. Basic validity and sufficient gas to cover inclusion.
+    /// 2. A valid account sender with enough balance to cover the maximum gas cost.
+    /// 3. A valid signature. Messages without a signature must be BLS messages covered by
+    ///    `bls_aggregate`; if the aggregate signature is invalid, all such messages are rejected.
+    /// 4. The expected sequence, taking earlier messages from the same sender in the batch into
+    ///    account. Only messages that passed all other checks count towards the sender's sequence.
+    ///
+    /// Signatures are verified in parallel. This method doesn't modify the state tree.
+    pub fn validate_messages(
+        &self,
+        msgs: &[UnvalidatedMessage],
+        bls_aggregate: Option<&Signature>,
+    ) -> anyhow::Result<Vec<MessageVerdict>> {
+        // Check everything that requires the state tree serially, resolving the senders' key
+        // addresses as we go.
+        let checked = msgs
+            .iter()
+            .map(|msg| self.check_message(msg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let senders: Vec<_> = checked
+            .iter()
+            .map(|res| res.as_ref().ok().copied())
+            .collect();
+
+        // Collect the BLS messages covered by the aggregate signature. The aggregate covers all of
+        // them, so we include them even if they've already been rejected.
+        let aggregated: Vec<(Vec<u8>, Vec<u8>)> = msgs
+            .iter()
+            .zip(&checked)
+            .filter(|(msg, _)| msg.signature.is_none())
+            .filter_map(|(msg, res)| match res {
+                Ok((key, _)) | Err((Some(key), _)) if key.protocol() == Protocol::BLS => {
+                    Some((msg.message.to_signing_bytes(), key.payload_bytes()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let verify_aggregate = || match bls_aggregate {
+            _ if aggregated.is_empty() => Ok(()),
+            None => Err(ValidationError::MissingSignature),
+            Some(sig) => {
+                let data: Vec<&[u8]> = aggregated.iter().map(|(d, _)| &d[..]).collect();
+                let keys: Vec<&[u8]> = aggregated.iter().map(|(_, k)| &k[..]).collect();
+                if verify_bls_aggregate(&data, &keys, sig) {
+                    Ok(())
+                } else {
+                    Err(ValidationError::InvalidSignature(
+                        "bls aggregate signature verification failed".to_owned(),
+                    ))
+                }
+            }
+        };
+
+        let verify_signatures = || {
+            msgs.into_par_iter()
+                .zip(checked.into_par_iter())
+                .map(|(msg, res)| {
+                    let (key, _) = res.map_err(|(_, e)| e)?;
+                    match &msg.signature {
+                        Some(sig) => sig
+                            .verify(&msg.message.to_signing_bytes(), &key)
+                            .map_err(ValidationError::InvalidSignature),
+                        // Checked against the aggregate signature below.
+                        None if key.protocol() == Protocol::BLS => Ok(()),
+                        None => Err(ValidationError::MissingSignature),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (aggregate_verdict, mut verdicts) = rayon::join(verify_aggregate, verify_signatures);
+
+        if let Err(e) = aggregate_verdict {
+            for (msg, verdict) in msgs.iter().zip(verdicts.iter_mut()) {
+                if msg.signature.is_none() && verdict.is_ok() {
+                    *verdict = Err(e.clone());
+                }
+            }
+        }
+
+        // Finally, check the sequences serially. This comes last so that rejected messages (e.g.,
+        // with a bad signature) don't use up their sender's next sequence.
+        let mut next_sequence: HashMap<Address, u64> = HashMap::new();
+        for ((msg, sender), verdict) in msgs.iter().zip(senders).zip(verdicts.iter_mut()) {
+            let (key, sequence) = match sender {
+                Some(sender) if verdict.is_ok() => sender,
+                _ => continue,
+            };
+            let expected = next_sequence.entry(key).or_insert(sequence);
+            if msg.message.sequence == *expected {
+                *expected += 1;
+            } else {
+                *verdict = Err(ValidationError::InvalidSequence {
+                    expected: *expected,
+                    actual: msg.message.sequence,
+                });
+            }
+        }
+
+        Ok(verdicts)
+    }
+
+    /// Checks a message against the state tree (except for its sequence), returning the sender's
+    /// key address and current sequence. On failure, the sender's key address is returned along
+    /// with the error, if known.
+    fn check_message(&self, msg: &UnvalidatedMessage) -> anyhow::Result<CheckedMessage> {
+        let UnvalidatedMessage {
+            message,
+            raw_length,
+            ..
+        } = msg;
+
+        // Resolve the sender first so we know its key address (for the BLS aggregate), even if
+        // the message is otherwise invalid. If the message is from a key address, that's the key
+        // even if the sender doesn't exist.
+        let from_key = match message.from.protocol() {
+            Protocol::BLS | Protocol::Secp256k1 => Some(message.from),
+            _ => None,
+        };
+        let sender = match self.state_tree().get_actor(&message.from)? {
+            Some(act) => act,
+            None => {
+                return Ok(Err((
+                    from_key,
+                    ValidationError::SenderNotFound(message.from),
+                )))
+            }
+        };
+        let is_account = self
+            .builtin_actors()
+            .get_by_left(&sender.code)
+            .map(Type::is_account_actor)
+            .unwrap_or(false);
+        if !is_account {
+            return Ok(Err((
+                from_key,
+                ValidationError::SenderNotAccount(message.from),
+            )));
+        }
+        let key = match from_key {
+            Some(key) => key,
+            None => {
+                let state: account_actor::State = self
+                    .blockstore()
+                    .get_cbor(&sender.state)?
+                    .ok_or_else(|| anyhow::anyhow!("account actor state not found"))?;
+                state.address
+            }
+        };
+
+        if let Err(e) = message.check() {
+            return Ok(Err((
+                Some(key),
+                ValidationError::InvalidMessage(e.to_string()),
+            )));
+        }
+
+        let inclusion_cost = self
+            .context()
+            .network
+            .price_list
+            .on_chain_message(*raw_length)
+            .total();
+        if inclusion_cost > message.gas_limit {
+            return Ok(Err((
+                Some(key),
+                ValidationError::InsufficientGas {
+                    required: inclusion_cost,
+                    limit: message.gas_limit,
+                },
+            )));
+        }
+
+        let gas_cost: TokenAmount = message.gas_fee_cap.clone() * message.gas_limit;
+        if sender.balance < gas_cost {
+            return Ok(Err((
+                Some(key),
+                ValidationError::InsufficientBalance {
+                    balance: sender.balance,
+                    gas_cost,
+                },
+            )));
+        }
+
+        Ok(Ok((key, sender.sequence)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bls_signatures::{PrivateKey, Serialize};
+    use cid::multihash::Code;
+    use fvm_shared::encoding::blake2b_256;
+    use fvm_shared::{ActorID, METHOD_SEND};
+    use libsecp256k1::{PublicKey, SecretKey};
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::state_tree::ActorState;
+    use crate::test_utils::*;
+
+    const ACCOUNT_ID: ActorID = 1000;
+
+    /// Creates an account actor with the given key address and balance.
+    fn add_account(executor: &mut TestExecutor, key: Address, balance: TokenAmount) {
+        let state = executor
+            .blockstore()
+            .put_cbor(&account_actor::State { address: key }, Code::Blake2b256)
+            .unwrap();
+        let account = ActorState::new(builtin_code(Type::Account), state, balance, 0);
+        executor
+            .state_tree_mut()
+            .set_actor_id(ACCOUNT_ID, account)
+            .unwrap();
+    }
+
+    /// Creates an executor with a secp256k1 account, returning the account's key.
+    fn secp_executor(balance: TokenAmount) -> (TestExecutor, SecretKey) {
+        let key = SecretKey::random(&mut ChaCha8Rng::seed_from_u64(8));
+        let addr = Address::new_secp256k1(&PublicKey::from_secret_key(&key).serialize()).unwrap();
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[]));
+        add_account(&mut executor, addr, balance);
+        (executor, key)
+    }
+
+    /// Returns a message from the account created by [`secp_executor`], signed with `key`.
+    fn signed(key: &SecretKey, sequence: u64) -> UnvalidatedMessage {
+        sign(
+            key,
+            Message {
+                from: Address::new_id(ACCOUNT_ID),
+                sequence,
+                ..message(2, METHOD_SEND)
+            },
+        )
+    }
+
+    /// Signs a message with a secp256k1 key.
+    fn sign(key: &SecretKey, message: Message) -> UnvalidatedMessage {
+        let hash = blake2b_256(&message.to_signing_bytes());
+        let (sig, recovery_id) = libsecp256k1::sign(&libsecp256k1::Message::parse(&hash), key);
+        let mut signature = sig.serialize().to_vec();
+        signature.push(recovery_id.serialize());
+        UnvalidatedMessage {
+            message,
+            signature: Some(Signature::new_secp256k1(signature)),
+            raw_length: 100,
+        }
+    }
+
+    #[test]
+    fn secp_signatures() {
+        let (executor, key) = secp_executor(TokenAmount::from(10u128.pow(24)));
+        let other = SecretKey::random(&mut ChaCha8Rng::seed_from_u64(9));
+
+        let unsigned = UnvalidatedMessage {
+            signature: None,
+            ..signed(&key, 0)
+        };
+        let msgs = [
+            signed(&other, 0),
+            unsigned,
+            signed(&key, 0),
+            signed(&key, 1),
+        ];
+        let verdicts = executor.validate_messages(&msgs, None).unwrap();
+        assert!(matches!(
+            verdicts[0],
+            Err(ValidationError::InvalidSignature(_))
+        ));
+        assert_eq!(verdicts[1], Err(ValidationError::MissingSignature));
+        // The rejected messages didn't use up the sequence.
+        assert_eq!(verdicts[2..], [Ok(()), Ok(())]);
+    }
+
+    #[test]
+    fn sequence_gaps_and_duplicates() {
+        let (executor, key) = secp_executor(TokenAmount::from(10u128.pow(24)));
+
+        let msgs = [
+            signed(&key, 0),
+            signed(&key, 0),
+            signed(&key, 2),
+            signed(&key, 1),
+        ];
+        let verdicts = executor.validate_messages(&msgs, None).unwrap();
+        assert_eq!(
+            verdicts,
+            [
+                Ok(()),
+                Err(ValidationError::InvalidSequence {
+                    expected: 1,
+                    actual: 0
+                }),
+                Err(ValidationError::InvalidSequence {
+                    expected: 1,
+                    actual: 2
+                }),
+                Ok(()),
+            ]
+        );
+    }
+
+    #[test]
+    fn insufficient_balance() {
+        // Enough for a gas limit of up to 10^8, at the default fee cap of 100.
+        let balance = TokenAmount::from(10u64.pow(10));
+        let (executor, key) = secp_executor(balance.clone());
+
+        let expensive = signed(&key, 0);
+        let cheap = sign(
+            &key,
+            Message {
+                gas_limit: 10i64.pow(8),
+                ..expensive.message.clone()
+            },
+        );
+
+        let verdicts = executor
+            .validate_messages(&[expensive, cheap], None)
+            .unwrap();
+        assert_eq!(
+            verdicts,
+            [
+                Err(ValidationError::InsufficientBalance {
+                    balance,
+                    gas_cost: TokenAmount::from(10u64.pow(11)),
+                }),
+                Ok(()),
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_covers_messages_from_missing_senders() {
+        let rng = &mut ChaCha8Rng::seed_from_u64(8);
+        let keys = [PrivateKey::generate(rng), PrivateKey::generate(rng)];
+        let addrs: Vec<_> = keys
+            .iter()
+            .map(|k| Address::new_bls(&k.public_key().as_bytes()).unwrap())
+            .collect();
+
+        // An account for the first key only.
+        let mut executor = TestExecutor::new(new_machine(test_config(), &[]));
+        add_account(&mut executor, addrs[0], TokenAmount::from(10u128.pow(24)));
+
+        let msgs = [Address::new_id(ACCOUNT_ID), addrs[1]].map(|from| UnvalidatedMessage {
+            message: Message {
+                from,
+                ..message(2, METHOD_SEND)
+            },
+            signature: None,
+            raw_length: 100,
+        });
+        let signatures: Vec<_> = keys
+            .iter()
+            .zip(&msgs)
+            .map(|(k, m)| k.sign(m.message.to_signing_bytes()))
+            .collect();
+        let aggregate =
+            Signature::new_bls(bls_signatures::aggregate(&signatures).unwrap().as_bytes());
+
+        let verdicts = executor.validate_messages(&msgs, Some(&aggregate)).unwrap();
+        assert_eq!(
+            verdicts,
+            [Ok(()), Err(ValidationError::SenderNotFound(addrs[1]))]
+        );
+    }
+}
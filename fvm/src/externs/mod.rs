@@ -1,10 +1,40 @@
 //! This module contains the logic to invoke the node by traversing Boundary A.
 
+use cid::Cid;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::consensus::ConsensusFault;
 use fvm_shared::crypto::randomness::DomainSeparationTag;
+use fvm_shared::econ::TokenAmount;
 
-pub trait Externs: Rand + Consensus {}
+pub trait Externs: Rand + Consensus {
+    /// Returns the node's chain lookups, if it provides any. Without them, the FVM falls back on
+    /// its own behavior (see [`Chain`]).
+    fn chain(&self) -> Option<&dyn Chain> {
+        None
+    }
+}
+
+/// Chain related methods, optionally provided by the node through [`Externs::chain`].
+///
+/// All methods are optional. Returning `None` tells the FVM to fall back on its own behavior.
+pub trait Chain {
+    /// Returns the total FIL circulating supply at the current point of execution, overriding the
+    /// FVM's own behavior (see [`CircSupplyOps`](crate::kernel::CircSupplyOps)), for all network
+    /// versions:
+    ///
+    /// - Up to V14, the FVM calculates the supply from the state of the reward, power, market and
+    ///   burnt funds actors, and this replaces that calculation.
+    /// - From V15, the FVM reports the static per-epoch supply from the machine context
+    ///   (`MachineContext::circ_supply`), and this replaces that value.
+    fn total_fil_circ_supply(&self, _epoch: ChainEpoch) -> anyhow::Result<Option<TokenAmount>> {
+        Ok(None)
+    }
+
+    /// Returns the CID of the tipset at the given (past) epoch, or `None` if unknown.
+    fn get_tipset_cid(&self, _epoch: ChainEpoch) -> anyhow::Result<Option<Cid>> {
+        Ok(None)
+    }
+}
 
 /// Consensus related methods.
 pub trait Consensus {
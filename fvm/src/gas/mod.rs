@@ -3,12 +3,16 @@
 
 pub use self::charge::GasCharge;
 pub(crate) use self::outputs::GasOutputs;
-pub use self::price_list::{price_list_by_epoch, PriceList};
+pub(crate) use self::price_list::WasmInstructionCosts;
+pub use self::price_list::{load_price_lists, price_list_by_network_version, PriceList};
+pub(crate) use self::profile::GasProfiler;
+pub use self::profile::{GasProfile, GasTotals};
 use crate::kernel::{ExecutionError, Result};
 
 mod charge;
 mod outputs;
 mod price_list;
+mod profile;
 
 pub struct GasTracker {
     gas_available: i64,
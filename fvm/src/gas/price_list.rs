@@ -1,18 +1,23 @@
 // Copyright 2019-2022 ChainSafe Systems
 // SPDX-License-Identifier: Apache-2.0, MIT
 
+use std::collections::BTreeMap;
+
 use ahash::AHashMap;
-use fvm_shared::clock::ChainEpoch;
 use fvm_shared::crypto::signature::SignatureType;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::encoding::{from_slice, Cbor};
 use fvm_shared::piece::PieceInfo;
 use fvm_shared::sector::{
     AggregateSealVerifyProofAndInfos, RegisteredPoStProof, RegisteredSealProof, ReplicaUpdateInfo,
     SealVerifyInfo, WindowPoStVerifyInfo,
 };
+use fvm_shared::version::NetworkVersion;
 use fvm_shared::{MethodNum, METHOD_SEND};
 use lazy_static::lazy_static;
 use num_traits::Zero;
+use serde::{Deserialize, Serialize};
+use wasmparser::Operator;
 
 use super::GasCharge;
 
@@ -40,6 +45,22 @@ lazy_static! {
         create_actor_storage: 36 + 40,
         delete_actor: -(36 + 40),
 
+        install_actor_base: 1108454,
+        install_actor_per_byte: 10000,
+
+        wasm_instruction_costs: WasmInstructionCosts {
+            structural: 0,
+            base: 4,
+            memory_access: 8,
+            division: 16,
+            call: 16,
+            memory_grow: 64,
+        },
+        wasm_memory_page_cost: 65536,
+
+        block_allocate_per_byte: 2,
+        block_read_per_byte: 1,
+
         bls_sig_cost: 16598605,
         secp256k1_sig_cost: 1637292,
 
@@ -95,6 +116,7 @@ lazy_static! {
 
         verify_consensus_fault: 495422,
         verify_replica_update: 36316136,
+        tipset_cid_lookup: 114617,
         verify_post_lookup: [
             (
                 RegisteredPoStProof::StackedDRGWindow512MiBV1,
@@ -125,16 +147,18 @@ lazy_static! {
     };
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct ScalingCost {
     flat: i64,
     scale: i64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) struct StepCost(Vec<Step>);
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Step {
     start: i64,
     cost: i64,
@@ -157,8 +181,77 @@ impl StepCost {
     }
 }
 
+/// Gas costs of wasm instructions, by kind of instruction. Floating point, SIMD and bulk memory
+/// instructions aren't allowed in user-deployed actors, so they aren't priced.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WasmInstructionCosts {
+    /// Cost of instructions that compile to little or no code: `nop`, `drop`, `block`, `loop`,
+    /// `else`, and `end`.
+    pub(crate) structural: i64,
+    /// Cost of any instruction not listed below (constants, locals and globals, integer
+    /// arithmetic and comparisons, branches, etc.).
+    pub(crate) base: i64,
+    /// Cost of memory loads and stores.
+    pub(crate) memory_access: i64,
+    /// Cost of integer divisions and remainders.
+    pub(crate) division: i64,
+    /// Cost of direct and indirect calls, excluding the callee's instructions.
+    pub(crate) call: i64,
+    /// Cost of `memory.grow`, excluding the pages allocated (see `wasm_memory_page_cost`).
+    pub(crate) memory_grow: i64,
+}
+
+impl WasmInstructionCosts {
+    /// Returns the gas cost of executing the given instruction.
+    pub(crate) fn operator_cost(&self, op: &Operator) -> i64 {
+        match op {
+            Operator::Nop
+            | Operator::Drop
+            | Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::Else
+            | Operator::End => self.structural,
+            Operator::I32Load { .. }
+            | Operator::I64Load { .. }
+            | Operator::I32Load8S { .. }
+            | Operator::I32Load8U { .. }
+            | Operator::I32Load16S { .. }
+            | Operator::I32Load16U { .. }
+            | Operator::I64Load8S { .. }
+            | Operator::I64Load8U { .. }
+            | Operator::I64Load16S { .. }
+            | Operator::I64Load16U { .. }
+            | Operator::I64Load32S { .. }
+            | Operator::I64Load32U { .. }
+            | Operator::I32Store { .. }
+            | Operator::I64Store { .. }
+            | Operator::I32Store8 { .. }
+            | Operator::I32Store16 { .. }
+            | Operator::I64Store8 { .. }
+            | Operator::I64Store16 { .. }
+            | Operator::I64Store32 { .. } => self.memory_access,
+            Operator::I32DivS
+            | Operator::I32DivU
+            | Operator::I32RemS
+            | Operator::I32RemU
+            | Operator::I64DivS
+            | Operator::I64DivU
+            | Operator::I64RemS
+            | Operator::I64RemU => self.division,
+            Operator::Call { .. } | Operator::CallIndirect { .. } => self.call,
+            Operator::MemoryGrow { .. } => self.memory_grow,
+            _ => self.base,
+        }
+    }
+}
+
 /// Provides prices for operations in the VM
-#[derive(Clone, Debug)]
+///
+/// Price lists can be (de)serialized, so gas schedules can be defined outside of the FVM (see
+/// [`load_price_lists`]) and plugged in through `Config::price_lists`. All fields are required.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PriceList {
     /// Compute gas charge multiplier
     // * This multiplier is not currently applied to anything, but is matching lotus.
@@ -226,6 +319,36 @@ pub struct PriceList {
     /// Note: this partially refunds the create cost to incentivise the deletion of the actors.
     pub(crate) delete_actor: i64,
 
+    /// Gas cost (Base + len*PerByte) for installing user-deployed actor code. This covers
+    /// validating and (later) compiling the code.
+    ///
+    /// Both scale with the size of the code, and are priced conservatively: the per-byte cost
+    /// assumes a throughput of 1MB/s (at 10 gas per nanosecond, the scale of the signature
+    /// verification prices), well below what wasmparser and cranelift achieve on a single core.
+    /// The base cost covers the fixed overhead, and is priced like creating an actor.
+    pub(crate) install_actor_base: i64,
+    pub(crate) install_actor_per_byte: i64,
+
+    /// Gas costs of the wasm instructions executed by user-deployed actors. Built-in actors are
+    /// not metered.
+    pub(crate) wasm_instruction_costs: WasmInstructionCosts,
+    /// Gas cost charged per wasm page (64KiB) of linear memory allocated by user-deployed actors,
+    /// including their initial memory.
+    pub(crate) wasm_memory_page_cost: i64,
+
+    /// Gas cost charged per byte of block data held in the kernel's block registry (opened or
+    /// created blocks) by user-deployed actors.
+    ///
+    /// Built-in actors are exempt from this charge and `block_read_per_byte`, as they are from
+    /// wasm instruction and memory charges: their gas usage must match the schedule implemented
+    /// by other Filecoin clients (e.g., Lotus), which has no such charges. Their block I/O is
+    /// covered by the IPLD get and put charges instead. Charging them would be a consensus change,
+    /// requiring a new network version.
+    pub(crate) block_allocate_per_byte: i64,
+    /// Gas cost charged per byte of block data read by user-deployed actors. Built-in actors are
+    /// exempt (see `block_allocate_per_byte`).
+    pub(crate) block_read_per_byte: i64,
+
     /// Gas cost for verifying bls signature
     pub(crate) bls_sig_cost: i64,
     /// Gas cost for verifying secp256k1 signature
@@ -237,13 +360,23 @@ pub struct PriceList {
     pub(crate) verify_seal_base: i64,
     #[allow(unused)]
     pub(crate) verify_aggregate_seal_base: i64,
+    #[serde(with = "pairs")]
     pub(crate) verify_aggregate_seal_per: AHashMap<RegisteredSealProof, i64>,
+    #[serde(with = "pairs")]
     pub(crate) verify_aggregate_seal_steps: AHashMap<RegisteredSealProof, StepCost>,
 
+    #[serde(with = "pairs")]
     pub(crate) verify_post_lookup: AHashMap<RegisteredPoStProof, ScalingCost>,
     pub(crate) verify_post_discount: bool,
     pub(crate) verify_consensus_fault: i64,
     pub(crate) verify_replica_update: i64,
+
+    /// Gas cost for looking up a tipset CID (through the node).
+    ///
+    /// Nodes resolve tipsets by epoch from an index over their chain store, so a lookup is priced
+    /// like reading a block from the IPLD store (`ipld_get_base`), however far back the epoch is.
+    /// Nodes without such an index must bound the cost of lookups themselves.
+    pub(crate) tipset_cid_lookup: i64,
 }
 
 impl PriceList {
@@ -318,6 +451,42 @@ impl PriceList {
             self.delete_actor * self.storage_gas_multiplier,
         )
     }
+    /// Returns the gas required for installing actor code.
+    #[inline]
+    pub fn on_install_actor(&self, code_size: usize) -> GasCharge<'static> {
+        GasCharge::new(
+            "OnInstallActor",
+            self.install_actor_base + code_size as i64 * self.install_actor_per_byte,
+            0,
+        )
+    }
+    /// Returns the gas required for growing a user-deployed actor's linear memory.
+    #[inline]
+    pub fn on_memory_grow(&self, pages: usize) -> GasCharge<'static> {
+        GasCharge::new(
+            "OnMemoryGrow",
+            (pages as i64).saturating_mul(self.wasm_memory_page_cost),
+            0,
+        )
+    }
+    /// Returns the gas required for holding block data in the block registry.
+    #[inline]
+    pub fn on_block_allocate(&self, data_size: usize) -> GasCharge<'static> {
+        GasCharge::new(
+            "OnBlockAllocate",
+            data_size as i64 * self.block_allocate_per_byte,
+            0,
+        )
+    }
+    /// Returns the gas required for reading block data.
+    #[inline]
+    pub fn on_block_read(&self, data_size: usize) -> GasCharge<'static> {
+        GasCharge::new(
+            "OnBlockRead",
+            data_size as i64 * self.block_read_per_byte,
+            0,
+        )
+    }
     /// Returns gas required for signature verification.
     #[inline]
     pub fn on_verify_signature(&self, sig_type: SignatureType) -> GasCharge<'static> {
@@ -416,9 +585,87 @@ impl PriceList {
     pub fn on_verify_consensus_fault(&self) -> GasCharge<'static> {
         GasCharge::new("OnVerifyConsensusFault", self.verify_consensus_fault, 0)
     }
+    /// Returns gas required for looking up a tipset CID.
+    #[inline]
+    pub fn on_tipset_cid(&self) -> GasCharge<'static> {
+        GasCharge::new("OnTipsetCid", self.tipset_cid_lookup, 0)
+    }
+}
+
+impl Cbor for PriceList {}
+
+/// Returns the built-in gas price list for the given network version, if any.
+pub fn price_list_by_network_version(
+    network_version: NetworkVersion,
+) -> Option<&'static PriceList> {
+    match network_version {
+        // The gas schedule hasn't changed since V14. Networks with a different schedule can
+        // provide it through `Config::price_lists`.
+        NetworkVersion::V14 | NetworkVersion::V15 | NetworkVersion::V16 | NetworkVersion::V17 => {
+            Some(&CALICO_PRICES)
+        }
+        _ => None,
+    }
 }
 
-/// Returns gas price list by Epoch for gas consumption.
-pub fn price_list_by_epoch(_: ChainEpoch) -> PriceList {
-    CALICO_PRICES.clone()
+/// Loads a CBOR-encoded gas schedule: a list of `(network version, price list)` pairs.
+///
+/// Any other serde format (e.g., JSON) can be used by deserializing the same structure directly.
+pub fn load_price_lists(bytes: &[u8]) -> anyhow::Result<BTreeMap<NetworkVersion, PriceList>> {
+    let lists: Vec<(NetworkVersion, PriceList)> = from_slice(bytes)?;
+    Ok(lists.into_iter().collect())
+}
+
+/// (De)serializes maps as lists of `(key, value)` pairs, because DAG-CBOR only allows string keys.
+mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_shared::encoding::to_vec;
+
+    use super::*;
+
+    #[test]
+    fn price_list_round_trip() {
+        let prices = price_list_by_network_version(NetworkVersion::V15).unwrap();
+        let bytes = prices.marshal_cbor().unwrap();
+        assert_eq!(&PriceList::unmarshal_cbor(&bytes).unwrap(), prices);
+    }
+
+    #[test]
+    fn load_schedule() {
+        let mut modified = CALICO_PRICES.clone();
+        modified.send_base += 1;
+        let schedule = vec![
+            (NetworkVersion::V14, CALICO_PRICES.clone()),
+            (NetworkVersion::V15, modified.clone()),
+        ];
+        let lists = load_price_lists(&to_vec(&schedule).unwrap()).unwrap();
+        assert_eq!(lists[&NetworkVersion::V14], *CALICO_PRICES);
+        assert_eq!(lists[&NetworkVersion::V15], modified);
+    }
 }
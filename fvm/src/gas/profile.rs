@@ -0,0 +1,129 @@
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::BTreeMap;
+
+use fvm_shared::{ActorID, MethodNum};
+
+use super::GasCharge;
+
+/// Aggregated gas charges.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GasTotals {
+    /// The number of charges.
+    pub count: u64,
+    /// The total compute gas charged.
+    pub compute_gas: i64,
+    /// The total storage gas charged.
+    pub storage_gas: i64,
+}
+
+impl GasTotals {
+    /// Returns the total compute and storage gas charged.
+    pub fn total(&self) -> i64 {
+        self.compute_gas + self.storage_gas
+    }
+
+    fn add(&mut self, charge: &GasCharge) {
+        self.count += 1;
+        self.compute_gas += charge.compute_gas;
+        self.storage_gas += charge.storage_gas;
+    }
+}
+
+/// A breakdown of the gas charged while applying a message.
+///
+/// Gas profiles are only recorded when [`Config::gas_profiling`](crate::Config::gas_profiling)
+/// is enabled. Every charge is recorded, including a charge that ran out of gas.
+#[derive(Debug, Default, Clone)]
+pub struct GasProfile {
+    /// All charges.
+    pub total: GasTotals,
+    /// Charges aggregated by [`GasCharge::name`].
+    pub by_name: BTreeMap<String, GasTotals>,
+    /// Charges aggregated by the actor executing at the time of the charge. Charges made outside
+    /// of any actor invocation (e.g., message inclusion) are not attributed to an actor.
+    pub by_actor: BTreeMap<ActorID, GasTotals>,
+    /// Charges aggregated by the actor and method executing at the time of the charge.
+    pub by_method: BTreeMap<(ActorID, MethodNum), GasTotals>,
+}
+
+/// Builds a [`GasProfile`], keeping track of the actor currently being invoked.
+#[derive(Default)]
+pub(crate) struct GasProfiler {
+    profile: GasProfile,
+    /// The actors/methods currently being invoked, innermost last.
+    stack: Vec<(ActorID, MethodNum)>,
+}
+
+impl GasProfiler {
+    /// Record a gas charge against the current invocation.
+    pub fn record(&mut self, charge: &GasCharge) {
+        let profile = &mut self.profile;
+        profile.total.add(charge);
+        match profile.by_name.get_mut(charge.name) {
+            Some(totals) => totals.add(charge),
+            None => profile
+                .by_name
+                .entry(charge.name.to_owned())
+                .or_default()
+                .add(charge),
+        }
+        if let Some(&(actor, method)) = self.stack.last() {
+            profile.by_actor.entry(actor).or_default().add(charge);
+            profile
+                .by_method
+                .entry((actor, method))
+                .or_default()
+                .add(charge);
+        }
+    }
+
+    /// Enter an actor invocation. Charges will be attributed to this actor and method until the
+    /// matching `exit_call`.
+    pub fn enter_call(&mut self, actor: ActorID, method: MethodNum) {
+        self.stack.push((actor, method))
+    }
+
+    /// Exit the current actor invocation.
+    pub fn exit_call(&mut self) {
+        self.stack.pop();
+    }
+
+    /// Finish profiling, returning the profile.
+    pub fn finish(self) -> GasProfile {
+        debug_assert!(self.stack.is_empty(), "unbalanced calls in gas profile");
+        self.profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_charges() {
+        let mut profiler = GasProfiler::default();
+        profiler.record(&GasCharge::new("OnChainMessage", 10, 5));
+        profiler.enter_call(100, 2);
+        profiler.record(&GasCharge::new("OnIpldGet", 3, 0));
+        profiler.enter_call(101, 3);
+        profiler.record(&GasCharge::new("OnIpldGet", 3, 0));
+        profiler.record(&GasCharge::new("OnIpldPut", 1, 7));
+        profiler.exit_call();
+        profiler.exit_call();
+
+        let profile = profiler.finish();
+        assert_eq!(profile.total.total(), 29);
+        assert_eq!(profile.total.count, 4);
+
+        let get = profile.by_name["OnIpldGet"];
+        assert_eq!((get.count, get.compute_gas, get.storage_gas), (2, 6, 0));
+
+        // The inclusion charge isn't attributed to any actor.
+        assert_eq!(profile.by_actor.len(), 2);
+        assert_eq!(profile.by_actor[&100].total(), 3);
+        assert_eq!(profile.by_method[&(101, 3)].total(), 11);
+        assert_eq!(profile.by_method[&(101, 3)].storage_gas, 7);
+    }
+}
@@ -1,8 +1,10 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
+use std::io::Cursor;
 
 use anyhow::{anyhow, Context as _};
 use byteorder::{BigEndian, WriteBytesExt};
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 use filecoin_proofs_api::seal::{
     compute_comm_d, verify_aggregate_seal_commit_proofs, verify_seal as proofs_verify_seal,
@@ -18,21 +20,24 @@ use fvm_shared::commcid::{
 };
 use fvm_shared::consensus::ConsensusFault;
 use fvm_shared::econ::TokenAmount;
-use fvm_shared::encoding::{blake2b_256, bytes_32, to_vec, RawBytes};
+use fvm_shared::encoding::{blake2b_256, bytes_32, to_vec, RawBytes, DAG_CBOR};
 use fvm_shared::error::ErrorNumber;
 use fvm_shared::piece::{zero_piece_commitment, PaddedPieceSize};
-use fvm_shared::sector::SectorInfo;
+use fvm_shared::sector::{RegisteredPoStProof, RegisteredUpdateProof, SectorInfo};
 use fvm_shared::version::NetworkVersion;
-use fvm_shared::{ActorID, FILECOIN_PRECISION};
+use fvm_shared::{ActorID, FILECOIN_PRECISION, IPLD_RAW};
 use lazy_static::lazy_static;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use super::blocks::{Block, BlockRegistry};
 use super::error::Result;
 use super::*;
-use crate::call_manager::{CallManager, InvocationResult};
+use crate::blockstore::scan_for_links;
+use crate::call_manager::{
+    CallManager, ExecutionEvent, ExecutionLimiter, InvocationResult, NO_DATA_BLOCK_ID,
+};
 use crate::externs::{Consensus, Rand};
-use crate::gas::GasCharge;
+use crate::gas::{GasCharge, PriceList};
 use crate::market_actor::State as MarketActorState;
 use crate::power_actor::State as PowerActorState;
 use crate::reward_actor::State as RewardActorState;
@@ -48,8 +53,6 @@ lazy_static! {
 }
 
 /// Tracks data accessed and modified during the execution of a message.
-///
-/// TODO writes probably ought to be scoped by invocation container.
 pub struct DefaultKernel<C> {
     // Fields extracted from the message, except parameters, which have been
     // preloaded into the block registry.
@@ -63,9 +66,17 @@ pub struct DefaultKernel<C> {
     call_manager: C,
     /// Tracks block data and organizes it through index handles so it can be
     /// referred to.
-    ///
-    /// This does not yet reason about reachability.
     blocks: BlockRegistry,
+    /// The CIDs this actor may open, other than its state root: those of blocks it linked during
+    /// this invocation, and those linked from blocks it opened. Only tracked for user-deployed
+    /// actors; built-in actors are trusted to open any block.
+    reachable: HashSet<Cid>,
+    /// Blocks linked during this invocation. They're only written to the blockstore once they're
+    /// reachable from outside the invocation, and are dropped if the invocation aborts.
+    write_buffer: HashMap<Cid, Block>,
+    /// Whether this actor is user-deployed, and therefore pays for the resources (block data,
+    /// etc.) it uses. Built-in actors follow the legacy gas model.
+    metered: bool,
 }
 
 // Even though all children traits are implemented, Rust needs to know that the
@@ -89,14 +100,18 @@ where
         to: ActorID,
         method: MethodNum,
         value_received: TokenAmount,
+        metered: bool,
     ) -> Self {
         DefaultKernel {
             call_manager: mgr,
             blocks: BlockRegistry::new(),
+            reachable: HashSet::new(),
+            write_buffer: HashMap::new(),
             caller: from,
             actor_id: to,
             method,
             value_received,
+            metered,
         }
     }
 }
@@ -105,6 +120,103 @@ impl<C> DefaultKernel<C>
 where
     C: CallManager,
 {
+    /// Charges gas for resources used by user-deployed actors. This is a no-op for built-in actors.
+    fn charge_metered(&mut self, charge: GasCharge) -> Result<()> {
+        if self.metered {
+            self.call_manager.charge_gas(charge)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns whether this actor may open (or link to) the block with the given CID.
+    fn is_reachable(&self, cid: &Cid) -> Result<bool> {
+        if !self.metered || self.reachable.contains(cid) {
+            return Ok(true);
+        }
+        // The actor may have deleted itself.
+        Ok(self.get_self()?.map_or(false, |actor| actor.state == *cid))
+    }
+
+    /// Writes the buffered blocks reachable from `roots` to the blockstore, removing them from the
+    /// write buffer. Links to blocks that aren't buffered aren't followed: they were either
+    /// already written, or were never created by this invocation.
+    fn flush_reachable(&mut self, mut roots: Vec<Cid>) -> Result<()> {
+        let mut blocks = Vec::new();
+        while let Some(cid) = roots.pop() {
+            if let Some(block) = self.write_buffer.remove(&cid) {
+                scan_links(block.codec(), block.data(), &mut roots)
+                    .context(format_args!("failed to scan block {} for links", cid))?;
+                blocks.push((cid, block));
+            }
+        }
+        self.call_manager
+            .blockstore()
+            .put_many_keyed(blocks.iter().map(|(k, b)| (*k, b.data())))
+            .or_fatal()
+    }
+
+    /// Checks that the network supports the given seal proof type.
+    fn check_seal_proof(&self, proof: RegisteredSealProof) -> Result<()> {
+        if !self
+            .call_manager
+            .context()
+            .network
+            .seal_proofs
+            .contains(&proof)
+        {
+            return Err(
+                syscall_error!(IllegalArgument; "unsupported seal proof type {:?}", proof).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that the network supports the given window PoSt proof type.
+    fn check_post_proof(&self, proof: RegisteredPoStProof) -> Result<()> {
+        if !self
+            .call_manager
+            .context()
+            .network
+            .post_proofs
+            .contains(&proof)
+        {
+            return Err(
+                syscall_error!(IllegalArgument; "unsupported post proof type {:?}", proof).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that the network supports the given replica update proof type.
+    fn check_update_proof(&self, proof: RegisteredUpdateProof) -> Result<()> {
+        if !self
+            .call_manager
+            .context()
+            .network
+            .update_proofs
+            .contains(&proof)
+        {
+            return Err(syscall_error!(IllegalArgument;
+                "unsupported replica update proof type {:?}", proof)
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Loads user-deployed actor code (raw wasm bytecode) from the blockstore.
+    fn load_actor_code(&self, code_cid: &Cid) -> Result<Vec<u8>> {
+        if code_cid.codec() != IPLD_RAW {
+            return Err(syscall_error!(IllegalArgument;
+                "actor code {} must be a raw block", code_cid)
+            .into());
+        }
+        match self.call_manager.blockstore().get(code_cid).or_fatal()? {
+            Some(wasm) => Ok(wasm),
+            None => Err(syscall_error!(NotFound; "actor code {} not found", code_cid).into()),
+        }
+    }
+
     fn resolve_to_key_addr(&mut self, addr: &Address, charge_gas: bool) -> Result<Address> {
         if addr.protocol() == Protocol::BLS || addr.protocol() == Protocol::Secp256k1 {
             return Ok(*addr);
@@ -229,6 +341,11 @@ where
     }
 
     fn set_root(&mut self, new: Cid) -> Result<()> {
+        if !self.is_reachable(&new)? {
+            return Err(
+                syscall_error!(IllegalArgument; "new root {} is not reachable", new).into(),
+            );
+        }
         self.mutate_self(|actor_state| {
             actor_state.state = new;
             Ok(())
@@ -284,19 +401,43 @@ where
         self.call_manager
             .charge_gas(self.call_manager.price_list().on_ipld_get())?;
 
-        let data = self
-            .call_manager
-            .blockstore()
-            .get(cid)
-            .or_fatal()?
-            .ok_or_else(|| anyhow!("missing state: {}", cid))
-            // Missing state is a fatal error because it means we have a bug. Once we do
-            // reachability checking (for user actors) we won't get here unless the block is known
-            // to be in the state-tree.
-            .or_fatal()?;
+        if !self.is_reachable(cid)? {
+            return Err(syscall_error!(NotFound; "block {} is not reachable", cid).into());
+        }
+
+        // Blocks linked during this invocation may not have been written yet.
+        let block = match self.write_buffer.get(cid) {
+            Some(block) => block.clone(),
+            None => {
+                let data = self
+                    .call_manager
+                    .blockstore()
+                    .get(cid)
+                    .or_fatal()?
+                    .ok_or_else(|| anyhow!("missing state: {}", cid))
+                    // Missing state is a fatal error because it means we have a bug: user-deployed
+                    // actors can only open reachable blocks, and built-in actors are trusted.
+                    .or_fatal()?;
+                Block::new(cid.codec(), data)
+            }
+        };
+
+        // User-deployed actors also pay for holding the block in the registry.
+        self.charge_metered(
+            self.call_manager
+                .price_list()
+                .on_block_allocate(block.size() as usize),
+        )?;
+
+        // Its children are now reachable too.
+        if self.metered {
+            let mut links = Vec::new();
+            scan_links(block.codec(), block.data(), &mut links)
+                .context(format_args!("failed to scan block {} for links", cid))?;
+            self.reachable.extend(links);
+        }
 
         // We charge on open, not read, to emulate the current gas model.
-        let block = Block::new(cid.codec(), data);
         let stat = block.stat();
 
         // TODO: I mean, this means you put 4M blocks in a single message. That's not actually possible?
@@ -305,6 +446,7 @@ where
     }
 
     fn block_create(&mut self, codec: u64, data: &[u8]) -> Result<BlockId> {
+        self.charge_metered(self.call_manager.price_list().on_block_allocate(data.len()))?;
         self.blocks
             .put(Block::new(codec, data))
             .or_illegal_argument()
@@ -333,29 +475,63 @@ where
             );
         }
         let k = Cid::new_v1(block.codec(), hash.truncate(hash_len as u8));
-        // TODO: for now, we _put_ the block here. In the future, we should put it into a write
-        // cache, then flush it later.
-        self.call_manager
-            .blockstore()
-            .put_keyed(&k, block.data())
-            .or_fatal()?;
+
+        // The new block becomes reachable, so it may only link to reachable blocks. Otherwise,
+        // actors could open arbitrary blocks by linking to them.
+        if self.metered {
+            let mut links = Vec::new();
+            scan_links(block.codec(), block.data(), &mut links)
+                .context("failed to scan block for links")?;
+            // The actor may have deleted itself.
+            let root = self.get_self()?.map(|actor| actor.state);
+            for link in &links {
+                if !self.reachable.contains(link) && root.as_ref() != Some(link) {
+                    return Err(syscall_error!(IllegalArgument;
+                        "block links to unreachable block {}", link)
+                    .into());
+                }
+            }
+            self.reachable.insert(k);
+        }
+
+        // The block is only written to the blockstore if it's reachable when the invocation ends.
+        self.write_buffer.insert(k, block.clone());
         Ok(k)
     }
 
-    fn block_read(&self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32> {
+    fn block_read(&mut self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32> {
+        let size = self.blocks.get(id).or_illegal_argument()?.size() as usize;
+        let len = buf.len().min(size.saturating_sub(offset as usize));
+        self.charge_metered(self.call_manager.price_list().on_block_read(len))?;
+
         let data = self.blocks.get(id).or_illegal_argument()?.data();
-        Ok(if offset as usize >= data.len() {
-            0
-        } else {
-            let len = buf.len().min(data.len());
-            buf.copy_from_slice(&data[offset as usize..][..len]);
-            len as u32
-        })
+        if len > 0 {
+            buf[..len].copy_from_slice(&data[offset as usize..][..len]);
+        }
+        Ok(len as u32)
     }
 
     fn block_stat(&self, id: BlockId) -> Result<BlockStat> {
         self.blocks.stat(id).or_illegal_argument()
     }
+
+    fn block_flush(&mut self, ret: BlockId) -> Result<()> {
+        let mut roots = Vec::new();
+        // The actor may have deleted itself.
+        if let Some(actor) = self.get_self()? {
+            roots.push(actor.state);
+        }
+        if ret != NO_DATA_BLOCK_ID {
+            let block = self.blocks.get(ret).or_illegal_argument()?;
+            scan_links(block.codec(), block.data(), &mut roots)
+                .context("failed to scan return value for links")?;
+        }
+        self.flush_reachable(roots)?;
+
+        // Anything else is garbage.
+        self.write_buffer.clear();
+        Ok(())
+    }
 }
 
 impl<C> MessageOps for DefaultKernel<C>
@@ -390,6 +566,22 @@ where
         params: &RawBytes,
         value: &TokenAmount,
     ) -> Result<InvocationResult> {
+        // The callee may re-enter this actor and open its state, or open blocks linked from the
+        // parameters, so those must be written first.
+        let mut roots = Vec::new();
+        if let Some(actor) = self.get_self()? {
+            roots.push(actor.state);
+        }
+        match scan_links(DAG_CBOR, params, &mut roots) {
+            Ok(()) => {}
+            // Parameters aren't required to be DAG-CBOR. If they aren't, the callee can't decode
+            // any links from them either, so there's nothing more to write (beyond any links found
+            // before the invalid data).
+            Err(ExecutionError::Syscall(_)) => {}
+            Err(err) => return Err(err),
+        }
+        self.flush_reachable(roots)?;
+
         let from = self.actor_id;
         self.call_manager
             .with_transaction(|cm| cm.send::<Self>(from, *recipient, method, params, value))
@@ -401,6 +593,16 @@ where
     C: CallManager,
 {
     fn total_fil_circ_supply(&self) -> Result<TokenAmount> {
+        // The node may provide the circulating supply directly, for any network version.
+        if let Some(chain) = self.call_manager.externs().chain() {
+            if let Some(circ_supply) = chain
+                .total_fil_circ_supply(self.network_epoch())
+                .or_fatal()?
+            {
+                return Ok(circ_supply);
+            }
+        }
+
         let circ_supply = if self.network_version() <= NetworkVersion::V14 {
             // Pre-v15 the circ supply was dynamically calculated on the Filecoin mainnet,
             // meaning it fluctuated within an epoch (as messages were executed). This forced the FVM
@@ -508,12 +710,16 @@ where
     fn verify_seal(&mut self, vi: &SealVerifyInfo) -> Result<bool> {
         self.call_manager
             .charge_gas(self.call_manager.price_list().on_verify_seal(vi))?;
+        self.check_seal_proof(vi.registered_proof)?;
         verify_seal(vi)
     }
 
     fn verify_post(&mut self, verify_info: &WindowPoStVerifyInfo) -> Result<bool> {
         self.call_manager
             .charge_gas(self.call_manager.price_list().on_verify_post(verify_info))?;
+        for proof in &verify_info.proofs {
+            self.check_post_proof(proof.post_proof)?;
+        }
 
         let WindowPoStVerifyInfo {
             ref proofs,
@@ -570,10 +776,19 @@ where
         // NOTE: gas has already been charged by the power actor when the batch verify was enqueued.
         // Lotus charges "virtual" gas here for tracing only.
         log::debug!("batch verify seals start");
+        let supported = &self.call_manager.context().network.seal_proofs;
         let out = vis
             .par_iter()
             .with_min_len(vis.len() / *NUM_CPUS)
             .map(|seal| {
+                if !supported.contains(&seal.registered_proof) {
+                    log::debug!(
+                        "seal verify in batch failed (miner: {}) (err: unsupported proof type {:?})",
+                        seal.sector_id.miner,
+                        seal.registered_proof
+                    );
+                    return false;
+                }
                 let verify_seal_result = std::panic::catch_unwind(|| verify_seal(seal));
                 match verify_seal_result {
                     Ok(res) => {
@@ -620,6 +835,7 @@ where
         if aggregate.infos.is_empty() {
             return Err(syscall_error!(IllegalArgument; "no seal verify infos").into());
         }
+        self.check_seal_proof(aggregate.seal_proof)?;
         let spt: proofs::RegisteredSealProof =
             aggregate.seal_proof.try_into().or_illegal_argument()?;
         let prover_id = prover_id_from_u64(aggregate.miner);
@@ -688,6 +904,7 @@ where
                 .price_list()
                 .on_verify_replica_update(replica),
         )?;
+        self.check_update_proof(replica.update_proof_type)?;
 
         let up: proofs::RegisteredUpdateProof =
             replica.update_proof_type.try_into().or_illegal_argument()?;
@@ -711,6 +928,19 @@ where
         let charge = GasCharge::new(name, compute, 0);
         self.call_manager.charge_gas(charge)
     }
+
+    fn price_list(&self) -> &PriceList {
+        self.call_manager.price_list()
+    }
+}
+
+impl<C> LimiterOps for DefaultKernel<C>
+where
+    C: CallManager,
+{
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter {
+        self.call_manager.limiter_mut()
+    }
 }
 
 impl<C> NetworkOps for DefaultKernel<C>
@@ -728,6 +958,24 @@ where
     fn network_base_fee(&self) -> &TokenAmount {
         &self.call_manager.context().base_fee
     }
+
+    fn tipset_cid(&mut self, epoch: ChainEpoch) -> Result<Option<Cid>> {
+        if epoch < 0 || epoch >= self.network_epoch() {
+            return Err(syscall_error!(IllegalArgument;
+                "tipset epoch {} must be between 0 and the current epoch ({})",
+                epoch, self.network_epoch()
+            )
+            .into());
+        }
+
+        // The lookup is resolved by the node, so charge before we call out.
+        self.call_manager
+            .charge_gas(self.call_manager.price_list().on_tipset_cid())?;
+        match self.call_manager.externs().chain() {
+            Some(chain) => chain.get_tipset_cid(epoch).or_fatal(),
+            None => Ok(None),
+        }
+    }
 }
 
 impl<C> RandomnessOps for DefaultKernel<C>
@@ -803,16 +1051,32 @@ where
 
     // TODO merge new_actor_address and create_actor into a single syscall.
     fn create_actor(&mut self, code_id: Cid, actor_id: ActorID) -> Result<()> {
-        let typ = self
-            .resolve_builtin_actor_type(&code_id)
-            .ok_or_else(|| syscall_error!(IllegalArgument; "can only create built-in actors"))?;
-
-        if typ.is_singleton_actor() {
-            return Err(
-                syscall_error!(IllegalArgument; "can only have one instance of singleton actors")
-                    .into(),
-            );
-        };
+        match self.resolve_builtin_actor_type(&code_id) {
+            Some(typ) if typ.is_singleton_actor() => {
+                return Err(syscall_error!(IllegalArgument;
+                    "can only have one instance of singleton actors")
+                .into());
+            }
+            Some(_) => {}
+            // User-deployed code must have been installed. We can't rely on the engine having
+            // validated it already (e.g., after a restart), so this is charged like installing it.
+            None => {
+                let wasm = self.load_actor_code(&code_id)?;
+                self.call_manager
+                    .charge_gas(self.call_manager.price_list().on_install_actor(wasm.len()))?;
+                self.call_manager
+                    .machine()
+                    .engine()
+                    .validate_bytecode(
+                        &code_id,
+                        &wasm,
+                        self.call_manager.machine().config().max_pages,
+                        self.call_manager.machine().config().max_table_elements,
+                    )
+                    .context("invalid actor code")
+                    .or_illegal_argument()?;
+            }
+        }
 
         let state_tree = self.call_manager.state_tree();
         if let Ok(Some(_)) = state_tree.get_actor_id(actor_id) {
@@ -829,6 +1093,44 @@ where
         )
     }
 
+    fn install_actor(&mut self, code: &[u8]) -> Result<Cid> {
+        let caller_is_singleton = self
+            .call_manager
+            .state_tree()
+            .get_actor_id(self.actor_id)?
+            .and_then(|act| self.resolve_builtin_actor_type(&act.code))
+            .map(|typ| typ.is_singleton_actor())
+            .unwrap_or(false);
+        if !caller_is_singleton {
+            return Err(syscall_error!(Forbidden;
+                "actor {} may not install actor code", self.actor_id)
+            .into());
+        }
+
+        self.call_manager
+            .charge_gas(self.call_manager.price_list().on_install_actor(code.len()))?;
+
+        let code_cid = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(code));
+        self.call_manager
+            .machine()
+            .engine()
+            .validate_bytecode(
+                &code_cid,
+                code,
+                self.call_manager.machine().config().max_pages,
+                self.call_manager.machine().config().max_table_elements,
+            )
+            .context("invalid actor code")
+            .or_illegal_argument()?;
+
+        self.call_manager
+            .blockstore()
+            .put_keyed(&code_cid, code)
+            .context("failed to write actor code")
+            .or_fatal()?;
+        Ok(code_cid)
+    }
+
     fn resolve_builtin_actor_type(&self, code_cid: &Cid) -> Option<actor::builtin::Type> {
         self.call_manager
             .machine()
@@ -859,6 +1161,10 @@ where
     fn debug_enabled(&self) -> bool {
         self.call_manager.context().debug
     }
+
+    fn trace(&mut self, event: ExecutionEvent) {
+        self.call_manager.trace(event)
+    }
 }
 
 /// PoSt proof variants.
@@ -868,6 +1174,18 @@ enum ProofType {
     Window,
 }
 
+/// Appends the CIDs linked from a block to `links`. Only DAG-CBOR blocks can contain links.
+fn scan_links(codec: u64, data: &[u8], links: &mut Vec<Cid>) -> Result<()> {
+    if codec != DAG_CBOR || data.is_empty() {
+        return Ok(());
+    }
+    scan_for_links(&mut Cursor::new(data), |cid| {
+        links.push(cid);
+        Ok(())
+    })
+    .or_illegal_argument()
+}
+
 fn prover_id_from_u64(id: u64) -> ProverId {
     let mut prover_id = ProverId::default();
     let prover_bytes = Address::new_id(id).payload().to_raw_bytes();
@@ -943,3 +1261,308 @@ fn verify_seal(vi: &SealVerifyInfo) -> Result<bool> {
     // Worst case, _some_ node falls out of sync. Better than the network halting.
     .context("failed to verify seal proof")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use fvm_shared::address::Address;
+    use fvm_shared::METHOD_SEND;
+    use wasmtime::Store;
+
+    use super::*;
+    use crate::syscalls::InvocationData;
+    use crate::test_utils::*;
+
+    const ACTOR_ID: ActorID = 1000;
+
+    /// Creates a kernel for the given actor, invoked by [`SENDER_ID`] with the given gas limit. The
+    /// machine has a user-deployed actor, [`ACTOR_ID`], besides the actors created by
+    /// [`new_machine`]. Only that actor is metered.
+    fn new_kernel(actor_id: ActorID, gas_limit: i64) -> TestKernel {
+        let wasm = wasm(NOOP_ACTOR);
+        let machine = new_machine(test_config(), &[(ACTOR_ID, &wasm)]);
+        let cm = TestCallManager::new(machine, gas_limit, Address::new_id(SENDER_ID), 0);
+        let metered = actor_id == ACTOR_ID;
+        TestKernel::new(cm, SENDER_ID, actor_id, 2, Zero::zero(), metered)
+    }
+
+    /// Puts the kernel in a store, as if it were invoking an actor. This binds the syscalls, so
+    /// actor code can be validated.
+    fn new_store(kernel: TestKernel) -> Store<InvocationData<TestKernel>> {
+        let engine = kernel.call_manager.machine().engine().clone();
+        engine.new_store(kernel).map_err(|(e, _)| e).unwrap()
+    }
+
+    fn gas_used(kernel: &TestKernel) -> i64 {
+        kernel.call_manager.gas_tracker().gas_used()
+    }
+
+    fn assert_syscall_error<T: Debug>(res: Result<T>, code: ErrorNumber) {
+        match res {
+            Err(ExecutionError::Syscall(e)) => assert_eq!(e.1, code),
+            res => panic!("expected {:?}, got {:?}", code, res),
+        }
+    }
+
+    #[test]
+    fn tipset_cid_is_charged() {
+        let mut kernel = new_kernel(ACTOR_ID, GAS_LIMIT);
+        let charge = kernel.price_list().on_tipset_cid().total();
+        assert!(charge > 0);
+
+        assert_eq!(
+            kernel.tipset_cid(EPOCH - 1).unwrap(),
+            Some(tipset_cid(EPOCH - 1))
+        );
+        assert_eq!(gas_used(&kernel), charge);
+
+        // Invalid epochs aren't looked up, or charged for.
+        for epoch in [-1, EPOCH] {
+            assert_syscall_error(kernel.tipset_cid(epoch), ErrorNumber::IllegalArgument);
+        }
+        assert_eq!(gas_used(&kernel), charge);
+    }
+
+    #[test]
+    fn tipset_cid_out_of_gas() {
+        let mut kernel = new_kernel(ACTOR_ID, 1);
+        assert!(matches!(
+            kernel.tipset_cid(EPOCH - 1),
+            Err(ExecutionError::OutOfGas)
+        ));
+    }
+
+    #[test]
+    fn install_actor() {
+        let mut store = new_store(new_kernel(INIT_ACTOR_ID, GAS_LIMIT));
+        let kernel = &mut store.data_mut().kernel;
+        let code = wasm(NOOP_ACTOR);
+        let charge = kernel.price_list().on_install_actor(code.len()).total();
+
+        let code_cid = kernel.install_actor(&code).unwrap();
+        assert_eq!(code_cid, crate::test_utils::code_cid(&code));
+        assert_eq!(
+            kernel.call_manager.blockstore().get(&code_cid).unwrap(),
+            Some(code)
+        );
+        assert_eq!(gas_used(kernel), charge);
+
+        // Invalid code is charged for, but not installed.
+        let invalid = wasm("(module)");
+        let charge = charge + kernel.price_list().on_install_actor(invalid.len()).total();
+        assert_syscall_error(kernel.install_actor(&invalid), ErrorNumber::IllegalArgument);
+        assert!(!kernel
+            .call_manager
+            .blockstore()
+            .has(&crate::test_utils::code_cid(&invalid))
+            .unwrap());
+        assert_eq!(gas_used(kernel), charge);
+    }
+
+    #[test]
+    fn install_actor_requires_singleton() {
+        let mut store = new_store(new_kernel(ACTOR_ID, GAS_LIMIT));
+        let kernel = &mut store.data_mut().kernel;
+        assert_syscall_error(
+            kernel.install_actor(&wasm(NOOP_ACTOR)),
+            ErrorNumber::Forbidden,
+        );
+        assert_eq!(gas_used(kernel), 0);
+    }
+
+    #[test]
+    fn create_actor_from_user_code() {
+        let mut store = new_store(new_kernel(INIT_ACTOR_ID, GAS_LIMIT));
+        let kernel = &mut store.data_mut().kernel;
+        let code = wasm(NOOP_ACTOR);
+        let code_cid = kernel.install_actor(&code).unwrap();
+
+        // Creating an actor is charged like installing its code, even if it's been validated.
+        let used = gas_used(kernel);
+        kernel.create_actor(code_cid, 1001).unwrap();
+        let prices = kernel.price_list();
+        assert_eq!(
+            gas_used(kernel) - used,
+            prices.on_install_actor(code.len()).total() + prices.on_create_actor().total()
+        );
+        let actor = kernel
+            .call_manager
+            .state_tree()
+            .get_actor_id(1001)
+            .unwrap()
+            .unwrap();
+        assert_eq!(actor.code, code_cid);
+    }
+
+    #[test]
+    fn create_actor_rejects_unknown_or_invalid_code() {
+        let mut store = new_store(new_kernel(INIT_ACTOR_ID, GAS_LIMIT));
+        let kernel = &mut store.data_mut().kernel;
+
+        let missing = code_cid(b"missing");
+        assert_syscall_error(kernel.create_actor(missing, 1001), ErrorNumber::NotFound);
+
+        let invalid = wasm("(module)");
+        let invalid_cid = code_cid(&invalid);
+        kernel
+            .call_manager
+            .blockstore()
+            .put_keyed(&invalid_cid, &invalid)
+            .unwrap();
+        assert_syscall_error(
+            kernel.create_actor(invalid_cid, 1001),
+            ErrorNumber::IllegalArgument,
+        );
+
+        // Actor code must be a raw block.
+        let cbor = Cid::new_v1(DAG_CBOR, *invalid_cid.hash());
+        assert_syscall_error(
+            kernel.create_actor(cbor, 1001),
+            ErrorNumber::IllegalArgument,
+        );
+
+        assert!(kernel
+            .call_manager
+            .state_tree()
+            .get_actor_id(1001)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn block_registry_is_charged_to_user_actors() {
+        let data = [0x82, 1, 2];
+        let mut buf = [0u8; 3];
+        // The gas charged for creating, reading, and opening a block.
+        let charges = |actor_id| {
+            let mut kernel = new_kernel(actor_id, GAS_LIMIT);
+            let id = kernel.block_create(DAG_CBOR, &data).unwrap();
+            let created = gas_used(&kernel);
+            kernel.block_read(id, 1, &mut buf).unwrap();
+            let read = gas_used(&kernel) - created;
+            kernel.block_open(&EMPTY_ARR_CID).unwrap();
+            let opened = gas_used(&kernel) - created - read;
+            (created, read, opened)
+        };
+
+        let prices = new_kernel(ACTOR_ID, GAS_LIMIT).price_list().clone();
+        let (created, read, opened) = charges(ACTOR_ID);
+        assert_eq!(created, prices.on_block_allocate(3).total());
+        assert_eq!(read, prices.on_block_read(2).total());
+
+        // Built-in actors only pay for opening blocks, as before.
+        let (builtin_created, builtin_read, builtin_opened) = charges(INIT_ACTOR_ID);
+        assert_eq!((builtin_created, builtin_read), (0, 0));
+        assert_eq!(opened - builtin_opened, prices.on_block_allocate(1).total());
+    }
+
+    /// Creates and links a DAG-CBOR block, returning its CID.
+    fn link_block(kernel: &mut TestKernel, data: &[u8]) -> Cid {
+        let id = kernel.block_create(DAG_CBOR, data).unwrap();
+        kernel.block_link(id, 0xb220, 32).unwrap()
+    }
+
+    #[test]
+    fn block_flush_writes_reachable_blocks() {
+        let mut kernel = new_kernel(ACTOR_ID, GAS_LIMIT);
+        let state = link_block(&mut kernel, &[0x82, 1, 2]);
+        let returned = link_block(&mut kernel, &[0x82, 3, 4]);
+        let garbage = link_block(&mut kernel, &[0x82, 5, 6]);
+        kernel.set_root(state).unwrap();
+
+        // The return value isn't linked itself, but links to a block.
+        let ret = kernel
+            .block_create(DAG_CBOR, &to_vec(&returned).unwrap())
+            .unwrap();
+        kernel.block_flush(ret).unwrap();
+
+        let bs = kernel.call_manager.blockstore();
+        assert!(bs.has(&state).unwrap());
+        assert!(bs.has(&returned).unwrap());
+        assert!(!bs.has(&garbage).unwrap());
+    }
+
+    #[test]
+    fn send_writes_state_and_params() {
+        let mut kernel = new_kernel(ACTOR_ID, GAS_LIMIT);
+        let state = link_block(&mut kernel, &[0x82, 1, 2]);
+        let param = link_block(&mut kernel, &[0x82, 3, 4]);
+        let unsent = link_block(&mut kernel, &[0x82, 5, 6]);
+        kernel.set_root(state).unwrap();
+
+        let recipient = Address::new_id(2);
+        let params = RawBytes::new(to_vec(&param).unwrap());
+        kernel
+            .send(&recipient, METHOD_SEND, &params, &Zero::zero())
+            .unwrap();
+        let bs = kernel.call_manager.blockstore();
+        assert!(bs.has(&state).unwrap());
+        assert!(bs.has(&param).unwrap());
+        assert!(!bs.has(&unsent).unwrap());
+
+        // Parameters don't have to be DAG-CBOR.
+        let params = RawBytes::new(vec![0xff]);
+        kernel
+            .send(&recipient, METHOD_SEND, &params, &Zero::zero())
+            .unwrap();
+        assert!(!kernel.call_manager.blockstore().has(&unsent).unwrap());
+    }
+
+    /// Writes a DAG-CBOR block straight to the blockstore, returning its CID.
+    fn put_block(kernel: &TestKernel, data: &[u8]) -> Cid {
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(data));
+        kernel
+            .call_manager
+            .blockstore()
+            .put_keyed(&cid, data)
+            .unwrap();
+        cid
+    }
+
+    #[test]
+    fn block_open_requires_reachability() {
+        let mut kernel = new_kernel(ACTOR_ID, GAS_LIMIT);
+        let grandchild = put_block(&kernel, &[0x82, 1, 2]);
+        let other = put_block(&kernel, &[0x82, 3, 4]);
+        let child = put_block(&kernel, &to_vec(&(grandchild, 1)).unwrap());
+        let root = put_block(&kernel, &to_vec(&(child, 1)).unwrap());
+
+        // Arbitrary blocks can't be opened, even if they're in the blockstore.
+        assert_syscall_error(kernel.block_open(&other), ErrorNumber::NotFound);
+        assert_syscall_error(kernel.block_open(&root), ErrorNumber::NotFound);
+
+        // Nor linked to, or used as the state root.
+        let linking = kernel
+            .block_create(DAG_CBOR, &to_vec(&(other, 1)).unwrap())
+            .unwrap();
+        assert_syscall_error(
+            kernel.block_link(linking, 0xb220, 32),
+            ErrorNumber::IllegalArgument,
+        );
+        assert_syscall_error(kernel.set_root(other), ErrorNumber::IllegalArgument);
+
+        // The state root can be opened, then its children, but only once their parent is open.
+        let mut actor = kernel.get_self().unwrap().unwrap();
+        actor.state = root;
+        kernel
+            .call_manager
+            .state_tree_mut()
+            .set_actor_id(ACTOR_ID, actor)
+            .unwrap();
+        assert_syscall_error(kernel.block_open(&grandchild), ErrorNumber::NotFound);
+        kernel.block_open(&root).unwrap();
+        kernel.block_open(&child).unwrap();
+        kernel.block_open(&grandchild).unwrap();
+        assert_syscall_error(kernel.block_open(&other), ErrorNumber::NotFound);
+
+        // Built-in actors are trusted to open, and link to, any block.
+        let mut kernel = new_kernel(INIT_ACTOR_ID, GAS_LIMIT);
+        kernel.block_open(&other).unwrap();
+        let linking = kernel
+            .block_create(DAG_CBOR, &to_vec(&(other, 1)).unwrap())
+            .unwrap();
+        kernel.block_link(linking, 0xb220, 32).unwrap();
+        kernel.set_root(other).unwrap();
+    }
+}
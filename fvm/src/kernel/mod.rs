@@ -23,7 +23,8 @@ mod error;
 
 pub use error::{ClassifyResult, Context, ExecutionError, Result, SyscallError};
 
-use crate::call_manager::{CallManager, InvocationResult};
+use crate::call_manager::{CallManager, ExecutionEvent, ExecutionLimiter, InvocationResult};
+use crate::gas::PriceList;
 use crate::machine::Machine;
 
 pub trait Kernel:
@@ -33,6 +34,7 @@ pub trait Kernel:
     + CryptoOps
     + DebugOps
     + GasOps
+    + LimiterOps
     + MessageOps
     + NetworkOps
     + RandomnessOps
@@ -46,12 +48,15 @@ pub trait Kernel:
     where
         Self: Sized;
 
+    /// Creates a kernel for an invocation of `to` by `from`. `metered` is set for user-deployed
+    /// actors, which pay for the resources they use (see [`GasOps`]).
     fn new(
         mgr: Self::CallManager,
         from: ActorID,
         to: ActorID,
         method: MethodNum,
         value_received: TokenAmount,
+        metered: bool,
     ) -> Self
     where
         Self: Sized;
@@ -62,6 +67,10 @@ pub trait NetworkOps {
     fn network_epoch(&self) -> ChainEpoch;
     fn network_version(&self) -> NetworkVersion;
     fn network_base_fee(&self) -> &TokenAmount;
+
+    /// Returns the CID of the tipset at the given epoch, which must be in the past. Returns
+    /// `None` if the node doesn't know the tipset.
+    fn tipset_cid(&mut self, epoch: ChainEpoch) -> Result<Option<Cid>>;
 }
 
 /// Accessors to query attributes of the incoming message.
@@ -73,6 +82,9 @@ pub trait MessageOps {
 }
 
 /// The IPLD subset of the kernel.
+///
+/// Actors may only open blocks that are "reachable": their state root, blocks they linked during
+/// the current invocation, and blocks linked from blocks they've already opened.
 pub trait BlockOps {
     /// Open a block.
     ///
@@ -82,21 +94,21 @@ pub trait BlockOps {
     /// Create a new block.
     ///
     /// This method will fail if the block is too large (SPEC_AUDIT), the codec is not allowed
-    /// (SPEC_AUDIT), the block references unreachable blocks, or the block contains too many links
-    /// (SPEC_AUDIT).
+    /// (SPEC_AUDIT), or the block contains too many links (SPEC_AUDIT).
     fn block_create(&mut self, codec: u64, data: &[u8]) -> Result<BlockId>;
 
     /// Computes a CID for a block.
     ///
     /// This is the only way to add a new block to the "reachable" set.
     ///
-    /// This method will fail if the block handle is invalid.
+    /// This method will fail if the block handle is invalid, or if the block references
+    /// unreachable blocks.
     fn block_link(&mut self, id: BlockId, hash_fun: u64, hash_len: u32) -> Result<Cid>;
 
     /// Read data from a block.
     ///
     /// This method will fail if the block handle is invalid.
-    fn block_read(&self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32>;
+    fn block_read(&mut self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32>;
 
     /// Returns the blocks codec & size.
     ///
@@ -106,7 +118,7 @@ pub trait BlockOps {
     /// Returns a codec and a block as an owned buffer, given an ID.
     ///
     /// This method will fail if the block handle is invalid.
-    fn block_get(&self, id: BlockId) -> Result<(u64, Vec<u8>)> {
+    fn block_get(&mut self, id: BlockId) -> Result<(u64, Vec<u8>)> {
         let stat = self.block_stat(id)?;
         let mut ret = vec![0; stat.size as usize];
         // TODO error handling.
@@ -115,7 +127,13 @@ pub trait BlockOps {
         Ok((stat.codec, ret))
     }
 
-    // TODO: add a way to _flush_ new blocks.
+    /// Writes the blocks linked during this invocation to the blockstore, if they're reachable
+    /// from the actor's state root or linked from the return value block `ret` (if any). All
+    /// other blocks linked during this invocation are discarded.
+    ///
+    /// This is called by the call manager when the invocation succeeds. Blocks linked by aborted
+    /// invocations are never written.
+    fn block_flush(&mut self, ret: BlockId) -> Result<()>;
 }
 
 /// Actor state access and manipulation.
@@ -157,8 +175,20 @@ pub trait ActorOps {
 
     /// Creates an actor with code `code_cid` and id `actor_id`, with empty state.
     /// May only be called by Init actor.
+    ///
+    /// The code must either belong to a (non-singleton) built-in actor, or be valid wasm bytecode
+    /// present in the blockstore (see [`ActorOps::install_actor`]). Creating an actor from
+    /// user-deployed code is charged like installing the code, as it may have to be validated
+    /// again.
     fn create_actor(&mut self, code_cid: Cid, actor_id: ActorID) -> Result<()>;
 
+    /// Installs user-supplied actor code so actors can be created from it, returning its code CID.
+    /// The bytecode is validated and written to the blockstore as a raw block, but is only
+    /// compiled when first invoked. Like any other block, the code is only persisted once it's
+    /// reachable from the state tree (i.e., once an actor has been created from it).
+    /// May only be called by built-in singleton actors (e.g., the Init actor).
+    fn install_actor(&mut self, code: &[u8]) -> Result<Cid>;
+
     /// Returns whether the supplied code_cid belongs to a known built-in actor type.
     fn resolve_builtin_actor_type(&self, code_cid: &Cid) -> Option<actor::builtin::Type>;
 
@@ -192,14 +222,18 @@ pub trait CircSupplyOps {
 
 /// Operations for explicit gas charging.
 ///
-/// TODO this is unsafe; most gas charges should occur as part of syscalls, but
-///  some built-in actors currently charge gas explicitly for concrete actions.
-///  In the future (Phase 1), this should disappear and be replaced by gas instrumentation
-///  at the WASM level.
+/// Execution of user-deployed actors is metered at the wasm level, and charged through this
+/// interface at every syscall and when the actor returns.
+///
+/// TODO built-in actors are not metered, and charge gas explicitly for concrete actions. This
+///  should go away once they're metered as well.
 pub trait GasOps {
     /// ChargeGas charges specified amount of `gas` for execution.
     /// `name` provides information about gas charging point
     fn charge_gas(&mut self, name: &str, compute: i64) -> Result<()>;
+
+    /// Returns the currently active gas price list.
+    fn price_list(&self) -> &PriceList;
 }
 
 /// Cryptographic primitives provided by the kernel.
@@ -278,6 +312,12 @@ pub trait RandomnessOps {
     ) -> Result<[u8; RANDOMNESS_LENGTH]>;
 }
 
+/// Access to the resource limiter shared by all invocations on the call stack.
+pub trait LimiterOps {
+    /// Returns a mutable reference to the resource limiter.
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter;
+}
+
 /// Debugging APIs.
 pub trait DebugOps {
     /// Log a message.
@@ -285,4 +325,7 @@ pub trait DebugOps {
 
     /// Returns whether debug mode is enabled.
     fn debug_enabled(&self) -> bool;
+
+    /// Record an event in the execution trace, if tracing is enabled.
+    fn trace(&mut self, event: ExecutionEvent);
 }
@@ -13,6 +13,7 @@ pub mod executor;
 pub mod externs;
 pub mod kernel;
 pub mod machine;
+pub mod migration;
 pub mod syscalls;
 
 // TODO Public only for conformance tests.
@@ -20,17 +21,27 @@ pub mod syscalls;
 pub mod gas;
 pub mod state_tree;
 
-mod blockstore;
+pub mod blockstore;
+
+#[cfg(test)]
+mod test_utils;
 
 mod account_actor;
+mod cron_actor;
 mod init_actor;
 mod market_actor;
 mod power_actor;
 mod reward_actor;
 
+use std::collections::BTreeMap;
+
 use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 use fvm_shared::encoding::{to_vec, DAG_CBOR};
+use fvm_shared::version::NetworkVersion;
+
+use crate::gas::PriceList;
+use crate::machine::NetworkBehavior;
 
 lazy_static::lazy_static! {
     /// Cid of the empty array Cbor bytes (`EMPTY_ARR_BYTES`).
@@ -49,8 +60,31 @@ pub struct Config {
     /// Maximum number of memory pages an invocation container's memory
     /// can expand to.
     pub max_pages: usize,
+    /// Maximum number of elements an invocation container's table can grow to.
+    pub max_table_elements: u32,
+    /// Maximum total number of memory pages across all invocation containers on the call stack.
+    pub max_stack_pages: usize,
+    /// Maximum total number of table elements across all invocation containers on the call stack.
+    pub max_stack_table_elements: usize,
+    /// Maximum number of invocation containers on the call stack.
+    pub max_instances: usize,
+    /// Maximum total size (in bytes) of the blocks buffered by the machine between flushes. Past
+    /// this, buffered blocks are written to the node's blockstore even if they're unreachable.
+    pub max_buffered_bytes: usize,
     /// Whether debug mode is enabled or not.
     pub debug: bool,
+    /// Whether to record an execution trace for each message.
+    pub tracing: bool,
+    /// Whether to record a gas profile for each message.
+    pub gas_profiling: bool,
+    /// Gas price lists keyed by network version, overriding the built-in gas schedule. This lets
+    /// test networks and network upgrades ship new gas schedules (see `gas::load_price_lists`).
+    /// These take precedence over the gas schedules of the `network_behaviors`.
+    pub price_lists: BTreeMap<NetworkVersion, PriceList>,
+    /// Network behaviors (gas schedule, transfer semantics, supported proofs, etc.) keyed by
+    /// network version, overriding the built-in ones. This lets test networks and network upgrades
+    /// ship new behaviors.
+    pub network_behaviors: BTreeMap<NetworkVersion, NetworkBehavior>,
 }
 
 impl Default for Config {
@@ -58,61 +92,39 @@ impl Default for Config {
         Self {
             initial_pages: 0,
             max_pages: 1024,
+            max_table_elements: 1 << 16,
+            max_stack_pages: 1 << 16,
+            max_stack_table_elements: 1 << 20,
+            max_instances: 4096,
             max_call_depth: 4096,
+            max_buffered_bytes: 1 << 30,
             debug: false,
+            tracing: false,
+            gas_profiling: false,
+            price_lists: BTreeMap::new(),
+            network_behaviors: BTreeMap::new(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use fvm_shared::actor::builtin::Manifest;
     use fvm_shared::blockstore::{CborStore, MemoryBlockstore};
     use fvm_shared::state::StateTreeVersion;
+    use fvm_shared::version::NetworkVersion;
     use multihash::Code;
     use num_traits::Zero;
 
+    use crate::blockstore::SyncBufferedBlockstore;
     use crate::call_manager::DefaultCallManager;
-    use crate::externs::{Consensus, Externs, Rand};
-    use crate::machine::{DefaultMachine, Engine};
+    use crate::machine::{DefaultMachine, Engine, NetworkBehavior};
     use crate::state_tree::StateTree;
+    use crate::test_utils::DummyExterns;
     use crate::{executor, Config, DefaultKernel};
 
-    struct DummyExterns;
-
-    impl Externs for DummyExterns {}
-
-    impl Rand for DummyExterns {
-        fn get_chain_randomness(
-            &self,
-            _pers: fvm_shared::crypto::randomness::DomainSeparationTag,
-            _round: fvm_shared::clock::ChainEpoch,
-            _entropy: &[u8],
-        ) -> anyhow::Result<[u8; 32]> {
-            todo!()
-        }
-
-        fn get_beacon_randomness(
-            &self,
-            _pers: fvm_shared::crypto::randomness::DomainSeparationTag,
-            _round: fvm_shared::clock::ChainEpoch,
-            _entropy: &[u8],
-        ) -> anyhow::Result<[u8; 32]> {
-            todo!()
-        }
-    }
-
-    impl Consensus for DummyExterns {
-        fn verify_consensus_fault(
-            &self,
-            _h1: &[u8],
-            _h2: &[u8],
-            _extra: &[u8],
-        ) -> anyhow::Result<(Option<fvm_shared::consensus::ConsensusFault>, i64)> {
-            todo!()
-        }
-    }
-
     #[test]
     fn test_constructor() {
         let mut bs = MemoryBlockstore::default();
@@ -126,13 +138,23 @@ mod test {
             bs.put_cbor(&manifest, Code::Blake2b256).unwrap()
         };
 
+        // Don't require any built-in actors.
+        let mut config = Config::default();
+        config.network_behaviors.insert(
+            NetworkVersion::V14,
+            NetworkBehavior {
+                builtin_actors: Vec::new(),
+                ..NetworkBehavior::for_version(NetworkVersion::V14).unwrap()
+            },
+        );
+
         let machine = DefaultMachine::new(
-            Config::default(),
+            config,
             Engine::default(),
             0,
             Zero::zero(),
             Zero::zero(),
-            fvm_shared::version::NetworkVersion::V14,
+            NetworkVersion::V14,
             root,
             manifest_cid,
             bs,
@@ -143,4 +165,16 @@ mod test {
             machine,
         ));
     }
+
+    #[test]
+    fn machine_is_send() {
+        fn assert_send<T: Send>() {}
+        // Machines sharing a blockstore can execute messages on different threads.
+        type Machine = DefaultMachine<Arc<MemoryBlockstore>, DummyExterns>;
+        assert_send::<Machine>();
+        assert_send::<executor::DefaultExecutor<DefaultKernel<DefaultCallManager<Machine>>>>();
+        // Including machines sharing a write buffer.
+        type Shared = Arc<SyncBufferedBlockstore<MemoryBlockstore>>;
+        assert_send::<DefaultMachine<Shared, DummyExterns>>();
+    }
 }
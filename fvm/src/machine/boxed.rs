@@ -63,6 +63,16 @@ impl<M: Machine> Machine for Box<M> {
         (&mut **self).transfer(from, to, value)
     }
 
+    #[inline(always)]
+    fn begin_transaction(&mut self) {
+        (&mut **self).begin_transaction()
+    }
+
+    #[inline(always)]
+    fn end_transaction(&mut self, revert: bool) -> Result<()> {
+        (&mut **self).end_transaction(revert)
+    }
+
     #[inline(always)]
     fn consume(self) -> Self::Blockstore {
         (*self).consume()
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context as _};
+use cid::Cid;
+use futures::executor::block_on;
+use fvm_ipld_car::load_car;
+use fvm_shared::actor::builtin::Manifest;
+use fvm_shared::blockstore::{Blockstore, CborStore};
+use fvm_shared::version::NetworkVersion;
+
+use super::{Engine, NetworkBehavior};
+
+/// Loads a built-in actors bundle (a CAR file) into the blockstore, returning the CID of its
+/// manifest, ready to be passed to [`DefaultMachine::new`](super::DefaultMachine::new).
+///
+/// The bundle is validated: it must have a single root, the manifest, which must contain every
+/// built-in actor type the network version requires (see [`NetworkBehavior::check_manifest`]).
+/// Every actor's wasm bytecode is compiled and cached in the engine, so
+/// constructing a machine with this bundle won't need to compile it again.
+pub fn load_bundle<BS>(
+    blockstore: &BS,
+    engine: &Engine,
+    network_version: NetworkVersion,
+    car: &[u8],
+) -> anyhow::Result<Cid>
+where
+    BS: Blockstore,
+{
+    let roots = block_on(load_car(blockstore, car)).context("failed to load actor bundle")?;
+    let manifest_cid = match roots.as_slice() {
+        [root] => *root,
+        _ => {
+            return Err(anyhow!(
+                "expected a single root in the actor bundle, found {}",
+                roots.len()
+            ))
+        }
+    };
+
+    let manifest: Manifest = blockstore
+        .get_cbor(&manifest_cid)
+        .context("failed to decode the actor bundle manifest")?
+        .ok_or_else(|| anyhow!("actor bundle manifest {} not found", manifest_cid))?;
+    NetworkBehavior::for_version(network_version)
+        .ok_or_else(|| anyhow!("unsupported network version {}", network_version))?
+        .check_manifest(&manifest)?;
+
+    engine
+        .preload(blockstore, manifest.left_values())
+        .context("failed to compile the actor bundle")?;
+
+    Ok(manifest_cid)
+}
+
+/// Loads built-in actors bundles (CAR files) for multiple network versions with [`load_bundle`],
+/// returning the manifest CID for each network version.
+pub fn load_bundles<'a, BS, I>(
+    blockstore: &BS,
+    engine: &Engine,
+    bundles: I,
+) -> anyhow::Result<BTreeMap<NetworkVersion, Cid>>
+where
+    BS: Blockstore,
+    I: IntoIterator<Item = (NetworkVersion, &'a [u8])>,
+{
+    bundles
+        .into_iter()
+        .map(|(nv, car)| {
+            let manifest_cid = load_bundle(blockstore, engine, nv, car).with_context(|| {
+                format!("failed to load actor bundle for network version {}", nv)
+            })?;
+            Ok((nv, manifest_cid))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::{Code, MultihashDigest};
+    use futures::io::Cursor;
+    use futures::stream;
+    use fvm_ipld_car::CarHeader;
+    use fvm_shared::actor::builtin::Type;
+    use fvm_shared::blockstore::MemoryBlockstore;
+    use fvm_shared::encoding::{to_vec, DAG_CBOR};
+
+    use super::*;
+    use crate::test_utils::{code_cid, wasm};
+
+    /// Writes a bundle of stub actors of the given types as a CAR file, returning the CAR file and
+    /// the manifest CID.
+    fn bundle(types: &[Type]) -> (Vec<u8>, Cid) {
+        let mut manifest = Manifest::new();
+        let mut blocks = Vec::new();
+        for typ in types {
+            let code = wasm(&format!(
+                "(module (global i32 (i32.const {})))",
+                *typ as i32
+            ));
+            manifest.insert(code_cid(&code), *typ);
+            blocks.push((code_cid(&code), code));
+        }
+        let manifest = to_vec(&manifest).unwrap();
+        let manifest_cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&manifest));
+        blocks.push((manifest_cid, manifest));
+
+        let mut car = Cursor::new(Vec::new());
+        block_on(
+            CarHeader::from(vec![manifest_cid])
+                .write_stream_async(&mut car, &mut stream::iter(blocks)),
+        )
+        .unwrap();
+        (car.into_inner(), manifest_cid)
+    }
+
+    #[test]
+    fn load_complete_bundle() {
+        let types = [
+            Type::System,
+            Type::Init,
+            Type::Cron,
+            Type::Account,
+            Type::Power,
+            Type::Miner,
+            Type::Market,
+            Type::PaymentChannel,
+            Type::Multisig,
+            Type::Reward,
+            Type::VerifiedRegistry,
+        ];
+        let (car, manifest_cid) = bundle(&types);
+        let bs = MemoryBlockstore::default();
+        let loaded = load_bundle(&bs, &Engine::default(), NetworkVersion::V15, &car).unwrap();
+        assert_eq!(loaded, manifest_cid);
+        assert!(bs.has(&manifest_cid).unwrap());
+
+        // The network version must be supported.
+        assert!(load_bundle(&bs, &Engine::default(), NetworkVersion::V13, &car).is_err());
+    }
+
+    #[test]
+    fn reject_incomplete_bundle() {
+        let (car, _) = bundle(&[Type::Init, Type::Account]);
+        let err = load_bundle(
+            &MemoryBlockstore::default(),
+            &Engine::default(),
+            NetworkVersion::V15,
+            &car,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("missing built-in actors"));
+    }
+}
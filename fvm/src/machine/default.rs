@@ -1,5 +1,3 @@
-use std::ops::RangeInclusive;
-
 use anyhow::{anyhow, Context as _};
 use cid::Cid;
 use fvm_shared::actor::builtin::Manifest;
@@ -13,10 +11,9 @@ use fvm_shared::ActorID;
 use log::debug;
 use num_traits::{Signed, Zero};
 
-use super::{Engine, Machine, MachineContext};
+use super::{Engine, Machine, MachineContext, NetworkBehavior, TransferSemantics};
 use crate::blockstore::BufferedBlockstore;
 use crate::externs::Externs;
-use crate::gas::price_list_by_epoch;
 use crate::kernel::{ClassifyResult, Context as _, Result};
 use crate::state_tree::{ActorState, StateTree};
 use crate::{syscall_error, Config};
@@ -65,16 +62,20 @@ where
         blockstore: B,
         externs: E,
     ) -> anyhow::Result<Self> {
-        const SUPPORTED_VERSIONS: RangeInclusive<NetworkVersion> =
-            NetworkVersion::V14..=NetworkVersion::V15;
-
         debug!(
             "initializing a new machine, epoch={}, base_fee={}, nv={:?}, root={}",
             epoch, &base_fee, network_version, state_root
         );
 
-        if !SUPPORTED_VERSIONS.contains(&network_version) {
-            return Err(anyhow!("unsupported network version: {}", network_version));
+        // Prefer the configured behavior, falling back on the built-in one.
+        let mut network = match config.network_behaviors.get(&network_version) {
+            Some(network) => network.clone(),
+            None => NetworkBehavior::for_version(network_version)
+                .ok_or_else(|| anyhow!("unsupported network version: {}", network_version))?,
+        };
+        // Prefer the configured gas schedule, falling back on the behavior's.
+        if let Some(price_list) = config.price_lists.get(&network_version) {
+            network.price_list = price_list.clone();
         }
 
         let context = MachineContext {
@@ -83,7 +84,7 @@ where
             circ_supply,
             network_version,
             initial_state_root: state_root,
-            price_list: price_list_by_epoch(epoch),
+            network,
             debug: config.debug,
         };
 
@@ -99,7 +100,6 @@ where
         }
 
         // Load the built-in actors manifest.
-        // TODO: Check that the actor bundle is sane for the network version.
         let builtin_actors: Manifest = blockstore
             .get_cbor(&builtin_actors)
             .context("failed to load built-in actor index")?
@@ -109,16 +109,23 @@ where
                     &builtin_actors
                 )
             })?;
+        context
+            .network
+            .check_manifest(&builtin_actors)
+            .with_context(|| {
+                format!(
+                    "invalid actor bundle for network version {}",
+                    network_version
+                )
+            })?;
 
-        // Preload any uncached modules.
-        // This interface works for now because we know all actor CIDs
-        // ahead of time, but with user-supplied code, we won't have that
-        // guarantee.
+        // Preload any uncached built-in actor modules. User-deployed actors are loaded lazily, on
+        // first invocation.
         engine.preload(&blockstore, builtin_actors.left_values())?;
 
         // Create a new state tree from the supplied root.
         let state_tree = {
-            let bstore = BufferedBlockstore::new(blockstore);
+            let bstore = BufferedBlockstore::with_limit(blockstore, config.max_buffered_bytes);
             StateTree::new_from_root(bstore, &context.initial_state_root)?
         };
 
@@ -184,6 +191,16 @@ where
         Ok(root)
     }
 
+    fn begin_transaction(&mut self) {
+        self.state_tree.begin_transaction();
+        self.state_tree.store().begin_transaction();
+    }
+
+    fn end_transaction(&mut self, revert: bool) -> Result<()> {
+        self.state_tree.end_transaction(revert)?;
+        self.state_tree.store().end_transaction(revert).or_fatal()
+    }
+
     /// Creates an uninitialized actor.
     // TODO: Remove
     fn create_actor(&mut self, addr: &Address, act: ActorState) -> Result<ActorID> {
@@ -202,7 +219,7 @@ where
     }
 
     fn transfer(&mut self, from: ActorID, to: ActorID, value: &TokenAmount) -> Result<()> {
-        if self.context.network_version >= NetworkVersion::V15 {
+        if self.context.network.transfer == TransferSemantics::Strict {
             if value.is_negative() {
                 return Err(syscall_error!(IllegalArgument;
                 "attempted to transfer negative transfer value {}", value)
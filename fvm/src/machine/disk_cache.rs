@@ -0,0 +1,176 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use cid::Cid;
+use fvm_shared::encoding::blake2b_256;
+use wasmtime::Module;
+
+/// The smallest valid wasm module.
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+/// The length of the checksum prefixed to each cache entry.
+const CHECKSUM_LEN: usize = 32;
+
+/// An on-disk cache of compiled wasm modules, keyed by code CID.
+///
+/// Entries are stored in a sub-directory named after a fingerprint of the wasmtime version and
+/// engine configuration, so entries compiled by an incompatible engine are never loaded. Each
+/// entry is prefixed with a checksum; corrupt or otherwise unloadable entries are removed and
+/// recompiled.
+pub(super) struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// Opens (creating if necessary) the cache for the given engine in `dir`.
+    pub fn new(engine: &wasmtime::Engine, dir: &Path) -> anyhow::Result<Self> {
+        let dir = dir.join(fingerprint(engine)?);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create module cache at {}", dir.display()))?;
+        Ok(DiskCache { dir })
+    }
+
+    /// Loads a compiled module from the cache, if present and valid.
+    ///
+    /// # Safety
+    ///
+    /// The cache directory must be trusted: see `wasmtime::Module::deserialize`.
+    pub unsafe fn load(&self, engine: &wasmtime::Engine, k: &Cid) -> Option<Module> {
+        let path = self.entry_path(k);
+        let data = fs::read(&path).ok()?;
+
+        let module = if data.len() < CHECKSUM_LEN {
+            Err(anyhow::anyhow!("truncated entry"))
+        } else {
+            let (checksum, compiled) = data.split_at(CHECKSUM_LEN);
+            if checksum == &blake2b_256(compiled)[..] {
+                Module::deserialize(engine, compiled)
+            } else {
+                Err(anyhow::anyhow!("checksum mismatch"))
+            }
+        };
+
+        match module {
+            Ok(module) => {
+                log::debug!("loaded compiled module {} from {}", k, path.display());
+                Some(module)
+            }
+            Err(e) => {
+                log::warn!("invalidating module cache entry {}: {}", path.display(), e);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Stores a compiled module in the cache. Failures are logged, but otherwise ignored.
+    pub fn store(&self, k: &Cid, module: &Module) {
+        if let Err(e) = self.try_store(k, module) {
+            log::warn!(
+                "failed to write compiled module {} to the cache: {:#}",
+                k,
+                e
+            );
+        }
+    }
+
+    fn try_store(&self, k: &Cid, module: &Module) -> anyhow::Result<()> {
+        let compiled = module.serialize()?;
+        let path = self.entry_path(k);
+
+        // Write to a temporary file first, then rename, so concurrent readers never observe a
+        // partially written entry.
+        let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&blake2b_256(&compiled))?;
+        file.write_all(&compiled)?;
+        file.sync_all()?;
+        drop(file);
+        if let Err(e) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, k: &Cid) -> PathBuf {
+        self.dir.join(format!("{}.module", k))
+    }
+}
+
+/// Computes a fingerprint of the wasmtime version and engine configuration. Serialized modules
+/// embed both, so serializing the empty module gives us exactly what we need.
+fn fingerprint(engine: &wasmtime::Engine) -> anyhow::Result<String> {
+    let probe = Module::from_binary(engine, EMPTY_MODULE)?.serialize()?;
+    Ok(blake2b_256(&probe)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{code_cid, wasm};
+
+    const MODULE: &str =
+        r#"(module (func (export "invoke") (param i32) (result i32) (i32.const 0)))"#;
+
+    /// A fresh directory for a test's cache.
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fvm-disk-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn round_trip_and_invalidate() {
+        let dir = cache_dir("round-trip");
+        let engine = wasmtime::Engine::default();
+        let cache = DiskCache::new(&engine, &dir).unwrap();
+
+        let wasm = wasm(MODULE);
+        let k = code_cid(&wasm);
+        assert!(unsafe { cache.load(&engine, &k) }.is_none());
+
+        let module = Module::new(&engine, &wasm).unwrap();
+        cache.store(&k, &module);
+        let loaded = unsafe { cache.load(&engine, &k) }.expect("module not cached");
+        assert!(loaded.get_export("invoke").is_some());
+
+        // Corrupt entries are removed.
+        let path = cache.entry_path(&k);
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, data).unwrap();
+        assert!(unsafe { cache.load(&engine, &k) }.is_none());
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn separates_engine_configurations() {
+        let dir = cache_dir("fingerprint");
+        let engine = wasmtime::Engine::default();
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let fuel_engine = wasmtime::Engine::new(&config).unwrap();
+
+        let wasm = wasm(MODULE);
+        let k = code_cid(&wasm);
+        let cache = DiskCache::new(&engine, &dir).unwrap();
+        cache.store(&k, &Module::new(&engine, &wasm).unwrap());
+
+        // Modules compiled by the other engine are never loaded.
+        let fuel_cache = DiskCache::new(&fuel_engine, &dir).unwrap();
+        assert_ne!(cache.dir, fuel_cache.dir);
+        assert!(unsafe { fuel_cache.load(&fuel_engine, &k) }.is_none());
+        assert!(unsafe { cache.load(&engine, &k) }.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,20 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use anyhow::anyhow;
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 use fvm_shared::blockstore::Blockstore;
-use wasmtime::{Linker, Module};
+use fvm_shared::IPLD_RAW;
+use wasmtime::{Extern, ExternType, Linker, Module, ResourceLimiter};
 
+use super::disk_cache::DiskCache;
+use super::metering::instrument;
+use super::validation::{validate_module, Syscalls};
+use crate::gas::WasmInstructionCosts;
 use crate::syscalls::{bind_syscalls, InvocationData};
 use crate::Kernel;
 
@@ -23,7 +30,17 @@ impl Default for Engine {
 
 struct EngineInner {
     engine: wasmtime::Engine,
+    disk_cache: Option<DiskCache>,
     module_cache: Mutex<HashMap<Cid, Module>>,
+    /// The keys of the instrumented modules loaded for (user-deployed) code, by code CID and
+    /// instruction costs (see [`Engine::preload_metered`]).
+    metered: Mutex<HashMap<(Cid, WasmInstructionCosts), Cid>>,
+    /// Code CIDs of (user-deployed) bytecode that has been validated (but not necessarily
+    /// compiled), along with the page and table limits it was validated against.
+    validated: Mutex<HashSet<(Cid, usize, u32)>>,
+    /// The signatures of the syscalls bound by `bind_syscalls`, recorded when the first store is
+    /// created.
+    syscalls: Mutex<Option<Syscalls>>,
     instance_cache: Mutex<anymap::Map<dyn anymap::any::Any + Send>>,
 }
 
@@ -40,17 +57,43 @@ impl Engine {
     pub fn new(c: &wasmtime::Config) -> anyhow::Result<Self> {
         Ok(wasmtime::Engine::new(c)?.into())
     }
+
+    /// Create a new Engine from a wasmtime config, persisting compiled modules in (and loading
+    /// them from) the given directory.
+    ///
+    /// # Safety
+    ///
+    /// Compiled modules are loaded from the cache directory without being re-validated, so the
+    /// directory must be trusted: see `wasmtime::Module::deserialize`.
+    pub unsafe fn new_with_disk_cache(
+        c: &wasmtime::Config,
+        cache_dir: impl AsRef<Path>,
+    ) -> anyhow::Result<Self> {
+        let engine = wasmtime::Engine::new(c)?;
+        let disk_cache = DiskCache::new(&engine, cache_dir.as_ref())?;
+        Ok(Engine(Arc::new(EngineInner {
+            engine,
+            disk_cache: Some(disk_cache),
+            module_cache: Default::default(),
+            metered: Default::default(),
+            validated: Default::default(),
+            syscalls: Default::default(),
+            instance_cache: Mutex::new(anymap::Map::new()),
+        })))
+    }
 }
 
 impl From<wasmtime::Engine> for Engine {
     fn from(engine: wasmtime::Engine) -> Self {
-        let engine = Engine(Arc::new(EngineInner {
+        Engine(Arc::new(EngineInner {
             engine,
+            disk_cache: None,
             module_cache: Default::default(),
+            metered: Default::default(),
+            validated: Default::default(),
+            syscalls: Default::default(),
             instance_cache: Mutex::new(anymap::Map::new()),
-        }));
-
-        engine
+        }))
     }
 }
 
@@ -74,25 +117,109 @@ impl Engine {
             if cache.contains_key(cid) {
                 continue;
             }
-            let wasm = blockstore.get(&cid)?.ok_or_else(|| {
-                anyhow!(
-                    "no wasm bytecode in blockstore for CID {}",
-                    &cid.to_string()
-                )
-            })?;
-            let module = Module::from_binary(&self.0.engine, wasm.as_slice())?;
+            let module = match self.load_from_disk(cid) {
+                Some(module) => module,
+                None => {
+                    let wasm = blockstore.get(&cid)?.ok_or_else(|| {
+                        anyhow!(
+                            "no wasm bytecode in blockstore for CID {}",
+                            &cid.to_string()
+                        )
+                    })?;
+                    self.compile(cid, wasm.as_slice())?
+                }
+            };
             cache.insert(*cid, module);
         }
         Ok(())
     }
 
+    /// Instruments the user-deployed code with the given CID to charge for the instructions it
+    /// executes at the given costs (see `metering::instrument`), then compiles and caches the
+    /// instrumented module. This is a no-op if the code has already been loaded at these costs.
+    ///
+    /// Returns the key of the instrumented module, to instantiate it with [`Engine::get_instance`].
+    /// The code must have been validated first (see [`Engine::validate_bytecode`]).
+    pub(crate) fn preload_metered<BS: Blockstore>(
+        &self,
+        blockstore: BS,
+        k: &Cid,
+        costs: &WasmInstructionCosts,
+    ) -> anyhow::Result<Cid> {
+        let key = (*k, *costs);
+        if let Some(cid) = self.0.metered.lock().expect("metered poisoned").get(&key) {
+            return Ok(*cid);
+        }
+        let wasm = blockstore
+            .get(k)?
+            .ok_or_else(|| anyhow!("no wasm bytecode in blockstore for CID {}", k))?;
+        let wasm = instrument(&wasm, costs)?;
+        // The instrumented module is keyed by its own CID, so it can't be mistaken for the code
+        // it was derived from, either in memory or in the on-disk cache.
+        let cid = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(&wasm));
+        self.load_bytecode(&cid, &wasm)?;
+        self.0
+            .metered
+            .lock()
+            .expect("metered poisoned")
+            .insert(key, cid);
+        Ok(cid)
+    }
+
+    /// Validates some wasm code against the actor sandboxing policy, without compiling it. The
+    /// module's memory may not be declared to grow beyond `max_pages`, and its table may not be
+    /// declared to hold more than `max_table_elements` elements.
+    ///
+    /// Successful validations are cached (by CID and limits), so validating the same code
+    /// twice is cheap. This method may only be called once a store has been created (see
+    /// [`Engine::new_store`]), as it relies on the syscall signatures recorded at that point.
+    pub fn validate_bytecode(
+        &self,
+        k: &Cid,
+        wasm: &[u8],
+        max_pages: usize,
+        max_table_elements: u32,
+    ) -> anyhow::Result<()> {
+        if self.is_validated(k, max_pages, max_table_elements) {
+            return Ok(());
+        }
+        {
+            let syscalls = self.0.syscalls.lock().expect("syscalls poisoned");
+            let syscalls = syscalls
+                .as_ref()
+                .ok_or_else(|| anyhow!("syscalls have not been bound yet"))?;
+            validate_module(wasm, syscalls, max_pages, max_table_elements)?;
+        }
+        // Make sure wasmtime accepts the module as well.
+        Module::validate(&self.0.engine, wasm)?;
+        self.0
+            .validated
+            .lock()
+            .expect("validated cache poisoned")
+            .insert((*k, max_pages, max_table_elements));
+        Ok(())
+    }
+
+    /// Returns true if the code with the given CID has been validated against the given page and
+    /// table limits (see [`Engine::validate_bytecode`]).
+    pub fn is_validated(&self, k: &Cid, max_pages: usize, max_table_elements: u32) -> bool {
+        self.0
+            .validated
+            .lock()
+            .expect("validated cache poisoned")
+            .contains(&(*k, max_pages, max_table_elements))
+    }
+
     /// Load some wasm code into the engine.
     pub fn load_bytecode(&self, k: &Cid, wasm: &[u8]) -> anyhow::Result<Module> {
         let mut cache = self.0.module_cache.lock().expect("module_cache poisoned");
         let module = match cache.get(k) {
             Some(module) => module.clone(),
             None => {
-                let module = Module::from_binary(&self.0.engine, wasm)?;
+                let module = match self.load_from_disk(k) {
+                    Some(module) => module,
+                    None => self.compile(k, wasm)?,
+                };
                 cache.insert(*k, module.clone());
                 module
             }
@@ -126,24 +253,15 @@ impl Engine {
 
     /// Lookup and instantiate a loaded wasmtime module with the given store. This will cache the
     /// linker, syscalls, "pre" isntance, etc.
+    ///
+    /// Returns `None` if the module hasn't been loaded (see [`Engine::preload`]).
     pub fn get_instance<K: Kernel>(
         &self,
         store: &mut wasmtime::Store<InvocationData<K>>,
         k: &Cid,
     ) -> anyhow::Result<Option<wasmtime::Instance>> {
         let mut instance_cache = self.0.instance_cache.lock().expect("cache poisoned");
-
-        let cache = match instance_cache.entry() {
-            anymap::Entry::Occupied(e) => e.into_mut(),
-            anymap::Entry::Vacant(e) => e.insert({
-                let mut linker = Linker::new(&self.0.engine);
-                bind_syscalls(&mut linker)?;
-                Cache {
-                    linker,
-                    instances: HashMap::new(),
-                }
-            }),
-        };
+        let cache = self.cache(&mut instance_cache, &mut *store)?;
         let instance_pre = match cache.instances.entry(*k) {
             Entry::Occupied(e) => e.into_mut(),
             Entry::Vacant(e) => {
@@ -161,8 +279,120 @@ impl Engine {
         Ok(Some(instance))
     }
 
-    /// Construct a new wasmtime "store" from the given kernel.
-    pub fn new_store<K: Kernel>(&self, kernel: K) -> wasmtime::Store<InvocationData<K>> {
-        wasmtime::Store::new(&self.0.engine, InvocationData::new(kernel))
+    /// Returns the linker and instance cache for the given kernel, binding the syscalls to a new
+    /// linker if necessary.
+    fn cache<'a, K: Kernel>(
+        &self,
+        instance_cache: &'a mut anymap::Map<dyn anymap::any::Any + Send>,
+        store: &mut wasmtime::Store<InvocationData<K>>,
+    ) -> anyhow::Result<&'a mut Cache<K>> {
+        Ok(match instance_cache.entry() {
+            anymap::Entry::Occupied(e) => e.into_mut(),
+            anymap::Entry::Vacant(e) => e.insert({
+                let mut linker = Linker::new(&self.0.engine);
+                bind_syscalls(&mut linker)?;
+                self.record_syscalls(&linker, store);
+                Cache {
+                    linker,
+                    instances: HashMap::new(),
+                }
+            }),
+        })
+    }
+
+    /// Records the signatures of the syscalls defined in the linker, for validating actor imports.
+    /// The syscalls are the same for all kernels, so we only need to do this once.
+    fn record_syscalls<K: Kernel>(
+        &self,
+        linker: &Linker<InvocationData<K>>,
+        store: &mut wasmtime::Store<InvocationData<K>>,
+    ) {
+        let mut syscalls = self.0.syscalls.lock().expect("syscalls poisoned");
+        if syscalls.is_some() {
+            return;
+        }
+        let defs: Vec<(String, String, Extern)> = linker
+            .iter(&mut *store)
+            .map(|(module, name, ext)| (module.to_owned(), name.to_owned(), ext))
+            .collect();
+        *syscalls = Some(
+            defs.into_iter()
+                .filter_map(|(module, name, ext)| match ext.ty(&*store) {
+                    ExternType::Func(ty) => Some((
+                        (module, name),
+                        (ty.params().collect(), ty.results().collect()),
+                    )),
+                    _ => None,
+                })
+                .collect(),
+        );
+    }
+
+    /// Load a compiled module from the on-disk cache, if enabled.
+    fn load_from_disk(&self, k: &Cid) -> Option<Module> {
+        let disk_cache = self.0.disk_cache.as_ref()?;
+        // SAFETY: the cache directory is trusted per `new_with_disk_cache`.
+        unsafe { disk_cache.load(&self.0.engine, k) }
+    }
+
+    /// Compile some wasm code, persisting the compiled module in the on-disk cache, if enabled.
+    fn compile(&self, k: &Cid, wasm: &[u8]) -> anyhow::Result<Module> {
+        let module = Module::from_binary(&self.0.engine, wasm)?;
+        if let Some(disk_cache) = &self.0.disk_cache {
+            disk_cache.store(k, &module);
+        }
+        Ok(module)
+    }
+
+    /// Construct a new wasmtime "store" from the given kernel. The store's memory and tables are
+    /// limited by the kernel's resource limiter.
+    ///
+    /// The syscalls are bound for the kernel's type up-front, so actor code can be validated
+    /// before any actor has been instantiated.
+    pub fn new_store<K: Kernel>(
+        &self,
+        kernel: K,
+    ) -> Result<wasmtime::Store<InvocationData<K>>, (anyhow::Error, K)> {
+        let mut store = wasmtime::Store::new(&self.0.engine, InvocationData::new(kernel));
+        store.limiter(|data| data as &mut dyn ResourceLimiter);
+        let mut instance_cache = self.0.instance_cache.lock().expect("cache poisoned");
+        match self.cache(&mut instance_cache, &mut store) {
+            Ok(_) => Ok(store),
+            Err(e) => Err((e, store.into_data().kernel)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_shared::blockstore::MemoryBlockstore;
+
+    use super::*;
+    use crate::gas::price_list_by_network_version;
+    use crate::test_utils::{wasm, NETWORK_VERSION, NOOP_ACTOR};
+
+    #[test]
+    fn metered_code_is_cached_by_costs() {
+        let engine = Engine::default();
+        let bs = MemoryBlockstore::default();
+        let wasm = wasm(NOOP_ACTOR);
+        let k = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(&wasm));
+        bs.put_keyed(&k, &wasm).unwrap();
+
+        let costs = price_list_by_network_version(NETWORK_VERSION)
+            .unwrap()
+            .wasm_instruction_costs;
+        let metered = engine.preload_metered(&bs, &k, &costs).unwrap();
+        assert_ne!(metered, k);
+        assert!(engine.get_module(&metered).is_some());
+        assert!(engine.get_module(&k).is_none());
+        assert_eq!(engine.preload_metered(&bs, &k, &costs).unwrap(), metered);
+
+        let mut expensive = costs;
+        expensive.base += 1;
+        assert_ne!(
+            engine.preload_metered(&bs, &k, &expensive).unwrap(),
+            metered
+        );
     }
 }
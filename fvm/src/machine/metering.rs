@@ -0,0 +1,329 @@
+use anyhow::{anyhow, bail};
+use wasmparser::{FunctionBody, Operator, Parser, Payload};
+
+use crate::gas::WasmInstructionCosts;
+
+/// The name under which instrumented modules export their gas counter: a mutable `i64` global
+/// holding the gas the actor has left. Actor code may not export anything else under this name.
+pub(crate) const GAS_COUNTER: &str = "__fvm_gas";
+
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+
+/// Instruments validated user-deployed actor code to charge for the instructions it executes,
+/// priced by `costs`.
+///
+/// The instrumented module gets a new global, exported as [`GAS_COUNTER`]. Function bodies are
+/// split into straight-line sequences of instructions, ending at control flow instructions and
+/// calls, and each sequence is prefixed with code subtracting its total cost from the counter.
+/// The module traps with `unreachable` as soon as the counter drops below zero, and the host
+/// charges the gas consumed by reading the counter back.
+///
+/// A sequence is charged in full when entered, even if it's left early by a trap.
+///
+/// The code must have been validated first (see `validate_module`): in particular, it may not
+/// import globals, so the counter's index is the number of globals the module defines.
+pub(super) fn instrument(wasm: &[u8], costs: &WasmInstructionCosts) -> anyhow::Result<Vec<u8>> {
+    // The charges to inject in each function body, as `(offset, cost)` pairs.
+    let mut charges = Vec::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::CodeSectionEntry(body) = payload? {
+            charges.push(sequences(&body, costs)?);
+        }
+    }
+    let mut charges = charges.into_iter();
+
+    // The module header (magic number and version) is kept as is.
+    let mut out = wasm
+        .get(..8)
+        .ok_or_else(|| anyhow!("truncated module"))?
+        .to_vec();
+    let mut counter = None;
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        let (size, len) = read_u32(&wasm[pos + 1..])?;
+        let start = pos + 1 + len;
+        let end = start + size as usize;
+        let section = wasm
+            .get(pos..end)
+            .ok_or_else(|| anyhow!("truncated section"))?;
+        let contents = &wasm[start..end];
+        pos = end;
+
+        match id {
+            GLOBAL_SECTION => {
+                let (count, len) = read_u32(contents)?;
+                let mut section = Vec::new();
+                write_u32(&mut section, count + 1);
+                section.extend_from_slice(&contents[len..]);
+                write_counter_global(&mut section);
+                write_section(&mut out, GLOBAL_SECTION, &section);
+                counter = Some(count);
+            }
+            EXPORT_SECTION => {
+                // The global section comes right before the export section, if there is one.
+                let index = match counter {
+                    Some(index) => index,
+                    None => {
+                        let mut section = Vec::new();
+                        write_u32(&mut section, 1);
+                        write_counter_global(&mut section);
+                        write_section(&mut out, GLOBAL_SECTION, &section);
+                        counter = Some(0);
+                        0
+                    }
+                };
+                let (count, len) = read_u32(contents)?;
+                let mut section = Vec::new();
+                write_u32(&mut section, count + 1);
+                section.extend_from_slice(&contents[len..]);
+                write_u32(&mut section, GAS_COUNTER.len() as u32);
+                section.extend_from_slice(GAS_COUNTER.as_bytes());
+                // Global export.
+                section.push(0x03);
+                write_u32(&mut section, index);
+                write_section(&mut out, EXPORT_SECTION, &section);
+            }
+            CODE_SECTION => {
+                // Validated modules always export `invoke`, so the counter has been added by now.
+                let counter = counter.ok_or_else(|| anyhow!("missing export section"))?;
+                let (count, mut offset) = read_u32(contents)?;
+                let mut section = Vec::new();
+                write_u32(&mut section, count);
+                for _ in 0..count {
+                    let (size, len) = read_u32(&contents[offset..])?;
+                    // Absolute offsets of the function body.
+                    let mut copied = start + offset + len;
+                    let body_end = copied + size as usize;
+                    offset += len + size as usize;
+
+                    let mut body = Vec::new();
+                    for (at, cost) in charges.next().ok_or_else(|| anyhow!("missing body"))? {
+                        body.extend_from_slice(&wasm[copied..at]);
+                        write_charge(&mut body, counter, cost);
+                        copied = at;
+                    }
+                    body.extend_from_slice(&wasm[copied..body_end]);
+
+                    write_u32(&mut section, body.len() as u32);
+                    section.extend_from_slice(&body);
+                }
+                write_section(&mut out, CODE_SECTION, &section);
+            }
+            _ => out.extend_from_slice(section),
+        }
+    }
+    Ok(out)
+}
+
+/// Splits a function body into straight-line sequences of instructions, returning the offset
+/// and total cost of each sequence that isn't free.
+fn sequences(
+    body: &FunctionBody,
+    costs: &WasmInstructionCosts,
+) -> anyhow::Result<Vec<(usize, i64)>> {
+    let mut sequences = Vec::new();
+    let mut current = None;
+    for op in body.get_operators_reader()?.into_iter_with_offsets() {
+        let (op, offset) = op?;
+        let (_, cost) = current.get_or_insert((offset, 0i64));
+        *cost = cost.saturating_add(costs.operator_cost(&op));
+        // Branch targets, and the code following branches and calls, start new sequences.
+        if matches!(
+            op,
+            Operator::Block { .. }
+                | Operator::Loop { .. }
+                | Operator::If { .. }
+                | Operator::Else
+                | Operator::End
+                | Operator::Br { .. }
+                | Operator::BrIf { .. }
+                | Operator::BrTable { .. }
+                | Operator::Return
+                | Operator::Unreachable
+                | Operator::Call { .. }
+                | Operator::CallIndirect { .. }
+        ) {
+            sequences.extend(current.take().filter(|&(_, cost)| cost > 0));
+        }
+    }
+    // Function bodies always end with `end`.
+    if current.is_some() {
+        bail!("unterminated function body");
+    }
+    Ok(sequences)
+}
+
+/// Writes the definition of the gas counter: a mutable `i64` global, initialized to zero.
+fn write_counter_global(out: &mut Vec<u8>) {
+    // i64, mutable, (i64.const 0) end
+    out.extend_from_slice(&[0x7e, 0x01, 0x42, 0x00, 0x0b]);
+}
+
+/// Writes code subtracting `cost` from the gas counter, and trapping if it drops below zero.
+fn write_charge(out: &mut Vec<u8>, counter: u32, cost: i64) {
+    // global.get $counter
+    out.push(0x23);
+    write_u32(out, counter);
+    // i64.const $cost
+    out.push(0x42);
+    write_i64(out, cost);
+    // i64.sub
+    out.push(0x7d);
+    // global.set $counter
+    out.push(0x24);
+    write_u32(out, counter);
+    // global.get $counter
+    out.push(0x23);
+    write_u32(out, counter);
+    // i64.const 0, i64.lt_s, if, unreachable, end
+    out.extend_from_slice(&[0x42, 0x00, 0x53, 0x04, 0x40, 0x00, 0x0b]);
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_u32(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+/// Reads an unsigned LEB128 integer, returning it along with its encoded length.
+fn read_u32(bytes: &[u8]) -> anyhow::Result<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, &byte) in bytes.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(anyhow!("invalid integer encoding"))
+}
+
+/// Writes an unsigned LEB128 integer.
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 integer.
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_shared::version::NetworkVersion;
+    use wasmtime::{Engine, Instance, Module, Store, Val};
+
+    use super::*;
+    use crate::gas::price_list_by_network_version;
+    use crate::test_utils::wasm;
+
+    fn costs() -> WasmInstructionCosts {
+        price_list_by_network_version(NetworkVersion::V15)
+            .unwrap()
+            .wasm_instruction_costs
+    }
+
+    /// Instruments and runs a module's `invoke` export with the given gas, returning the result
+    /// (if it didn't trap), and the gas left.
+    fn run(wat: &str, gas: i64) -> (Option<i32>, i64) {
+        let instrumented = instrument(&wasm(wat), &costs()).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &instrumented).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let counter = instance.get_global(&mut store, GAS_COUNTER).unwrap();
+        counter.set(&mut store, Val::I64(gas)).unwrap();
+        let invoke = instance
+            .get_typed_func::<i32, i32, _>(&mut store, "invoke")
+            .unwrap();
+        let res = invoke.call(&mut store, 0).ok();
+        (res, counter.get(&mut store).i64().unwrap())
+    }
+
+    #[test]
+    fn straight_line_code() {
+        let wat = r#"(module (memory (export "memory") 1 1)
+            (func (export "invoke") (param i32) (result i32)
+              (i32.add (local.get 0) (i32.const 1))))"#;
+        let costs = costs();
+        // local.get, i32.const, i32.add, and end.
+        let cost = 3 * costs.base + costs.structural;
+        assert_eq!(run(wat, 1000), (Some(1), 1000 - cost));
+        // Running out of gas traps, leaving the counter negative.
+        let (res, gas) = run(wat, cost - 1);
+        assert_eq!(res, None);
+        assert!(gas < 0);
+    }
+
+    #[test]
+    fn loops_are_charged_per_iteration() {
+        // Counts down from 10.
+        let wat = r#"(module (memory (export "memory") 1 1)
+            (func (export "invoke") (param i32) (result i32)
+              (local.set 0 (i32.const 10))
+              (loop
+                (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                (br_if 0 (local.get 0)))
+              (local.get 0)))"#;
+        let (res, gas) = run(wat, 1_000_000);
+        assert_eq!(res, Some(0));
+        let once = 1_000_000 - gas;
+
+        let wat = wat.replace("i32.const 10", "i32.const 20");
+        let (res, gas) = run(&wat, 1_000_000);
+        assert_eq!(res, Some(0));
+        let costs = costs();
+        // The loop body: local.get, i32.const, i32.sub, local.set, local.get, and br_if.
+        assert_eq!(1_000_000 - gas - once, 10 * 6 * costs.base);
+    }
+
+    #[test]
+    fn module_with_globals() {
+        let wat = r#"(module (memory (export "memory") 1 1)
+            (global $g (mut i32) (i32.const 41))
+            (func (export "invoke") (param i32) (result i32)
+              (global.set $g (i32.add (global.get $g) (i32.const 1)))
+              (global.get $g)))"#;
+        let (res, gas) = run(wat, 1000);
+        assert_eq!(res, Some(42));
+        assert!(gas < 1000);
+    }
+
+    #[test]
+    fn calls_and_memory() {
+        let wat = r#"(module (memory (export "memory") 1 1)
+            (func $store (param i32)
+              (i32.store (i32.const 0) (local.get 0)))
+            (func (export "invoke") (param i32) (result i32)
+              (call $store (i32.const 7))
+              (i32.div_u (i32.load (i32.const 0)) (i32.const 2))))"#;
+        let (res, gas) = run(wat, 1000);
+        assert_eq!(res, Some(3));
+        let costs = costs();
+        // Two sequences in `invoke` (split by the call), and one in `$store`.
+        let cost = costs.call
+            + 2 * costs.memory_access
+            + costs.division
+            + 5 * costs.base
+            + 2 * costs.structural;
+        assert_eq!(1000 - gas, cost);
+    }
+}
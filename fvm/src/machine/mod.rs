@@ -8,7 +8,6 @@ use fvm_shared::version::NetworkVersion;
 use fvm_shared::ActorID;
 
 use crate::externs::Externs;
-use crate::gas::PriceList;
 use crate::kernel::Result;
 use crate::state_tree::{ActorState, StateTree};
 use crate::Config;
@@ -21,6 +20,22 @@ mod engine;
 
 pub use engine::Engine;
 
+mod disk_cache;
+
+mod network;
+
+pub use network::{NetworkBehavior, TransferSemantics};
+
+mod bundle;
+
+pub use bundle::{load_bundle, load_bundles};
+
+mod validation;
+
+mod metering;
+
+pub(crate) use metering::GAS_COUNTER;
+
 mod boxed;
 
 pub const REWARD_ACTOR_ADDR: Address = Address::new_id(2);
@@ -67,6 +82,14 @@ pub trait Machine: 'static {
     /// Otherwise, if the amounts are invalid, etc., it fails with a syscall error.
     fn transfer(&mut self, from: ActorID, to: ActorID, value: &TokenAmount) -> Result<()>;
 
+    /// Begins a transaction: changes to the state tree, and the blocks written to the blockstore,
+    /// can be reverted until the matching [`Machine::end_transaction`]. Transactions can be nested.
+    fn begin_transaction(&mut self);
+
+    /// Ends the innermost transaction, reverting the state tree and discarding the blocks written
+    /// during the transaction if `revert` is set.
+    fn end_transaction(&mut self, revert: bool) -> Result<()>;
+
     /// Flushes the state-tree and returns the new root CID.
     fn flush(&mut self) -> Result<Cid> {
         self.state_tree_mut().flush()
@@ -89,10 +112,10 @@ pub struct MachineContext {
     pub circ_supply: TokenAmount,
     /// The initial state root on which this block is based.
     pub initial_state_root: Cid,
-    /// The price list.
-    pub price_list: PriceList,
     /// The network version at epoch
     pub network_version: NetworkVersion,
+    /// The network version specific behavior, including the price list.
+    pub network: NetworkBehavior,
     /// Whether debug mode is enabled or not.
     pub debug: bool,
 }
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use fvm_shared::actor::builtin::{Manifest, Type};
+use fvm_shared::sector::{RegisteredPoStProof, RegisteredSealProof, RegisteredUpdateProof};
+use fvm_shared::version::NetworkVersion;
+
+use crate::gas::{price_list_by_network_version, PriceList};
+
+/// The network version specific behavior of the FVM.
+///
+/// Built-in behaviors are provided for all supported network versions (see
+/// [`NetworkBehavior::for_version`]). They can be overridden per network version through
+/// [`Config::network_behaviors`](crate::Config::network_behaviors), e.g., to test network upgrades.
+#[derive(Clone, Debug)]
+pub struct NetworkBehavior {
+    /// The gas schedule.
+    pub price_list: PriceList,
+    /// How value transfers between actors are validated.
+    pub transfer: TransferSemantics,
+    /// The seal proof types actors may verify.
+    pub seal_proofs: HashSet<RegisteredSealProof>,
+    /// The window PoSt proof types actors may verify.
+    pub post_proofs: HashSet<RegisteredPoStProof>,
+    /// The replica update (snap deal) proof types actors may verify.
+    pub update_proofs: HashSet<RegisteredUpdateProof>,
+    /// The built-in actors the actor bundle must contain.
+    pub builtin_actors: Vec<Type>,
+}
+
+/// How value transfers between actors are validated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferSemantics {
+    /// Transfers to self and zero-value transfers are no-ops, and aren't validated (before V15).
+    Lenient,
+    /// All transfers are validated: the sender must exist and have sufficient funds, even for
+    /// transfers to self, and the receiver must exist, even for zero-value transfers (V15+).
+    Strict,
+}
+
+impl NetworkBehavior {
+    /// Returns the built-in behavior for the given network version, or `None` if the network
+    /// version isn't supported.
+    pub fn for_version(network_version: NetworkVersion) -> Option<Self> {
+        let transfer = match network_version {
+            NetworkVersion::V14 => TransferSemantics::Lenient,
+            NetworkVersion::V15 | NetworkVersion::V16 | NetworkVersion::V17 => {
+                TransferSemantics::Strict
+            }
+            _ => return None,
+        };
+        // Replica updates (snap deals) were introduced in V15.
+        let update_proofs = match network_version {
+            NetworkVersion::V14 => &[][..],
+            _ => ALL_UPDATE_PROOFS,
+        };
+        Some(NetworkBehavior {
+            price_list: price_list_by_network_version(network_version)?.clone(),
+            transfer,
+            // The seal and window PoSt proof types haven't changed since V14.
+            seal_proofs: ALL_SEAL_PROOFS.iter().copied().collect(),
+            post_proofs: ALL_POST_PROOFS.iter().copied().collect(),
+            update_proofs: update_proofs.iter().copied().collect(),
+            builtin_actors: ALL_BUILTIN_ACTORS.to_vec(),
+        })
+    }
+
+    /// Checks that the actor bundle's manifest contains all the built-in actors required by this
+    /// network version.
+    pub fn check_manifest(&self, manifest: &Manifest) -> anyhow::Result<()> {
+        let missing: Vec<_> = self
+            .builtin_actors
+            .iter()
+            .filter(|typ| !manifest.contains_right(*typ))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "actor bundle is missing built-in actors {:?}",
+                missing
+            ));
+        }
+        Ok(())
+    }
+}
+
+const ALL_SEAL_PROOFS: &[RegisteredSealProof] = &[
+    RegisteredSealProof::StackedDRG2KiBV1,
+    RegisteredSealProof::StackedDRG512MiBV1,
+    RegisteredSealProof::StackedDRG8MiBV1,
+    RegisteredSealProof::StackedDRG32GiBV1,
+    RegisteredSealProof::StackedDRG64GiBV1,
+    RegisteredSealProof::StackedDRG2KiBV1P1,
+    RegisteredSealProof::StackedDRG512MiBV1P1,
+    RegisteredSealProof::StackedDRG8MiBV1P1,
+    RegisteredSealProof::StackedDRG32GiBV1P1,
+    RegisteredSealProof::StackedDRG64GiBV1P1,
+];
+
+const ALL_POST_PROOFS: &[RegisteredPoStProof] = &[
+    RegisteredPoStProof::StackedDRGWindow2KiBV1,
+    RegisteredPoStProof::StackedDRGWindow8MiBV1,
+    RegisteredPoStProof::StackedDRGWindow512MiBV1,
+    RegisteredPoStProof::StackedDRGWindow32GiBV1,
+    RegisteredPoStProof::StackedDRGWindow64GiBV1,
+];
+
+const ALL_UPDATE_PROOFS: &[RegisteredUpdateProof] = &[
+    RegisteredUpdateProof::StackedDRG2KiBV1,
+    RegisteredUpdateProof::StackedDRG8MiBV1,
+    RegisteredUpdateProof::StackedDRG512MiBV1,
+    RegisteredUpdateProof::StackedDRG32GiBV1,
+    RegisteredUpdateProof::StackedDRG64GiBV1,
+];
+
+const ALL_BUILTIN_ACTORS: &[Type] = &[
+    Type::System,
+    Type::Init,
+    Type::Cron,
+    Type::Account,
+    Type::Power,
+    Type::Miner,
+    Type::Market,
+    Type::PaymentChannel,
+    Type::Multisig,
+    Type::Reward,
+    Type::VerifiedRegistry,
+];
+
+#[cfg(test)]
+mod tests {
+    use cid::Cid;
+    use fvm_shared::IPLD_RAW;
+    use multihash::{Code, MultihashDigest};
+
+    use super::*;
+
+    #[test]
+    fn supported_versions() {
+        assert!(NetworkBehavior::for_version(NetworkVersion::V13).is_none());
+        let v14 = NetworkBehavior::for_version(NetworkVersion::V14).unwrap();
+        assert_eq!(v14.transfer, TransferSemantics::Lenient);
+        assert!(v14.update_proofs.is_empty());
+        let v15 = NetworkBehavior::for_version(NetworkVersion::V15).unwrap();
+        assert!(v15
+            .update_proofs
+            .contains(&RegisteredUpdateProof::StackedDRG32GiBV1));
+        let v16 = NetworkBehavior::for_version(NetworkVersion::V16).unwrap();
+        assert_eq!(v16.transfer, TransferSemantics::Strict);
+        assert_eq!(v16.update_proofs, v15.update_proofs);
+    }
+
+    #[test]
+    fn check_manifest() {
+        let behavior = NetworkBehavior::for_version(NetworkVersion::V15).unwrap();
+        let mut manifest = Manifest::new();
+        assert!(behavior.check_manifest(&manifest).is_err());
+
+        for (i, typ) in ALL_BUILTIN_ACTORS.iter().enumerate() {
+            let code = Cid::new_v1(IPLD_RAW, Code::Identity.digest(&[i as u8]));
+            manifest.insert(code, *typ);
+        }
+        behavior.check_manifest(&manifest).unwrap();
+    }
+}
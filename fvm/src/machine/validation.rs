@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use wasmparser::{
+    ExternalKind, FuncType, ImportSectionEntryType, Operator, Parser, Payload, Type, TypeDef,
+    Validator, WasmFeatures,
+};
+use wasmtime::ValType;
+
+use super::metering::GAS_COUNTER;
+
+/// The signature of a wasm function, as `(params, results)`.
+pub(super) type Signature = (Vec<ValType>, Vec<ValType>);
+
+/// The syscalls available to actors, keyed by `(module, name)`.
+pub(super) type Syscalls = HashMap<(String, String), Signature>;
+
+/// Validates user-deployed actor code against the FVM's sandboxing policy:
+///
+/// - The module must be valid wasm, without threads, SIMD, bulk memory instructions, or reference
+///   types. The cost of bulk memory and table instructions depends on their operands, so they
+///   can't be metered per instruction.
+/// - The module may only import syscalls (with matching signatures), and nothing else.
+/// - The module must export its memory as `memory`, and an `invoke` function with the signature
+///   `(u32) -> u32`. The gas counter's export name (see `metering`) is reserved.
+/// - The module may not use floating point numbers, or have a start function.
+/// - The module's memory and table must declare their maximum sizes: the memory may not grow
+///   beyond `max_pages`, and the table may have at most `max_table_elements` elements.
+pub(super) fn validate_module(
+    wasm: &[u8],
+    syscalls: &Syscalls,
+    max_pages: usize,
+    max_table_elements: u32,
+) -> anyhow::Result<()> {
+    let mut validator = Validator::new();
+    validator.wasm_features(WasmFeatures {
+        simd: false,
+        threads: false,
+        bulk_memory: false,
+        reference_types: false,
+        multi_memory: false,
+        memory64: false,
+        module_linking: false,
+        ..WasmFeatures::default()
+    });
+    validator.validate_all(wasm)?;
+
+    // Wasm validation guarantees that all indices below are in bounds.
+    let mut types = Vec::new();
+    // The type index of each function, imported functions first.
+    let mut func_types = Vec::new();
+    let mut has_invoke = false;
+    let mut has_memory = false;
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for ty in reader {
+                    match ty? {
+                        TypeDef::Func(ty) => types.push(signature(&ty)?),
+                        _ => bail!("unsupported type definition"),
+                    }
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    let name = import.field.unwrap_or_default();
+                    let idx = match import.ty {
+                        ImportSectionEntryType::Function(idx) => idx,
+                        _ => bail!("import {}::{} is not a function", import.module, name),
+                    };
+                    let expected = syscalls
+                        .get(&(import.module.to_owned(), name.to_owned()))
+                        .ok_or_else(|| anyhow!("unknown syscall {}::{}", import.module, name))?;
+                    if &types[idx as usize] != expected {
+                        bail!(
+                            "syscall {}::{} has the wrong signature",
+                            import.module,
+                            name
+                        );
+                    }
+                    func_types.push(idx);
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for idx in reader {
+                    func_types.push(idx?);
+                }
+            }
+            Payload::TableSection(reader) => {
+                for table in reader {
+                    // Wasm validation guarantees that the initial size doesn't exceed the maximum.
+                    match table?.maximum {
+                        Some(max) if max <= max_table_elements => {}
+                        Some(_) => bail!("table exceeds {} elements", max_table_elements),
+                        None => bail!("table must declare a maximum size"),
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    match memory?.maximum {
+                        Some(max) if max <= max_pages as u64 => {}
+                        Some(_) => bail!("memory exceeds {} pages", max_pages),
+                        None => bail!("memory must declare a maximum size"),
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    check_type(global?.ty.content_type)?;
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    match (export.field, export.kind) {
+                        ("invoke", ExternalKind::Function) => {
+                            let ty = &types[func_types[export.index as usize] as usize];
+                            if ty != &(vec![ValType::I32], vec![ValType::I32]) {
+                                bail!("invoke must have the signature (u32) -> u32");
+                            }
+                            has_invoke = true;
+                        }
+                        ("memory", ExternalKind::Memory) => has_memory = true,
+                        (GAS_COUNTER, _) => bail!("export name {} is reserved", GAS_COUNTER),
+                        _ => {}
+                    }
+                }
+            }
+            // Start functions run on instantiation, before the actor is invoked.
+            Payload::StartSection { .. } => bail!("start functions are not allowed"),
+            Payload::CodeSectionEntry(body) => {
+                for local in body.get_locals_reader()? {
+                    check_type(local?.1)?;
+                }
+                for op in body.get_operators_reader()? {
+                    check_operator(&op?)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !has_invoke {
+        bail!("missing invoke export");
+    }
+    if !has_memory {
+        bail!("missing memory export");
+    }
+    Ok(())
+}
+
+/// Converts a wasm function type into a signature, rejecting anything but integers.
+fn signature(ty: &FuncType) -> anyhow::Result<Signature> {
+    let convert = |types: &[Type]| -> anyhow::Result<Vec<ValType>> {
+        types.iter().map(|&ty| val_type(ty)).collect()
+    };
+    Ok((convert(&ty.params)?, convert(&ty.returns)?))
+}
+
+fn check_type(ty: Type) -> anyhow::Result<()> {
+    val_type(ty).map(|_| ())
+}
+
+fn val_type(ty: Type) -> anyhow::Result<ValType> {
+    match ty {
+        Type::I32 => Ok(ValType::I32),
+        Type::I64 => Ok(ValType::I64),
+        _ => Err(anyhow!("unsupported value type {:?}", ty)),
+    }
+}
+
+/// Rejects operators that introduce floating point values. Floats can't come from anywhere else
+/// (function signatures, locals, and globals are checked separately), so this is sufficient to
+/// rule out all floating point operations.
+fn check_operator(op: &Operator) -> anyhow::Result<()> {
+    match op {
+        Operator::F32Load { .. }
+        | Operator::F64Load { .. }
+        | Operator::F32Const { .. }
+        | Operator::F64Const { .. }
+        | Operator::F32ConvertI32S
+        | Operator::F32ConvertI32U
+        | Operator::F32ConvertI64S
+        | Operator::F32ConvertI64U
+        | Operator::F64ConvertI32S
+        | Operator::F64ConvertI32U
+        | Operator::F64ConvertI64S
+        | Operator::F64ConvertI64U
+        | Operator::F32ReinterpretI32
+        | Operator::F64ReinterpretI64 => Err(anyhow!("floating point operations are not allowed")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::wasm;
+
+    fn syscalls() -> Syscalls {
+        let mut syscalls = Syscalls::new();
+        syscalls.insert(
+            ("self".into(), "set_root".into()),
+            (vec![ValType::I32], vec![ValType::I32]),
+        );
+        syscalls
+    }
+
+    /// Validates a module with the given imports, memory, and body for `invoke`, allowing up to 2
+    /// pages of memory and 4 table elements.
+    fn validate(imports: &str, memory: &str, body: &str) -> anyhow::Result<()> {
+        let wat = format!(
+            r#"(module {} (memory (export "memory") {})
+                 (func (export "invoke") (param i32) (result i32) {}))"#,
+            imports, memory, body
+        );
+        validate_module(&wasm(&wat), &syscalls(), 2, 4)
+    }
+
+    #[test]
+    fn valid_module() {
+        let imports = r#"(import "self" "set_root" (func (param i32) (result i32)))"#;
+        validate(imports, "1 2", "(call 0 (i32.const 0))").unwrap();
+    }
+
+    #[test]
+    fn memory_must_be_bounded() {
+        assert!(validate("", "1", "(i32.const 0)").is_err());
+        assert!(validate("", "1 3", "(i32.const 0)").is_err());
+    }
+
+    #[test]
+    fn table_must_be_bounded() {
+        let table = |limits| format!("(table {} funcref)", limits);
+        validate(&table("1 1"), "1 1", "(i32.const 0)").unwrap();
+        assert!(validate(&table("1"), "1 1", "(i32.const 0)").is_err());
+        validate(&table("1 4"), "1 1", "(i32.const 0)").unwrap();
+        assert!(validate(&table("1 5"), "1 1", "(i32.const 0)").is_err());
+    }
+
+    #[test]
+    fn unknown_imports() {
+        let imports = r#"(import "self" "unknown" (func (param i32) (result i32)))"#;
+        assert!(validate(imports, "1 1", "(i32.const 0)").is_err());
+
+        // Known syscalls must have the right signature.
+        let imports = r#"(import "self" "set_root" (func (param i64) (result i32)))"#;
+        assert!(validate(imports, "1 1", "(i32.const 0)").is_err());
+    }
+
+    #[test]
+    fn no_floats() {
+        assert!(validate("", "1 1", "(drop (f32.const 1)) (i32.const 0)").is_err());
+    }
+
+    #[test]
+    fn no_bulk_memory() {
+        let body = "(memory.fill (i32.const 0) (i32.const 0) (i32.const 1)) (i32.const 0)";
+        assert!(validate("", "1 1", body).is_err());
+    }
+
+    #[test]
+    fn gas_counter_export_is_reserved() {
+        let export = format!(r#"(global (export "{}") i32 (i32.const 0))"#, GAS_COUNTER);
+        validate("", "1 1", "(i32.const 0)").unwrap();
+        assert!(validate(&export, "1 1", "(i32.const 0)").is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! State migrations between built-in actor bundles.
+//!
+//! When the network upgrades, every built-in actor's code CID must be rewritten from the old
+//! bundle's [`Manifest`] to the new one (by actor type) and, for some actor types, its state must
+//! be transformed. A [`StateMigration`] does both: it walks the state tree, runs the registered
+//! per-actor-type [`ActorMigration`]s in parallel, and writes a new state root.
+//!
+//! User-deployed actors aren't part of any bundle, so they're carried over unchanged.
+//!
+//! Migrations read and write state through a [`MigrationStore`], so that dry runs can migrate
+//! every actor without writing anything to the underlying blockstore.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Context as _};
+use cid::Cid;
+use fvm_shared::actor::builtin::{Manifest, Type};
+use fvm_shared::address::Address;
+use fvm_shared::blockstore::{Blockstore, MemoryBlockstore};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::state_tree::{ActorState, StateTree};
+
+/// How often (in migrated actors) progress is reported.
+const PROGRESS_INTERVAL: usize = 10_000;
+
+/// The input to an actor state migration.
+#[derive(Clone, Debug)]
+pub struct ActorMigrationInput {
+    /// The actor's ID address.
+    pub address: Address,
+    /// The actor's built-in actor type.
+    pub actor_type: Type,
+    /// The actor as found in the old state tree.
+    pub actor: ActorState,
+    /// The actor's new code CID.
+    pub new_code: Cid,
+}
+
+/// Transforms the state of actors of a single built-in actor type.
+pub trait ActorMigration<BS>: Send + Sync {
+    /// Migrates an actor's state, returning the CID of its new state. The new state must be
+    /// written to `store`.
+    fn migrate_state(
+        &self,
+        store: &MigrationStore<BS>,
+        input: &ActorMigrationInput,
+    ) -> anyhow::Result<Cid>;
+}
+
+/// The blockstore migrations run against. Reads are served from the migrated blockstore. Writes
+/// go to the migrated blockstore too, except in dry runs, where they're kept in memory and
+/// discarded at the end of the run.
+pub struct MigrationStore<'a, BS> {
+    base: &'a BS,
+    /// The blocks written during a dry run.
+    discarded: Option<MemoryBlockstore>,
+}
+
+impl<'a, BS> MigrationStore<'a, BS>
+where
+    BS: Blockstore,
+{
+    fn new(base: &'a BS, dry_run: bool) -> Self {
+        MigrationStore {
+            base,
+            discarded: dry_run.then(MemoryBlockstore::default),
+        }
+    }
+}
+
+impl<'a, BS> Blockstore for MigrationStore<'a, BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(discarded) = &self.discarded {
+            if let Some(data) = discarded.get(k)? {
+                return Ok(Some(data));
+            }
+        }
+        self.base.get(k)
+    }
+
+    fn has(&self, k: &Cid) -> anyhow::Result<bool> {
+        if let Some(discarded) = &self.discarded {
+            if discarded.has(k)? {
+                return Ok(true);
+            }
+        }
+        self.base.has(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        match &self.discarded {
+            Some(discarded) => discarded.put_keyed(k, block),
+            None => self.base.put_keyed(k, block),
+        }
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> anyhow::Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        match &self.discarded {
+            Some(discarded) => discarded.put_many_keyed(blocks),
+            None => self.base.put_many_keyed(blocks),
+        }
+    }
+}
+
+/// The progress of a running migration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MigrationProgress {
+    /// The number of actors migrated so far.
+    pub migrated: usize,
+    /// The total number of actors to migrate.
+    pub total: usize,
+}
+
+/// Options for running a migration.
+#[derive(Default)]
+pub struct MigrationOptions<'a> {
+    /// If true, migrate all actors and verify the result, but don't write anything (neither the
+    /// new actor states nor the new state tree) to the blockstore.
+    pub dry_run: bool,
+    /// Called periodically (from any thread) with the migration's progress.
+    pub progress: Option<&'a (dyn Fn(MigrationProgress) + Sync)>,
+}
+
+/// The result of a migration.
+#[derive(Clone, Debug)]
+pub struct MigrationResult {
+    /// The new state root, or `None` for a dry run.
+    pub state_root: Option<Cid>,
+    /// The total number of actors.
+    pub actors: usize,
+    /// The number of actors whose state was changed by an [`ActorMigration`].
+    pub transformed: usize,
+}
+
+/// A migration of the state tree from one built-in actor bundle to another.
+pub struct StateMigration<BS> {
+    old_manifest: Manifest,
+    new_manifest: Manifest,
+    migrations: HashMap<Type, Box<dyn ActorMigration<BS>>>,
+}
+
+impl<BS> StateMigration<BS>
+where
+    BS: Blockstore + Sync,
+{
+    /// Creates a migration from the bundle described by `old_manifest` to the bundle described by
+    /// `new_manifest`. Without any registered [`ActorMigration`]s, only code CIDs are rewritten.
+    pub fn new(old_manifest: Manifest, new_manifest: Manifest) -> Self {
+        StateMigration {
+            old_manifest,
+            new_manifest,
+            migrations: HashMap::new(),
+        }
+    }
+
+    /// Registers the state transformer for actors of the given type, replacing any previously
+    /// registered one.
+    pub fn add_migration(
+        &mut self,
+        actor_type: Type,
+        migration: impl ActorMigration<BS> + 'static,
+    ) {
+        self.migrations.insert(actor_type, Box::new(migration));
+    }
+
+    /// Migrates the state tree rooted at `state_root`.
+    pub fn run(
+        &self,
+        store: &BS,
+        state_root: &Cid,
+        options: &MigrationOptions,
+    ) -> anyhow::Result<MigrationResult> {
+        let store = MigrationStore::new(store, options.dry_run);
+        let mut state_tree = StateTree::new_from_root(&store, state_root)?;
+
+        let mut actors = Vec::new();
+        state_tree.for_each(|addr, actor| {
+            actors.push((addr, actor.clone()));
+            Ok(())
+        })?;
+
+        let total = actors.len();
+        let migrated = AtomicUsize::new(0);
+        let transformed = AtomicUsize::new(0);
+        log::info!("migrating {} actors", total);
+
+        let new_actors = actors
+            .par_iter()
+            .map(|(addr, actor)| {
+                let new_actor = self
+                    .migrate_actor(&store, addr, actor)
+                    .with_context(|| format!("failed to migrate actor {}", addr))?;
+                if new_actor.state != actor.state {
+                    transformed.fetch_add(1, Ordering::Relaxed);
+                }
+
+                let migrated = migrated.fetch_add(1, Ordering::Relaxed) + 1;
+                if migrated % PROGRESS_INTERVAL == 0 || migrated == total {
+                    log::info!("migrated {}/{} actors", migrated, total);
+                    if let Some(progress) = options.progress {
+                        progress(MigrationProgress { migrated, total });
+                    }
+                }
+                Ok((addr.id()?, new_actor))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let transformed = transformed.into_inner();
+
+        if options.dry_run {
+            // Make sure all the new states have actually been written.
+            for (id, actor) in &new_actors {
+                if !store.has(&actor.state)? {
+                    return Err(anyhow!(
+                        "missing migrated state {} for actor {}",
+                        actor.state,
+                        id
+                    ));
+                }
+            }
+            return Ok(MigrationResult {
+                state_root: None,
+                actors: total,
+                transformed,
+            });
+        }
+
+        for (id, actor) in new_actors {
+            state_tree.set_actor_id(id, actor)?;
+        }
+        let state_root = state_tree.flush()?;
+        log::info!("migrated state tree to {}", state_root);
+
+        Ok(MigrationResult {
+            state_root: Some(state_root),
+            actors: total,
+            transformed,
+        })
+    }
+
+    /// Migrates a single actor, returning the new actor.
+    fn migrate_actor(
+        &self,
+        store: &MigrationStore<BS>,
+        addr: &Address,
+        actor: &ActorState,
+    ) -> anyhow::Result<ActorState> {
+        let actor_type = match self.old_manifest.get_by_left(&actor.code) {
+            Some(typ) => *typ,
+            // User-deployed actors aren't part of the bundle.
+            None => return Ok(actor.clone()),
+        };
+        let new_code = *self
+            .new_manifest
+            .get_by_right(&actor_type)
+            .ok_or_else(|| anyhow!("new actor bundle has no {:?} actor", actor_type))?;
+
+        let state = match self.migrations.get(&actor_type) {
+            Some(migration) => migration.migrate_state(
+                store,
+                &ActorMigrationInput {
+                    address: *addr,
+                    actor_type,
+                    actor: actor.clone(),
+                    new_code,
+                },
+            )?,
+            None => actor.state,
+        };
+
+        Ok(ActorState {
+            code: new_code,
+            state,
+            ..actor.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use fvm_shared::blockstore::CborStore;
+    use fvm_shared::encoding::{to_vec, DAG_CBOR};
+    use fvm_shared::state::StateTreeVersion;
+    use fvm_shared::IPLD_RAW;
+    use multihash::{Code, MultihashDigest};
+    use num_traits::Zero;
+
+    use super::*;
+
+    /// Increments the actor's state, a single integer.
+    struct Increment;
+
+    impl ActorMigration<MemoryBlockstore> for Increment {
+        fn migrate_state(
+            &self,
+            store: &MigrationStore<MemoryBlockstore>,
+            input: &ActorMigrationInput,
+        ) -> anyhow::Result<Cid> {
+            let value: u64 = store.get_cbor(&input.actor.state)?.unwrap();
+            store.put_cbor(&(value + 1), Code::Blake2b256)
+        }
+    }
+
+    fn code(name: &str) -> Cid {
+        Cid::new_v1(IPLD_RAW, Code::Identity.digest(name.as_bytes()))
+    }
+
+    #[test]
+    fn migrate() {
+        let store = MemoryBlockstore::default();
+        let old_manifest: Manifest = [
+            (code("account-v1"), Type::Account),
+            (code("multisig-v1"), Type::Multisig),
+        ]
+        .into_iter()
+        .collect();
+        let new_manifest: Manifest = [
+            (code("account-v2"), Type::Account),
+            (code("multisig-v2"), Type::Multisig),
+        ]
+        .into_iter()
+        .collect();
+
+        let state = store.put_cbor(&1u64, Code::Blake2b256).unwrap();
+        let root = {
+            let mut tree = StateTree::new(&store, StateTreeVersion::V4).unwrap();
+            for (id, code) in [
+                (100, code("account-v1")),
+                (101, code("multisig-v1")),
+                (102, code("user")),
+            ] {
+                tree.set_actor_id(id, ActorState::new(code, state, Zero::zero(), 0))
+                    .unwrap();
+            }
+            tree.flush().unwrap()
+        };
+
+        let mut migration = StateMigration::new(old_manifest, new_manifest);
+        migration.add_migration(Type::Multisig, Increment);
+
+        let dry_run = migration
+            .run(
+                &store,
+                &root,
+                &MigrationOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(dry_run.state_root, None);
+        assert_eq!(dry_run.transformed, 1);
+        // The migrated state was discarded.
+        let migrated_state =
+            Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&to_vec(&2u64).unwrap()));
+        assert!(!store.has(&migrated_state).unwrap());
+
+        let progress = Mutex::new(Vec::new());
+        let report = |p: MigrationProgress| progress.lock().unwrap().push(p);
+        let result = migration
+            .run(
+                &store,
+                &root,
+                &MigrationOptions {
+                    dry_run: false,
+                    progress: Some(&report),
+                },
+            )
+            .unwrap();
+        assert_eq!(result.actors, 3);
+        assert_eq!(
+            progress.into_inner().unwrap(),
+            vec![MigrationProgress {
+                migrated: 3,
+                total: 3
+            }]
+        );
+
+        let tree = StateTree::new_from_root(&store, &result.state_root.unwrap()).unwrap();
+        let account = tree.get_actor_id(100).unwrap().unwrap();
+        assert_eq!(account.code, code("account-v2"));
+        assert_eq!(account.state, state);
+        let multisig = tree.get_actor_id(101).unwrap().unwrap();
+        assert_eq!(multisig.code, code("multisig-v2"));
+        assert_eq!(multisig.state, migrated_state);
+        assert_eq!(store.get_cbor::<u64>(&multisig.state).unwrap(), Some(2));
+        let user = tree.get_actor_id(102).unwrap().unwrap();
+        assert_eq!(user.code, code("user"));
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::Context;
+use fvm_shared::address::Address;
 use fvm_shared::bigint::bigint_ser;
 use fvm_shared::blockstore::{Blockstore, CborStore};
 use fvm_shared::clock::ChainEpoch;
@@ -7,13 +8,27 @@ use fvm_shared::encoding::tuple::*;
 use fvm_shared::encoding::Cbor;
 use fvm_shared::sector::{Spacetime, StoragePower};
 use fvm_shared::smooth::FilterEstimate;
-use fvm_shared::ActorID;
+use fvm_shared::{ActorID, MethodNum};
 
 use crate::kernel::{ClassifyResult, Result};
 use crate::state_tree::{ActorState, StateTree};
 
 pub const REWARD_ACTOR_ID: ActorID = 2;
 
+/// The reward actor method invoked (implicitly) to reward block producers.
+pub const AWARD_BLOCK_REWARD_METHOD: MethodNum = 2;
+
+/// Parameters for `AWARD_BLOCK_REWARD_METHOD`.
+#[derive(Serialize_tuple, Deserialize_tuple)]
+pub struct AwardBlockRewardParams {
+    pub miner: Address,
+    #[serde(with = "bigint_ser")]
+    pub penalty: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub gas_reward: TokenAmount,
+    pub win_count: i64,
+}
+
 impl Cbor for State {}
 /// Reward actor state
 #[derive(Serialize_tuple, Deserialize_tuple, Default)]
@@ -6,7 +6,7 @@ use std::collections::HashMap;
 
 use anyhow::{anyhow, Context as _};
 use cid::{multihash, Cid};
-use fvm_ipld_hamt::Hamt;
+use fvm_ipld_hamt::{Change, Hamt};
 use fvm_shared::address::{Address, Payload};
 use fvm_shared::bigint::bigint_ser;
 use fvm_shared::blockstore::{Blockstore, CborStore};
@@ -19,8 +19,8 @@ use crate::init_actor::State as InitActorState;
 use crate::kernel::{ClassifyResult, Context as _, ExecutionError, Result};
 use crate::syscall_error;
 
-/// State tree implementation using hamt. This structure is not threadsafe and should only be used
-/// in sync contexts.
+/// State tree implementation using hamt. This structure can be moved between threads (if its store
+/// can), but can't be shared between them.
 pub struct StateTree<S> {
     hamt: Hamt<S, ActorState>,
 
@@ -509,6 +509,62 @@ where
         })?;
         Ok(())
     }
+
+    /// Returns the actors that changed from this state tree to `other`. Sub-trees shared by both
+    /// state trees are skipped without being loaded.
+    ///
+    /// Only flushed state is compared: pending changes (not yet flushed) are ignored.
+    pub fn diff<S2>(&self, other: &StateTree<S2>) -> anyhow::Result<Vec<ActorChange>>
+    where
+        S2: Blockstore,
+    {
+        self.hamt
+            .diff(&other.hamt)?
+            .map(|change| {
+                Ok(match change? {
+                    Change::Added(k, actor) => ActorChange::Added {
+                        address: Address::from_bytes(&k.0)?,
+                        actor,
+                    },
+                    Change::Removed(k, actor) => ActorChange::Removed {
+                        address: Address::from_bytes(&k.0)?,
+                        actor,
+                    },
+                    Change::Modified { key, old, new } => ActorChange::Modified {
+                        address: Address::from_bytes(&key.0)?,
+                        old,
+                        new,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+/// A change to a single actor between two state trees.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ActorChange {
+    /// The actor was created.
+    Added { address: Address, actor: ActorState },
+    /// The actor was deleted.
+    Removed { address: Address, actor: ActorState },
+    /// The actor's code, state, sequence, or balance changed.
+    Modified {
+        address: Address,
+        old: ActorState,
+        new: ActorState,
+    },
+}
+
+impl ActorChange {
+    /// Returns the address of the changed actor.
+    pub fn address(&self) -> &Address {
+        match self {
+            ActorChange::Added { address, .. }
+            | ActorChange::Removed { address, .. }
+            | ActorChange::Modified { address, .. } => address,
+        }
+    }
 }
 
 /// State of all actor implementations.
@@ -646,7 +702,7 @@ mod tests {
 
     use crate::init_actor;
     use crate::init_actor::INIT_ACTOR_ADDR;
-    use crate::state_tree::{ActorState, StateTree};
+    use crate::state_tree::{ActorChange, ActorState, StateTree};
 
     lazy_static! {
         pub static ref DUMMY_ACCOUNT_ACTOR_CODE_ID: Cid =
@@ -859,4 +915,53 @@ mod tests {
             assert!(err.is_fatal());
         }
     }
+
+    #[test]
+    fn diff() {
+        let store = MemoryBlockstore::default();
+        let actor = |sequence| {
+            ActorState::new(
+                *DUMMY_ACCOUNT_ACTOR_CODE_ID,
+                empty_cid(),
+                Default::default(),
+                sequence,
+            )
+        };
+
+        let mut tree = StateTree::new(&store, StateTreeVersion::V3).unwrap();
+        for id in 100..200 {
+            tree.set_actor_id(id, actor(0)).unwrap();
+        }
+        let old_root = tree.flush().unwrap();
+
+        tree.set_actor_id(100, actor(1)).unwrap();
+        tree.delete_actor_id(101).unwrap();
+        tree.set_actor_id(200, actor(0)).unwrap();
+        let new_root = tree.flush().unwrap();
+
+        let old_tree = StateTree::new_from_root(&store, &old_root).unwrap();
+        let new_tree = StateTree::new_from_root(&store, &new_root).unwrap();
+        assert_eq!(old_tree.diff(&old_tree).unwrap(), vec![]);
+
+        let mut changes = old_tree.diff(&new_tree).unwrap();
+        changes.sort_by_key(|c| c.address().id().unwrap());
+        assert_eq!(
+            changes,
+            vec![
+                ActorChange::Modified {
+                    address: Address::new_id(100),
+                    old: actor(0),
+                    new: actor(1),
+                },
+                ActorChange::Removed {
+                    address: Address::new_id(101),
+                    actor: actor(0),
+                },
+                ActorChange::Added {
+                    address: Address::new_id(200),
+                    actor: actor(0),
+                },
+            ]
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use anyhow::Context as _;
 use fvm_shared::actor::builtin::Type;
 use fvm_shared::sys;
 use num_traits::FromPrimitive;
@@ -82,6 +83,33 @@ pub fn create_actor(
     context.kernel.create_actor(typ, actor_id)
 }
 
+/// Installs the actor code in the input buffer, and writes its CID into the output buffer. Returns
+/// the length of the CID.
+pub fn install_actor(
+    context: Context<'_, impl Kernel>,
+    code_off: u32,
+    code_len: u32,
+    obuf_off: u32, // Cid
+    obuf_len: u32,
+) -> Result<u32> {
+    let code = context.memory.try_slice(code_off, code_len)?;
+    let cid = context.kernel.install_actor(code)?;
+
+    let size = super::encoded_cid_size(&cid);
+    if size > obuf_len {
+        return Err(syscall_error!(IllegalArgument;
+            "insufficient output buffer capacity; {} (cid) > {} (buffer capacity)",
+            size, obuf_len
+        )
+        .into());
+    }
+    let mut obuf = context.memory.try_slice_mut(obuf_off, size)?;
+    cid.write_bytes(&mut obuf)
+        .context("failed to write actor code cid")
+        .or_fatal()?;
+    Ok(size)
+}
+
 pub fn resolve_builtin_actor_type(
     context: Context<'_, impl Kernel>,
     code_cid_off: u32, // Cid
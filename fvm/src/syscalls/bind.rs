@@ -1,12 +1,13 @@
 use std::mem;
 
-use fvm_shared::error::ErrorNumber;
+use fvm_shared::error::{ErrorNumber, ExitCode};
 use wasmtime::{Caller, Linker, Trap, WasmTy};
 
 use super::context::Memory;
 use super::error::Abort;
 use super::{Context, InvocationData};
 use crate::call_manager::backtrace;
+use crate::call_manager::trace::{ExecutionEvent, SyscallTrace};
 use crate::kernel::{self, ExecutionError, Kernel, SyscallError};
 
 // TODO: we should consider implementing a proc macro attribute for syscall functions instead of
@@ -94,6 +95,29 @@ fn memory_and_data<'a, K: Kernel>(
     Ok((Memory::new(mem), data))
 }
 
+/// Charges for the wasm instructions executed by the actor since the last charge.
+fn charge_for_execution<K: Kernel>(caller: &mut Caller<'_, InvocationData<K>>) -> Result<(), Trap> {
+    let gas_counter = caller.data().gas_counter;
+    let gas_counter = gas_counter.and_then(|counter| counter.get(&mut *caller).i64());
+    caller
+        .data_mut()
+        .charge_for_execution(gas_counter)
+        .map_err(|e| Abort::from_error(ExitCode::SysErrIllegalActor, e).into())
+}
+
+fn trace_syscall<K: Kernel>(
+    kernel: &mut K,
+    module: &'static str,
+    function: &'static str,
+    error: Option<ErrorNumber>,
+) {
+    kernel.trace(ExecutionEvent::Syscall(SyscallTrace {
+        module,
+        function,
+        error,
+    }))
+}
+
 // Unfortunately, we can't implement this for _all_ functions. So we implement it for functions of up to 6 arguments.
 macro_rules! impl_bind_syscalls {
     ($($t:ident)*) => {
@@ -114,17 +138,20 @@ macro_rules! impl_bind_syscalls {
                 if mem::size_of::<Ret::Value>() == 0 {
                     // If we're returning a zero-sized "value", we return no value therefore and expect no out pointer.
                     self.func_wrap(module, name, move |mut caller: Caller<'_, InvocationData<K>> $(, $t: $t)*| {
+                        charge_for_execution(&mut caller)?;
                         let (mut memory, mut data) = memory_and_data(&mut caller)?;
                         let ctx = Context{kernel: &mut data.kernel, memory: &mut memory};
                         Ok(match syscall(ctx $(, $t)*).into()? {
                             Ok(_) => {
                                 log::trace!("syscall {}::{}: ok", module, name);
+                                trace_syscall(&mut data.kernel, module, name, None);
                                 data.last_error = None;
                                 0
                             },
                             Err(err) => {
                                 let code = err.1;
                                 log::trace!("syscall {}::{}: fail ({})", module, name, code as u32);
+                                trace_syscall(&mut data.kernel, module, name, Some(code));
                                 data.last_error = Some(backtrace::Cause::new(module, name, err));
                                 code as u32
                             },
@@ -133,12 +160,14 @@ macro_rules! impl_bind_syscalls {
                 } else {
                     // If we're returning an actual value, we need to write it back into the wasm module's memory.
                     self.func_wrap(module, name, move |mut caller: Caller<'_, InvocationData<K>>, ret: u32 $(, $t: $t)*| {
+                        charge_for_execution(&mut caller)?;
                         let (mut memory, mut data) = memory_and_data(&mut caller)?;
 
                         // We need to check to make sure we can store the return value _before_ we do anything.
                         if (ret as u64) > (memory.len() as u64)
                             || memory.len() - (ret as usize) < mem::size_of::<Ret::Value>() {
                             let code = ErrorNumber::IllegalArgument;
+                            trace_syscall(&mut data.kernel, module, name, Some(code));
                             data.last_error = Some(backtrace::Cause::new(module, name, SyscallError(format!("no space for return value"), code)));
                             return Ok(code as u32);
                         }
@@ -148,12 +177,14 @@ macro_rules! impl_bind_syscalls {
                             Ok(value) => {
                                 log::trace!("syscall {}::{}: ok", module, name);
                                 unsafe { *(memory.as_mut_ptr().offset(ret as isize) as *mut Ret::Value) = value };
+                                trace_syscall(&mut data.kernel, module, name, None);
                                 data.last_error = None;
                                 0
                             },
                             Err(err) => {
                                 let code = err.1;
                                 log::trace!("syscall {}::{}: fail ({})", module, name, code as u32);
+                                trace_syscall(&mut data.kernel, module, name, Some(code));
                                 data.last_error = Some(backtrace::Cause::new(module, name, err));
                                 code as u32
                             },
@@ -1,8 +1,9 @@
 use cid::Cid;
-use wasmtime::Linker;
+use wasmtime::{Global, Linker, ResourceLimiter};
 
-use crate::call_manager::backtrace;
-use crate::Kernel;
+use crate::call_manager::{backtrace, WASM_PAGE_SIZE};
+use crate::kernel::ExecutionError;
+use crate::{kernel, Kernel};
 
 pub(crate) mod error;
 
@@ -29,6 +30,16 @@ pub struct InvocationData<K> {
     /// The last-seen syscall error. This error is considered the abort "cause" if an actor aborts
     /// after receiving this error without calling any other syscalls.
     pub last_error: Option<backtrace::Cause>,
+    /// Set if the actor is user-deployed, and therefore metered. Built-in actors aren't metered.
+    pub metered: bool,
+    /// The global in which the (instrumented) code of a metered actor counts down the gas it has
+    /// left, once the actor has been instantiated.
+    pub gas_counter: Option<Global>,
+    /// The value of the gas counter when execution was last charged for.
+    pub gas_counter_charged: i64,
+    /// Set if the actor ran out of gas while growing its memory. Memory growth can't fail with an
+    /// error, so the actor is aborted at the next opportunity instead.
+    pub out_of_gas: bool,
 }
 
 impl<K> InvocationData<K> {
@@ -36,10 +47,70 @@ impl<K> InvocationData<K> {
         Self {
             kernel,
             last_error: None,
+            metered: false,
+            gas_counter: None,
+            gas_counter_charged: 0,
+            out_of_gas: false,
         }
     }
 }
 
+impl<K: Kernel> InvocationData<K> {
+    /// Charges gas for the wasm instructions executed since the last charge, given the current
+    /// value of the actor's gas counter, if any. This is a no-op for actors that aren't metered.
+    pub(crate) fn charge_for_execution(&mut self, gas_counter: Option<i64>) -> kernel::Result<()> {
+        if self.out_of_gas {
+            return Err(ExecutionError::OutOfGas);
+        }
+        let gas_counter = match gas_counter {
+            Some(gas_counter) => gas_counter,
+            None => return Ok(()),
+        };
+        // The counter drops below zero if the actor runs out of gas, in which case this charge
+        // exceeds the gas available.
+        let gas_used = self.gas_counter_charged.saturating_sub(gas_counter);
+        self.gas_counter_charged = gas_counter;
+        self.kernel.charge_gas("OnWasmExecution", gas_used)
+    }
+}
+
+impl<K: Kernel> ResourceLimiter for InvocationData<K> {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        if !self.kernel.limiter_mut().grow_memory(current, desired) {
+            return false;
+        }
+        // Only user-deployed actors pay for their memory.
+        if !self.metered {
+            return true;
+        }
+        let pages = (desired.saturating_sub(current) + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+        let charge = self.kernel.price_list().on_memory_grow(pages);
+        if self.kernel.charge_gas(charge.name, charge.total()).is_err() {
+            self.out_of_gas = true;
+            return false;
+        }
+        true
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        self.kernel.limiter_mut().grow_table(current, desired)
+    }
+
+    // Each invocation has a single instance, with at most one memory and one table.
+
+    fn instances(&self) -> usize {
+        1
+    }
+
+    fn tables(&self) -> usize {
+        1
+    }
+
+    fn memories(&self) -> usize {
+        1
+    }
+}
+
 use self::bind::BindSyscall;
 
 /// The maximum supported CID size. (SPEC_AUDIT)
@@ -79,11 +150,13 @@ pub fn bind_syscalls(
     )?;
     linker.bind("network", "version", network::version)?;
     linker.bind("network", "curr_epoch", network::curr_epoch)?;
+    linker.bind("network", "tipset_cid", network::tipset_cid)?;
 
     linker.bind("actor", "resolve_address", actor::resolve_address)?;
     linker.bind("actor", "get_actor_code_cid", actor::get_actor_code_cid)?;
     linker.bind("actor", "new_actor_address", actor::new_actor_address)?;
     linker.bind("actor", "create_actor", actor::create_actor)?;
+    linker.bind("actor", "install_actor", actor::install_actor)?;
     linker.bind(
         "actor",
         "resolve_builtin_actor_type",
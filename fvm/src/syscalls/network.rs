@@ -3,6 +3,7 @@ use fvm_shared::sys;
 
 use super::Context;
 use crate::kernel::{ClassifyResult, Kernel, Result};
+use crate::syscall_error;
 
 pub fn curr_epoch(context: Context<'_, impl Kernel>) -> Result<i64> {
     Ok(context.kernel.network_epoch())
@@ -31,3 +32,31 @@ pub fn total_fil_circ_supply(context: Context<'_, impl Kernel>) -> Result<sys::T
         .context("circulating supply exceeds u128 limit")
         .or_fatal()
 }
+
+/// Writes the CID of the tipset at the given epoch into the output buffer. Returns the length of
+/// the CID, or -1 if the tipset is unknown.
+pub fn tipset_cid(
+    context: Context<'_, impl Kernel>,
+    epoch: i64,
+    obuf_off: u32, // Cid
+    obuf_len: u32,
+) -> Result<i32> {
+    match context.kernel.tipset_cid(epoch)? {
+        Some(cid) => {
+            let size = super::encoded_cid_size(&cid);
+            if size > obuf_len {
+                return Err(syscall_error!(IllegalArgument;
+                    "insufficient output buffer capacity; {} (cid) > {} (buffer capacity)",
+                    size, obuf_len
+                )
+                .into());
+            }
+            let mut obuf = context.memory.try_slice_mut(obuf_off, size)?;
+            cid.write_bytes(&mut obuf)
+                .context("failed to write tipset cid")
+                .or_fatal()?;
+            Ok(size as i32)
+        }
+        None => Ok(-1),
+    }
+}
@@ -0,0 +1,241 @@
+//! Helpers for tests that need a machine: stub externs, and a machine over an in-memory blockstore
+//! with stub built-in actors and user-deployed actors written in the wasm text format.
+
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::actor::builtin::{Manifest, Type};
+use fvm_shared::address::Address;
+use fvm_shared::blockstore::{Blockstore, CborStore, MemoryBlockstore};
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::encoding::{to_vec, RawBytes};
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::{ActorID, MethodNum, HAMT_BIT_WIDTH, IPLD_RAW};
+use num_traits::Zero;
+
+use crate::call_manager::{CallManager, DefaultCallManager};
+use crate::executor::DefaultExecutor;
+use crate::externs::{Chain, Consensus, Externs, Rand};
+use crate::machine::{DefaultMachine, Engine, NetworkBehavior};
+use crate::state_tree::{ActorState, StateTree};
+use crate::{init_actor, Config, DefaultKernel, EMPTY_ARR_CID};
+
+pub(crate) type TestMachine = DefaultMachine<MemoryBlockstore, DummyExterns>;
+pub(crate) type TestCallManager = DefaultCallManager<TestMachine>;
+pub(crate) type TestKernel = DefaultKernel<TestCallManager>;
+pub(crate) type TestExecutor = DefaultExecutor<TestKernel>;
+
+/// The network version of test machines.
+pub(crate) const NETWORK_VERSION: NetworkVersion = NetworkVersion::V15;
+/// The epoch of test machines.
+pub(crate) const EPOCH: ChainEpoch = 100;
+
+/// The (built-in) Init actor.
+pub(crate) const INIT_ACTOR_ID: ActorID = 1;
+/// A funded (built-in) account actor, that test messages are sent from.
+pub(crate) const SENDER_ID: ActorID = 100;
+/// The gas limit of test messages.
+pub(crate) const GAS_LIMIT: i64 = 1_000_000_000;
+
+/// An actor that does nothing, successfully.
+pub(crate) const NOOP_ACTOR: &str = r#"
+    (module
+      (memory (export "memory") 1 1)
+      (func (export "invoke") (param i32) (result i32)
+        (i32.const 0)))
+"#;
+
+/// An actor that sets its state to a new block, `[1, 2]`.
+pub(crate) const SET_ROOT_ACTOR: &str = r#"
+    (module
+      (import "ipld" "create" (func $create (param i32 i64 i32 i32) (result i32)))
+      (import "ipld" "cid" (func $cid (param i32 i32 i64 i32 i32 i32) (result i32)))
+      (import "self" "set_root" (func $set_root (param i32) (result i32)))
+      (memory (export "memory") 1 1)
+      ;; The new state, in DAG-CBOR.
+      (data (i32.const 0) "\82\01\02")
+      (func (export "invoke") (param i32) (result i32)
+        ;; Create the block. Its ID is written at 16.
+        (drop (call $create (i32.const 16) (i64.const 0x71) (i32.const 0) (i32.const 3)))
+        ;; Link it with blake2b-256. Its CID is written at 32.
+        (drop (call $cid (i32.const 20) (i32.load (i32.const 16))
+          (i64.const 0xb220) (i32.const 32) (i32.const 32) (i32.const 64)))
+        (drop (call $set_root (i32.const 32)))
+        (i32.const 0)))
+"#;
+
+pub(crate) struct DummyExterns;
+
+impl Externs for DummyExterns {
+    fn chain(&self) -> Option<&dyn Chain> {
+        Some(self)
+    }
+}
+
+impl Chain for DummyExterns {
+    /// Tipset CIDs are derived from the epoch.
+    fn get_tipset_cid(&self, epoch: ChainEpoch) -> anyhow::Result<Option<Cid>> {
+        Ok(Some(tipset_cid(epoch)))
+    }
+}
+
+impl Rand for DummyExterns {
+    fn get_chain_randomness(
+        &self,
+        _pers: fvm_shared::crypto::randomness::DomainSeparationTag,
+        _round: fvm_shared::clock::ChainEpoch,
+        _entropy: &[u8],
+    ) -> anyhow::Result<[u8; 32]> {
+        todo!()
+    }
+
+    fn get_beacon_randomness(
+        &self,
+        _pers: fvm_shared::crypto::randomness::DomainSeparationTag,
+        _round: fvm_shared::clock::ChainEpoch,
+        _entropy: &[u8],
+    ) -> anyhow::Result<[u8; 32]> {
+        todo!()
+    }
+}
+
+impl Consensus for DummyExterns {
+    fn verify_consensus_fault(
+        &self,
+        _h1: &[u8],
+        _h2: &[u8],
+        _extra: &[u8],
+    ) -> anyhow::Result<(Option<fvm_shared::consensus::ConsensusFault>, i64)> {
+        todo!()
+    }
+}
+
+/// The CID [`DummyExterns`] returns for the tipset at the given epoch.
+pub(crate) fn tipset_cid(epoch: ChainEpoch) -> Cid {
+    Cid::new_v1(IPLD_RAW, Code::Identity.digest(&epoch.to_be_bytes()))
+}
+
+/// Assembles a module written in the wasm text format.
+pub(crate) fn wasm(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).expect("invalid wat")
+}
+
+/// Returns the CID of some actor code (a raw block).
+pub(crate) fn code_cid(wasm: &[u8]) -> Cid {
+    Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(wasm))
+}
+
+/// Returns the code CID of a stub built-in actor. The stubs are empty modules, so they can't be
+/// invoked.
+pub(crate) fn builtin_code(typ: Type) -> Cid {
+    code_cid(&builtin_wasm(typ))
+}
+
+fn builtin_wasm(typ: Type) -> Vec<u8> {
+    // Make sure each actor type gets a different CID.
+    wasm(&format!("(module (global i32 (i32.const {})))", typ as i32))
+}
+
+/// Returns a configuration for test machines, which don't require any built-in actors.
+pub(crate) fn test_config() -> Config {
+    let mut config = Config::default();
+    config.network_behaviors.insert(
+        NETWORK_VERSION,
+        NetworkBehavior {
+            builtin_actors: Vec::new(),
+            ..NetworkBehavior::for_version(NETWORK_VERSION).unwrap()
+        },
+    );
+    config
+}
+
+/// Creates a machine (see [`test_config`]) whose state tree contains:
+///
+/// - the Init actor (with no address mappings), the reward and burnt funds actors, and a funded
+///   account to send messages from ([`SENDER_ID`]), all running stub built-in code;
+/// - a user-deployed actor per entry in `actors`, running the given wasm code.
+pub(crate) fn new_machine(config: Config, actors: &[(ActorID, &[u8])]) -> TestMachine {
+    let bs = MemoryBlockstore::default();
+    bs.put_keyed(&EMPTY_ARR_CID, &to_vec::<[(); 0]>(&[]).unwrap())
+        .unwrap();
+
+    let mut manifest = Manifest::new();
+    for typ in [Type::Init, Type::Account] {
+        let wasm = builtin_wasm(typ);
+        let code = code_cid(&wasm);
+        bs.put_keyed(&code, &wasm).unwrap();
+        manifest.insert(code, typ);
+    }
+    let manifest_cid = bs.put_cbor(&manifest, Code::Blake2b256).unwrap();
+
+    let address_map = Hamt::<_, ActorID>::new_with_bit_width(&bs, HAMT_BIT_WIDTH)
+        .flush()
+        .unwrap();
+    let init_state = init_actor::State {
+        address_map,
+        // Leave room for the tests' actors.
+        next_id: 10_000,
+        network_name: "test".into(),
+    };
+    let init_state = bs.put_cbor(&init_state, Code::Blake2b256).unwrap();
+
+    let mut st = StateTree::new(bs, StateTreeVersion::V4).unwrap();
+    let builtin = |typ, balance| ActorState::new(builtin_code(typ), *EMPTY_ARR_CID, balance, 0);
+    st.set_actor_id(
+        INIT_ACTOR_ID,
+        ActorState::new(builtin_code(Type::Init), init_state, Zero::zero(), 0),
+    )
+    .unwrap();
+    for id in [2, 99] {
+        st.set_actor_id(id, builtin(Type::Account, Zero::zero()))
+            .unwrap();
+    }
+    let balance = TokenAmount::from(10u128.pow(24));
+    st.set_actor_id(SENDER_ID, builtin(Type::Account, balance))
+        .unwrap();
+    for (id, wasm) in actors {
+        let code = code_cid(wasm);
+        st.store().put_keyed(&code, wasm).unwrap();
+        st.set_actor_id(*id, ActorState::new(code, *EMPTY_ARR_CID, Zero::zero(), 0))
+            .unwrap();
+    }
+    let root = st.flush().unwrap();
+
+    DefaultMachine::new(
+        config,
+        Engine::default(),
+        EPOCH,
+        TokenAmount::from(100),
+        Zero::zero(),
+        NETWORK_VERSION,
+        root,
+        manifest_cid,
+        st.consume(),
+        DummyExterns,
+    )
+    .unwrap()
+}
+
+/// Creates a call manager for a message sent by [`SENDER_ID`].
+pub(crate) fn new_call_manager(machine: TestMachine) -> TestCallManager {
+    TestCallManager::new(machine, GAS_LIMIT, Address::new_id(SENDER_ID), 0)
+}
+
+/// Returns a message from [`SENDER_ID`] to the given actor.
+pub(crate) fn message(to: ActorID, method: MethodNum) -> Message {
+    Message {
+        version: 0,
+        from: Address::new_id(SENDER_ID),
+        to: Address::new_id(to),
+        sequence: 0,
+        value: Zero::zero(),
+        method_num: method,
+        params: RawBytes::default(),
+        gas_limit: GAS_LIMIT,
+        gas_fee_cap: TokenAmount::from(100),
+        gas_premium: TokenAmount::from(1),
+    }
+}
@@ -10,6 +10,8 @@ use fvm_shared::encoding::ser::Serialize;
 use itertools::sorted;
 
 use super::ValueMut;
+use crate::diff::{Diff, Side};
+use crate::iter::Iter;
 use crate::node::{CollapsedNode, Link};
 use crate::{
     init_sized_vec, nodes_for_height, Error, Node, Root, DEFAULT_BIT_WIDTH, MAX_HEIGHT, MAX_INDEX,
@@ -315,6 +317,126 @@ where
             .map(|_| ())
     }
 
+    /// Returns a lazy iterator over the changes from this AMT to `other`, in index order.
+    ///
+    /// Both AMTs are walked node-by-node and linked sub-trees with identical CIDs are skipped
+    /// without being loaded, so the cost is proportional to the size of the difference rather
+    /// than the size of the AMTs. Both AMTs must have the same bit width.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_amt::{Amt, Change};
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut a: Amt<String, _> = Amt::new(&store);
+    /// a.set(1, "One".to_owned()).unwrap();
+    /// a.set(4, "Four".to_owned()).unwrap();
+    ///
+    /// let mut b: Amt<String, _> = Amt::new(&store);
+    /// b.set(4, "Quatre".to_owned()).unwrap();
+    /// b.set(100, "Hundred".to_owned()).unwrap();
+    ///
+    /// assert_eq!(
+    ///     a.diff(&b).unwrap().collect::<Result<Vec<_>, _>>().unwrap(),
+    ///     vec![
+    ///         Change::Removed(1, "One".to_owned()),
+    ///         Change::Modified {
+    ///             index: 4,
+    ///             old: "Four".to_owned(),
+    ///             new: "Quatre".to_owned(),
+    ///         },
+    ///         Change::Added(100, "Hundred".to_owned()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff<'a, S>(&'a self, other: &'a Amt<V, S>) -> Result<Diff<'a, V, BS, S>, Error>
+    where
+        V: PartialEq + Clone,
+        S: Blockstore,
+    {
+        if self.bit_width() != other.bit_width() {
+            return Err(anyhow!(
+                "cannot diff AMTs with different bit widths ({} and {})",
+                self.bit_width(),
+                other.bit_width()
+            )
+            .into());
+        }
+        Ok(Diff::new(
+            Side {
+                node: &self.root.node,
+                height: self.height(),
+                store: &self.block_store,
+            },
+            Side {
+                node: &other.root.node,
+                height: other.height(),
+                store: &other.block_store,
+            },
+            self.bit_width(),
+        ))
+    }
+
+    /// Returns a lazy iterator over the values of the Amt, in index order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_amt::Amt;
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Amt<String, _> = Amt::new(&store);
+    /// map.set(1, "One".to_owned()).unwrap();
+    /// map.set(4, "Four".to_owned()).unwrap();
+    ///
+    /// let values: Vec<(u64, &String)> = map.iter().collect::<Result<_, _>>().unwrap();
+    /// assert_eq!(values, [(1, &"One".to_owned()), (4, &"Four".to_owned())]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, V, BS> {
+        Iter::new(
+            &self.block_store,
+            &self.root.node,
+            self.height(),
+            self.bit_width(),
+        )
+    }
+
+    /// Returns a lazy iterator over the values of the Amt at or after index `start`, in index
+    /// order. Only the nodes on the path to `start` are loaded up front.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_amt::Amt;
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Amt<u64, _> = Amt::new(&store);
+    /// map.batch_set(0..100).unwrap();
+    ///
+    /// // Values 10 through 19.
+    /// let page: Vec<(u64, &u64)> = map
+    ///     .iter_from(10)
+    ///     .unwrap()
+    ///     .take(10)
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    /// assert_eq!(page.first(), Some(&(10, &10)));
+    /// assert_eq!(page.last(), Some(&(19, &19)));
+    /// ```
+    pub fn iter_from(&self, start: u64) -> Result<Iter<'_, V, BS>, Error> {
+        Iter::new_from(
+            &self.block_store,
+            &self.root.node,
+            self.height(),
+            self.bit_width(),
+            start,
+        )
+    }
+
     /// Iterates over each value in the Amt and runs a function on the values that allows modifying
     /// each value.
     pub fn for_each_mut<F>(&mut self, mut f: F) -> Result<(), Error>
@@ -389,3 +511,16 @@ where
         }
     }
 }
+
+impl<'a, V, BS> IntoIterator for &'a Amt<V, BS>
+where
+    V: DeserializeOwned + Serialize,
+    BS: Blockstore,
+{
+    type Item = Result<(u64, &'a V), Error>;
+    type IntoIter = Iter<'a, V, BS>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
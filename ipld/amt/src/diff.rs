@@ -0,0 +1,290 @@
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+
+use fvm_shared::blockstore::Blockstore;
+use serde::de::DeserializeOwned;
+
+use crate::iter::Iter;
+use crate::node::Link;
+use crate::{nodes_for_height, Error, Node};
+
+/// A change to a single index between two AMTs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<V> {
+    /// A value was added at the index.
+    Added(u64, V),
+    /// The value at the index was removed.
+    Removed(u64, V),
+    /// The value at the index changed.
+    Modified { index: u64, old: V, new: V },
+}
+
+impl<V> Change<V> {
+    /// Returns the index that changed.
+    pub fn index(&self) -> u64 {
+        match self {
+            Change::Added(i, _) | Change::Removed(i, _) => *i,
+            Change::Modified { index, .. } => *index,
+        }
+    }
+}
+
+/// One side of a diff: a node, its height, and the store to load its children from.
+pub(crate) struct Side<'a, V, S> {
+    pub node: &'a Node<V>,
+    pub height: u32,
+    pub store: &'a S,
+}
+
+impl<'a, V, S> Clone for Side<'a, V, S> {
+    fn clone(&self) -> Self {
+        Side { ..*self }
+    }
+}
+
+impl<'a, V, S> Copy for Side<'a, V, S> {}
+
+/// A lazy iterator over the changes between two AMTs, in index order (see
+/// [`Amt::diff`](crate::Amt::diff)). Child nodes are loaded from the blockstores as they're
+/// reached, and linked sub-trees with identical CIDs are skipped without being loaded.
+///
+/// After yielding an error, the iterator is exhausted.
+pub struct Diff<'a, V, S1, S2> {
+    bit_width: u32,
+    /// The work left to do, next task last.
+    stack: Vec<Task<'a, V, S1, S2>>,
+    /// Changes found, but not yet yielded.
+    ready: VecDeque<Change<V>>,
+}
+
+enum Task<'a, V, S1, S2> {
+    /// Diff two nodes covering the same range of indices, starting at `offset`.
+    Nodes(Side<'a, V, S1>, Side<'a, V, S2>, u64),
+    /// Diff the sub-trees behind two links at the same position, either of which may be missing.
+    /// The sub-trees have the given height, and start at `offset`.
+    Links {
+        old: Option<(&'a Link<V>, &'a S1)>,
+        new: Option<(&'a Link<V>, &'a S2)>,
+        height: u32,
+        offset: u64,
+    },
+    /// Every remaining value of an old sub-tree was removed.
+    Removed(Iter<'a, V, S1>),
+    /// Every remaining value of a new sub-tree was added.
+    Added(Iter<'a, V, S2>),
+}
+
+impl<'a, V, S1, S2> Diff<'a, V, S1, S2>
+where
+    V: PartialEq + Clone + DeserializeOwned,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+    pub(crate) fn new(old: Side<'a, V, S1>, new: Side<'a, V, S2>, bit_width: u32) -> Self {
+        Diff {
+            bit_width,
+            stack: vec![Task::Nodes(old, new, 0)],
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Breaks down the next task, either into smaller tasks or into changes.
+    fn step(&mut self, task: Task<'a, V, S1, S2>) -> Result<(), Error> {
+        let bit_width = self.bit_width;
+        match task {
+            // If one side is taller, only its first child overlaps with the other side; everything
+            // else was either added or removed.
+            Task::Nodes(old, new, offset) if old.height > new.height => {
+                let links = links(old.node);
+                let nfh = nodes_for_height(bit_width, old.height);
+                for (i, link) in links.iter().enumerate().skip(1).rev() {
+                    self.stack.push(Task::Links {
+                        old: link.as_ref().map(|link| (link, old.store)),
+                        new: None,
+                        height: old.height - 1,
+                        offset: offset + i as u64 * nfh,
+                    });
+                }
+                self.stack.push(match &links[0] {
+                    Some(first) => {
+                        let first = first.load(old.store, bit_width)?;
+                        let old = Side {
+                            node: first,
+                            height: old.height - 1,
+                            ..old
+                        };
+                        Task::Nodes(old, new, offset)
+                    }
+                    None => Task::Added(iter(new, bit_width, offset)),
+                });
+            }
+            Task::Nodes(old, new, offset) if new.height > old.height => {
+                let links = links(new.node);
+                let nfh = nodes_for_height(bit_width, new.height);
+                for (i, link) in links.iter().enumerate().skip(1).rev() {
+                    self.stack.push(Task::Links {
+                        old: None,
+                        new: link.as_ref().map(|link| (link, new.store)),
+                        height: new.height - 1,
+                        offset: offset + i as u64 * nfh,
+                    });
+                }
+                self.stack.push(match &links[0] {
+                    Some(first) => {
+                        let first = first.load(new.store, bit_width)?;
+                        let new = Side {
+                            node: first,
+                            height: new.height - 1,
+                            ..new
+                        };
+                        Task::Nodes(old, new, offset)
+                    }
+                    None => Task::Removed(iter(old, bit_width, offset)),
+                });
+            }
+            Task::Nodes(old, new, offset) => match (old.node, new.node) {
+                (Node::Leaf { vals: old_vals }, Node::Leaf { vals: new_vals }) => {
+                    for (i, (old_v, new_v)) in (0..).zip(old_vals.iter().zip(new_vals)) {
+                        let index = offset + i;
+                        match (old_v, new_v) {
+                            (Some(old_v), Some(new_v)) if old_v != new_v => {
+                                self.ready.push_back(Change::Modified {
+                                    index,
+                                    old: old_v.clone(),
+                                    new: new_v.clone(),
+                                })
+                            }
+                            (Some(old_v), None) => {
+                                self.ready.push_back(Change::Removed(index, old_v.clone()))
+                            }
+                            (None, Some(new_v)) => {
+                                self.ready.push_back(Change::Added(index, new_v.clone()))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                (Node::Link { links: old_links }, Node::Link { links: new_links }) => {
+                    let nfh = nodes_for_height(bit_width, old.height);
+                    let pairs = old_links.iter().zip(new_links).enumerate();
+                    for (i, (old_l, new_l)) in pairs.rev() {
+                        self.stack.push(Task::Links {
+                            old: old_l.as_ref().map(|link| (link, old.store)),
+                            new: new_l.as_ref().map(|link| (link, new.store)),
+                            height: old.height - 1,
+                            offset: offset + i as u64 * nfh,
+                        });
+                    }
+                }
+                _ => return Err(Error::Dynamic(anyhow::anyhow!("malformed AMT node"))),
+            },
+            Task::Links {
+                old,
+                new,
+                height,
+                offset,
+            } => {
+                if let (Some((Link::Cid { cid: a, .. }, _)), Some((Link::Cid { cid: b, .. }, _))) =
+                    (old, new)
+                {
+                    if a == b {
+                        return Ok(());
+                    }
+                }
+                let old = old
+                    .map(|(link, store)| -> Result<_, Error> {
+                        let node = link.load(store, bit_width)?;
+                        Ok(Side {
+                            node,
+                            height,
+                            store,
+                        })
+                    })
+                    .transpose()?;
+                let new = new
+                    .map(|(link, store)| -> Result<_, Error> {
+                        let node = link.load(store, bit_width)?;
+                        Ok(Side {
+                            node,
+                            height,
+                            store,
+                        })
+                    })
+                    .transpose()?;
+                match (old, new) {
+                    (Some(old), Some(new)) => self.stack.push(Task::Nodes(old, new, offset)),
+                    (Some(old), None) => {
+                        self.stack.push(Task::Removed(iter(old, bit_width, offset)))
+                    }
+                    (None, Some(new)) => self.stack.push(Task::Added(iter(new, bit_width, offset))),
+                    (None, None) => {}
+                }
+            }
+            Task::Removed(mut iter) => {
+                if let Some(value) = iter.next() {
+                    let (index, v) = value?;
+                    self.ready.push_back(Change::Removed(index, v.clone()));
+                    self.stack.push(Task::Removed(iter));
+                }
+            }
+            Task::Added(mut iter) => {
+                if let Some(value) = iter.next() {
+                    let (index, v) = value?;
+                    self.ready.push_back(Change::Added(index, v.clone()));
+                    self.stack.push(Task::Added(iter));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, V, S1, S2> Iterator for Diff<'a, V, S1, S2>
+where
+    V: PartialEq + Clone + DeserializeOwned,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+    type Item = Result<Change<V>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.ready.pop_front() {
+                return Some(Ok(change));
+            }
+            let task = self.stack.pop()?;
+            if let Err(e) = self.step(task) {
+                self.stack.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, V, S1, S2> FusedIterator for Diff<'a, V, S1, S2>
+where
+    V: PartialEq + Clone + DeserializeOwned,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+}
+
+fn links<V>(node: &Node<V>) -> &[Option<Link<V>>] {
+    match node {
+        Node::Link { links } => links,
+        Node::Leaf { .. } => unreachable!("non zero height cannot be a leaf node"),
+    }
+}
+
+/// Returns a lazy iterator over the values under the given node, whose first value has index
+/// `offset`.
+fn iter<'a, V, S>(side: Side<'a, V, S>, bit_width: u32, offset: u64) -> Iter<'a, V, S>
+where
+    V: DeserializeOwned,
+    S: Blockstore,
+{
+    Iter::new_at(side.store, side.node, side.height, bit_width, offset)
+}
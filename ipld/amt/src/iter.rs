@@ -0,0 +1,181 @@
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::iter::FusedIterator;
+
+use fvm_shared::blockstore::Blockstore;
+use serde::de::DeserializeOwned;
+
+use crate::node::Link;
+use crate::{nodes_for_height, Error, Node};
+
+/// A lazy iterator over the values of an [`Amt`](crate::Amt), in index order. Child nodes are
+/// loaded from the blockstore as they're reached.
+///
+/// After yielding an error, the iterator is exhausted.
+pub struct Iter<'a, V, BS> {
+    store: &'a BS,
+    bit_width: u32,
+    /// The link nodes on the path to the current leaf, innermost last.
+    stack: Vec<Frame<'a, V>>,
+    /// The current leaf: its values, the position of the next value, and the index of its first
+    /// value.
+    leaf: Option<(&'a [Option<V>], usize, u64)>,
+}
+
+struct Frame<'a, V> {
+    links: &'a [Option<Link<V>>],
+    /// The position of the next link to visit.
+    pos: usize,
+    height: u32,
+    /// The index of the first value under this node.
+    offset: u64,
+}
+
+impl<'a, V, BS> Iter<'a, V, BS>
+where
+    V: DeserializeOwned,
+    BS: Blockstore,
+{
+    pub(crate) fn new(store: &'a BS, root: &'a Node<V>, height: u32, bit_width: u32) -> Self {
+        Self::new_at(store, root, height, bit_width, 0)
+    }
+
+    /// Creates an iterator over the values under a node of the given height, whose first value
+    /// has index `offset`.
+    pub(crate) fn new_at(
+        store: &'a BS,
+        node: &'a Node<V>,
+        height: u32,
+        bit_width: u32,
+        offset: u64,
+    ) -> Self {
+        let mut iter = Iter {
+            store,
+            bit_width,
+            stack: Vec::new(),
+            leaf: None,
+        };
+        match node {
+            Node::Leaf { vals } => iter.leaf = Some((&vals[..], 0, offset)),
+            Node::Link { links } => iter.stack.push(Frame {
+                links,
+                pos: 0,
+                height,
+                offset,
+            }),
+        }
+        iter
+    }
+
+    /// Creates an iterator starting at index `start`, descending from the root to the first leaf
+    /// that may contain it.
+    pub(crate) fn new_from(
+        store: &'a BS,
+        root: &'a Node<V>,
+        height: u32,
+        bit_width: u32,
+        start: u64,
+    ) -> Result<Self, Error> {
+        let mut iter = Iter {
+            store,
+            bit_width,
+            stack: Vec::new(),
+            leaf: None,
+        };
+        if start >= nodes_for_height(bit_width, height + 1) {
+            return Ok(iter);
+        }
+
+        let (mut node, mut height, mut offset) = (root, height, 0);
+        loop {
+            match node {
+                Node::Leaf { vals } => {
+                    iter.leaf = Some((&vals[..], (start - offset) as usize, offset));
+                    return Ok(iter);
+                }
+                Node::Link { links } => {
+                    let nfh = nodes_for_height(bit_width, height);
+                    let i = ((start - offset) / nfh) as usize;
+                    iter.stack.push(Frame {
+                        links,
+                        pos: i + 1,
+                        height,
+                        offset,
+                    });
+                    match &links[i] {
+                        Some(link) => {
+                            node = link.load(store, bit_width)?;
+                            height -= 1;
+                            offset += i as u64 * nfh;
+                        }
+                        // Nothing at or after `start` under this link; continue with the next.
+                        None => return Ok(iter),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, V, BS> Iterator for Iter<'a, V, BS>
+where
+    V: DeserializeOwned,
+    BS: Blockstore,
+{
+    type Item = Result<(u64, &'a V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((vals, pos, offset)) = &mut self.leaf {
+                let vals: &'a [Option<V>] = *vals;
+                while *pos < vals.len() {
+                    let i = *pos;
+                    *pos += 1;
+                    if let Some(v) = &vals[i] {
+                        return Some(Ok((*offset + i as u64, v)));
+                    }
+                }
+                self.leaf = None;
+            }
+
+            let frame = self.stack.last_mut()?;
+            let i = frame.pos;
+            let link = match frame.links.get(i) {
+                Some(Some(link)) => link,
+                Some(None) => {
+                    frame.pos += 1;
+                    continue;
+                }
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            frame.pos += 1;
+            let height = frame.height - 1;
+            let offset = frame.offset + i as u64 * nodes_for_height(self.bit_width, frame.height);
+
+            match link.load(self.store, self.bit_width) {
+                Ok(Node::Leaf { vals }) => self.leaf = Some((&vals[..], 0, offset)),
+                Ok(Node::Link { links }) => self.stack.push(Frame {
+                    links,
+                    pos: 0,
+                    height,
+                    offset,
+                }),
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, V, BS> FusedIterator for Iter<'a, V, BS>
+where
+    V: DeserializeOwned,
+    BS: Blockstore,
+{
+}
@@ -7,13 +7,17 @@
 //! https://github.com/ipld/specs/blob/51fab05b4fe4930d3d851d50cc1e5f1a02092deb/data-structures/vector.md
 
 mod amt;
+mod diff;
 mod error;
+mod iter;
 mod node;
 mod root;
 mod value_mut;
 
 pub use self::amt::Amt;
+pub use self::diff::{Change, Diff};
 pub use self::error::Error;
+pub use self::iter::Iter;
 pub(crate) use self::node::Node;
 pub(crate) use self::root::Root;
 pub use self::value_mut::ValueMut;
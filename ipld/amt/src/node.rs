@@ -58,6 +58,27 @@ where
 
 impl<V> Eq for Link<V> where V: Eq {}
 
+impl<V> Link<V>
+where
+    V: DeserializeOwned,
+{
+    /// Returns the linked node, loading (and caching) it if necessary.
+    pub(super) fn load<DB: Blockstore>(&self, bs: &DB, bit_width: u32) -> Result<&Node<V>, Error> {
+        match self {
+            Link::Cid { cid, cache } => {
+                let node = cache.get_or_try_init(|| {
+                    bs.get_cbor::<CollapsedNode<V>>(cid)?
+                        .ok_or_else(|| Error::CidNotFound(cid.to_string()))?
+                        .expand(bit_width)
+                        .map(Box::new)
+                })?;
+                Ok(&**node)
+            }
+            Link::Dirty(node) => Ok(&**node),
+        }
+    }
+}
+
 impl<V> From<Cid> for Link<V> {
     fn from(cid: Cid) -> Link<V> {
         Link::Cid {
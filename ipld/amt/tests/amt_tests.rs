@@ -3,7 +3,7 @@
 
 use std::fmt::Debug;
 
-use fvm_ipld_amt::{Amt, Error, MAX_INDEX};
+use fvm_ipld_amt::{Amt, Change, Error, MAX_INDEX};
 use fvm_shared::blockstore::tracking::{BSStats, TrackingBlockstore};
 use fvm_shared::blockstore::Blockstore;
 use fvm_shared::encoding::de::DeserializeOwned;
@@ -421,6 +421,116 @@ fn delete_bug_test() {
     assert_eq!(*db.stats.borrow(), BSStats {r:0, w:2, br:0, bw:18});
 }
 
+#[test]
+fn iter() {
+    let mem = fvm_shared::blockstore::MemoryBlockstore::default();
+    let mut a = Amt::new(&mem);
+    let indexes: Vec<u64> = (0..10_000).filter(|i| (i + 1) % 3 == 0).collect();
+    for &i in &indexes {
+        a.set(i, i).unwrap();
+    }
+
+    fn collect<'a>(iter: impl Iterator<Item = Result<(u64, &'a u64), Error>>) -> Vec<u64> {
+        iter.map(|r| {
+            r.map(|(i, v)| {
+                assert_eq!(i, *v);
+                i
+            })
+        })
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    // Iterate over the dirty AMT, then again after flushing and reloading.
+    assert_eq!(collect(a.iter()), indexes);
+    let c = a.flush().unwrap();
+    let db = TrackingBlockstore::new(&mem);
+    let a: Amt<u64, _> = Amt::load(&c, &db).unwrap();
+
+    // The iterator is lazy.
+    assert_eq!(a.iter().next().unwrap().unwrap(), (2, &2));
+    assert!(db.stats.borrow().r <= a.height() as usize + 1);
+    assert_eq!(collect((&a).into_iter()), indexes);
+
+    // Ranges start at the given index, whether or not it's set.
+    assert_eq!(collect(a.iter_from(0).unwrap()), indexes);
+    assert_eq!(collect(a.iter_from(5000).unwrap()), &indexes[1666..]);
+    assert_eq!(collect(a.iter_from(5001).unwrap()), &indexes[1667..]);
+    assert_eq!(
+        collect(a.iter_from(5000).unwrap().take(3)),
+        [5000, 5003, 5006]
+    );
+    assert!(collect(a.iter_from(10_000).unwrap()).is_empty());
+    assert!(collect(a.iter_from(MAX_INDEX).unwrap()).is_empty());
+}
+
+#[test]
+fn diff() {
+    let mem = fvm_shared::blockstore::MemoryBlockstore::default();
+
+    let mut a = Amt::new(&mem);
+    a.batch_set(0..10_000u64).unwrap();
+    let a_root = a.flush().unwrap();
+
+    let mut b: Amt<u64, _> = Amt::load(&a_root, &mem).unwrap();
+    b.set(5, 500).unwrap();
+    b.delete(6).unwrap();
+    // Grows the AMT by a level.
+    b.set(100_000, 100_000).unwrap();
+    let b_root = b.flush().unwrap();
+
+    let db = TrackingBlockstore::new(&mem);
+    let a: Amt<u64, _> = Amt::load(&a_root, &db).unwrap();
+    let same: Amt<u64, _> = Amt::load(&a_root, &db).unwrap();
+    assert!(a.diff(&same).unwrap().next().is_none());
+    assert_eq!(db.stats.borrow().r, 2);
+
+    let b: Amt<u64, _> = Amt::load(&b_root, &db).unwrap();
+    assert!(b.height() > a.height());
+    let expected = vec![
+        Change::Modified {
+            index: 5,
+            old: 5,
+            new: 500,
+        },
+        Change::Removed(6, 6),
+        Change::Added(100_000, 100_000),
+    ];
+    let changes: Vec<_> = a.diff(&b).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(changes, expected);
+
+    // Only the paths to the changed indices are loaded.
+    let diff_reads = db.stats.borrow().r;
+    let full_db = TrackingBlockstore::new(&mem);
+    let full: Amt<u64, _> = Amt::load(&a_root, &full_db).unwrap();
+    full.for_each(|_, _| Ok(())).unwrap();
+    assert!(diff_reads * 10 < full_db.stats.borrow().r);
+
+    // Diffing the other way around reverses the changes.
+    assert_eq!(
+        b.diff(&a).unwrap().collect::<Result<Vec<_>, _>>().unwrap(),
+        vec![
+            Change::Modified {
+                index: 5,
+                old: 500,
+                new: 5,
+            },
+            Change::Added(6, 6),
+            Change::Removed(100_000, 100_000),
+        ]
+    );
+
+    // Diffing against an empty AMT yields everything, lazily: finding the first change only loads
+    // the path to it.
+    let db = TrackingBlockstore::new(&mem);
+    let a: Amt<u64, _> = Amt::load(&a_root, &db).unwrap();
+    let empty: Amt<u64, _> = Amt::new(&db);
+    let mut added = empty.diff(&a).unwrap();
+    assert_eq!(added.next().unwrap().unwrap(), Change::Added(0, 0));
+    assert!(db.stats.borrow().r <= 1 + a.height() as usize);
+    assert_eq!(added.count(), 9_999);
+}
+
 fn tbytes(bz: &[u8]) -> BytesDe {
     BytesDe(bz.to_vec())
 }
@@ -0,0 +1,237 @@
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+
+use forest_hash_utils::BytesKey;
+use fvm_shared::blockstore::Blockstore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::iter::Iter;
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::{Error, Hash, HashAlgorithm, Sha256};
+
+/// A change to a single key between two HAMTs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<K, V> {
+    /// The key was added with the given value.
+    Added(K, V),
+    /// The key was removed; this was its value.
+    Removed(K, V),
+    /// The key's value changed.
+    Modified { key: K, old: V, new: V },
+}
+
+impl<K, V> Change<K, V> {
+    /// Returns the key that changed.
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Added(k, _) | Change::Removed(k, _) => k,
+            Change::Modified { key, .. } => key,
+        }
+    }
+}
+
+/// A lazy iterator over the changes between two HAMTs, in hash order (see
+/// [`Hamt::diff`](crate::Hamt::diff)). Child nodes are loaded from the blockstores as they're
+/// reached, and linked sub-trees with identical CIDs are skipped without being loaded.
+///
+/// After yielding an error, the iterator is exhausted.
+pub struct Diff<'a, S1, S2, V, K = BytesKey, H = Sha256> {
+    old_store: &'a S1,
+    new_store: &'a S2,
+    bit_width: u32,
+    /// The work left to do, next task last.
+    stack: Vec<Task<'a, S1, S2, V, K, H>>,
+    /// Changes found, but not yet yielded.
+    ready: VecDeque<Change<K, V>>,
+}
+
+enum Task<'a, S1, S2, V, K, H> {
+    /// Diff two nodes at the same depth.
+    Nodes(&'a Node<K, V, H>, &'a Node<K, V, H>),
+    /// Diff two pointers at the same position.
+    Pointers(&'a Pointer<K, V, H>, &'a Pointer<K, V, H>),
+    /// Every entry under an old pointer was removed.
+    RemovedPointer(&'a Pointer<K, V, H>),
+    /// Every entry under a new pointer was added.
+    AddedPointer(&'a Pointer<K, V, H>),
+    /// Every remaining entry of an old sub-tree was removed.
+    Removed(Iter<'a, S1, V, K, H>),
+    /// Every remaining entry of a new sub-tree was added.
+    Added(Iter<'a, S2, V, K, H>),
+}
+
+impl<'a, S1, S2, V, K, H> Diff<'a, S1, S2, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Clone + Serialize + DeserializeOwned,
+    V: PartialEq + Clone + Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+    pub(crate) fn new(
+        old: &'a Node<K, V, H>,
+        old_store: &'a S1,
+        new: &'a Node<K, V, H>,
+        new_store: &'a S2,
+        bit_width: u32,
+    ) -> Self {
+        Diff {
+            old_store,
+            new_store,
+            bit_width,
+            stack: vec![Task::Nodes(old, new)],
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Breaks down the next task, either into smaller tasks or into changes.
+    fn step(&mut self, task: Task<'a, S1, S2, V, K, H>) -> Result<(), Error> {
+        match task {
+            Task::Nodes(old, new) => {
+                // Pointers are stored in bit order, so we can walk both nodes in lockstep.
+                let (mut old_ptrs, mut new_ptrs) = (old.pointers.iter(), new.pointers.iter());
+                let mut tasks = Vec::new();
+                for idx in 0..(1u32 << self.bit_width) {
+                    let old_ptr = if old.bitfield.test_bit(idx) {
+                        old_ptrs.next()
+                    } else {
+                        None
+                    };
+                    let new_ptr = if new.bitfield.test_bit(idx) {
+                        new_ptrs.next()
+                    } else {
+                        None
+                    };
+                    match (old_ptr, new_ptr) {
+                        (None, None) => {}
+                        (Some(old_ptr), None) => tasks.push(Task::RemovedPointer(old_ptr)),
+                        (None, Some(new_ptr)) => tasks.push(Task::AddedPointer(new_ptr)),
+                        (Some(old_ptr), Some(new_ptr)) => {
+                            tasks.push(Task::Pointers(old_ptr, new_ptr))
+                        }
+                    }
+                }
+                self.stack.extend(tasks.into_iter().rev());
+            }
+            Task::Pointers(old, new) => match (old, new) {
+                (Pointer::Link { cid: a, .. }, Pointer::Link { cid: b, .. }) if a == b => {}
+                (Pointer::Values(old_kvs), Pointer::Values(new_kvs)) => diff_entries(
+                    old_kvs.iter().map(|kv| (kv.key(), kv.value())).collect(),
+                    new_kvs.iter().map(|kv| (kv.key(), kv.value())).collect(),
+                    &mut self.ready,
+                ),
+                // A bucket on one side and a sub-tree on the other; the sub-tree has to be loaded
+                // in full.
+                (Pointer::Values(_), _) | (_, Pointer::Values(_)) => diff_entries(
+                    entries(old, self.old_store)?.collect::<Result<_, _>>()?,
+                    entries(new, self.new_store)?.collect::<Result<_, _>>()?,
+                    &mut self.ready,
+                ),
+                _ => {
+                    let old_node = old.load_node(self.old_store)?.expect("not a bucket");
+                    let new_node = new.load_node(self.new_store)?.expect("not a bucket");
+                    self.stack.push(Task::Nodes(old_node, new_node));
+                }
+            },
+            Task::RemovedPointer(ptr) => self
+                .stack
+                .push(Task::Removed(entries(ptr, self.old_store)?)),
+            Task::AddedPointer(ptr) => self.stack.push(Task::Added(entries(ptr, self.new_store)?)),
+            Task::Removed(mut iter) => {
+                if let Some(entry) = iter.next() {
+                    let (k, v) = entry?;
+                    self.ready.push_back(Change::Removed(k.clone(), v.clone()));
+                    self.stack.push(Task::Removed(iter));
+                }
+            }
+            Task::Added(mut iter) => {
+                if let Some(entry) = iter.next() {
+                    let (k, v) = entry?;
+                    self.ready.push_back(Change::Added(k.clone(), v.clone()));
+                    self.stack.push(Task::Added(iter));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S1, S2, V, K, H> Iterator for Diff<'a, S1, S2, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Clone + Serialize + DeserializeOwned,
+    V: PartialEq + Clone + Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+    type Item = Result<Change<K, V>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.ready.pop_front() {
+                return Some(Ok(change));
+            }
+            let task = self.stack.pop()?;
+            if let Err(e) = self.step(task) {
+                self.stack.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, S1, S2, V, K, H> FusedIterator for Diff<'a, S1, S2, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Clone + Serialize + DeserializeOwned,
+    V: PartialEq + Clone + Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    S1: Blockstore,
+    S2: Blockstore,
+{
+}
+
+/// Returns a lazy iterator over the entries under the given pointer.
+fn entries<'a, K, V, H, S>(
+    ptr: &'a Pointer<K, V, H>,
+    store: &'a S,
+) -> Result<Iter<'a, S, V, K, H>, Error>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    S: Blockstore,
+{
+    Ok(match ptr {
+        Pointer::Values(kvs) => Iter::new_bucket(store, kvs),
+        _ => Iter::new(store, ptr.load_node(store)?.expect("not a bucket")),
+    })
+}
+
+/// Diffs two (small) sets of entries.
+fn diff_entries<K, V>(old: Vec<(&K, &V)>, new: Vec<(&K, &V)>, changes: &mut VecDeque<Change<K, V>>)
+where
+    K: Eq + Clone,
+    V: PartialEq + Clone,
+{
+    for &(k, old_v) in &old {
+        match new.iter().find(|(nk, _)| *nk == k) {
+            Some(&(_, new_v)) if new_v != old_v => changes.push_back(Change::Modified {
+                key: k.clone(),
+                old: old_v.clone(),
+                new: new_v.clone(),
+            }),
+            Some(_) => {}
+            None => changes.push_back(Change::Removed(k.clone(), old_v.clone())),
+        }
+    }
+    for &(k, new_v) in &new {
+        if !old.iter().any(|(ok, _)| *ok == k) {
+            changes.push_back(Change::Added(k.clone(), new_v.clone()));
+        }
+    }
+}
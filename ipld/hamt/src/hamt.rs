@@ -11,6 +11,8 @@ use fvm_shared::blockstore::{Blockstore, CborStore};
 use serde::de::DeserializeOwned;
 use serde::{Serialize, Serializer};
 
+use crate::diff::Diff;
+use crate::iter::Iter;
 use crate::node::Node;
 use crate::{Error, Hash, HashAlgorithm, Sha256, DEFAULT_BIT_WIDTH};
 
@@ -313,8 +315,129 @@ where
         self.root.for_each(self.store.borrow(), &mut f)
     }
 
+    /// Returns a lazy iterator over the entries of the HAMT, in hash order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, u64, usize> = Hamt::new(store);
+    /// map.set(1, 1).unwrap();
+    /// map.set(4, 2).unwrap();
+    ///
+    /// let total = map.iter().map(|r| r.map(|(_, v)| v)).sum::<Result<u64, _>>().unwrap();
+    /// assert_eq!(total, 3);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, BS, V, K, H> {
+        Iter::new(&self.store, &self.root)
+    }
+
+    /// Returns a lazy iterator over the entries of the HAMT, in hash order, starting at `key` (or
+    /// where `key` would be, if it isn't in the HAMT).
+    ///
+    /// This can be used to paginate over the HAMT: fetch one entry more than the page size and
+    /// start the next page at that entry's key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// for i in 0..10 {
+    ///     map.set(i, i).unwrap();
+    /// }
+    ///
+    /// let mut page: Vec<_> = map.iter().take(6).collect::<Result<_, _>>().unwrap();
+    /// let (&cursor, _) = page.pop().unwrap();
+    /// let rest: Vec<_> = map.iter_from(&cursor).unwrap().collect::<Result<_, _>>().unwrap();
+    /// assert_eq!(page.len() + rest.len(), 10);
+    /// assert_eq!(rest[0].0, &cursor);
+    /// ```
+    pub fn iter_from<Q: ?Sized>(&self, key: &Q) -> Result<Iter<'_, BS, V, K, H>, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialOrd,
+    {
+        Iter::new_from(&self.store, &self.root, self.bit_width, key)
+    }
+
+    /// Returns a lazy iterator over the changes from this HAMT to `other`, in hash order.
+    ///
+    /// Both HAMTs are walked node-by-node and linked sub-trees with identical CIDs are skipped
+    /// without being loaded, so the cost is proportional to the size of the difference rather
+    /// than the size of the HAMTs. Both HAMTs must have the same bit width.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::{Change, Hamt};
+    ///
+    /// let store = fvm_shared::blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut a: Hamt<_, _, usize> = Hamt::new(&store);
+    /// a.set(1, "a".to_string()).unwrap();
+    /// a.set(2, "b".to_string()).unwrap();
+    ///
+    /// let mut b: Hamt<_, _, usize> = Hamt::new(&store);
+    /// b.set(2, "c".to_string()).unwrap();
+    ///
+    /// let changes: Vec<_> = a.diff(&b).unwrap().collect::<Result<_, _>>().unwrap();
+    /// assert_eq!(changes.len(), 2);
+    /// assert!(changes.contains(&Change::Removed(1, "a".to_string())));
+    /// assert!(changes.contains(&Change::Modified {
+    ///     key: 2,
+    ///     old: "b".to_string(),
+    ///     new: "c".to_string(),
+    /// }));
+    /// ```
+    pub fn diff<'a, S>(
+        &'a self,
+        other: &'a Hamt<S, V, K, H>,
+    ) -> Result<Diff<'a, BS, S, V, K, H>, Error>
+    where
+        K: Clone,
+        V: PartialEq + Clone,
+        S: Blockstore,
+    {
+        if self.bit_width != other.bit_width {
+            return Err(Error::Dynamic(anyhow::anyhow!(
+                "cannot diff HAMTs with different bit widths ({} and {})",
+                self.bit_width,
+                other.bit_width
+            )));
+        }
+        Ok(Diff::new(
+            &self.root,
+            &self.store,
+            &other.root,
+            &other.store,
+            self.bit_width,
+        ))
+    }
+
     /// Consumes this HAMT and returns the Blockstore it owns.
     pub fn consume(self) -> BS {
         self.store
     }
 }
+
+impl<'a, BS, V, K, H> IntoIterator for &'a Hamt<BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    BS: Blockstore,
+    H: HashAlgorithm,
+{
+    type Item = Result<(&'a K, &'a V), Error>;
+    type IntoIter = Iter<'a, BS, V, K, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+
+use forest_hash_utils::BytesKey;
+use fvm_shared::blockstore::Blockstore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::hash_bits::HashBits;
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::{Error, Hash, HashAlgorithm, KeyValuePair, Sha256};
+
+/// A lazy iterator over the entries of a [`Hamt`](crate::Hamt), in hash order. Child nodes are
+/// loaded from the blockstore as they're reached.
+///
+/// After yielding an error, the iterator is exhausted.
+pub struct Iter<'a, BS, V, K = BytesKey, H = Sha256> {
+    store: &'a BS,
+    /// The remaining pointers of each node on the path to the current bucket, innermost last.
+    stack: Vec<std::slice::Iter<'a, Pointer<K, V, H>>>,
+    /// The remaining entries of the current bucket.
+    current: std::slice::Iter<'a, KeyValuePair<K, V>>,
+}
+
+impl<'a, BS, V, K, H> Iter<'a, BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    BS: Blockstore,
+{
+    pub(crate) fn new(store: &'a BS, root: &'a Node<K, V, H>) -> Self {
+        Iter {
+            store,
+            stack: vec![root.pointers.iter()],
+            current: [].iter(),
+        }
+    }
+
+    /// Creates an iterator over the entries of a single bucket.
+    pub(crate) fn new_bucket(store: &'a BS, kvs: &'a [KeyValuePair<K, V>]) -> Self {
+        Iter {
+            store,
+            stack: Vec::new(),
+            current: kvs.iter(),
+        }
+    }
+
+    /// Creates an iterator starting at `key`, or where `key` would be if it isn't in the HAMT.
+    pub(crate) fn new_from<Q: ?Sized>(
+        store: &'a BS,
+        root: &'a Node<K, V, H>,
+        bit_width: u32,
+        key: &Q,
+    ) -> Result<Self, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialOrd,
+    {
+        let hash = H::hash(key);
+        let mut hashed_key = HashBits::new(&hash);
+        let mut stack = Vec::new();
+        let mut node = root;
+        loop {
+            let idx = hashed_key.next(bit_width)?;
+            let i = node.index_for_bit_pos(idx);
+            if !node.bitfield.test_bit(idx) {
+                stack.push(node.pointers[i..].iter());
+                return Ok(Iter {
+                    store,
+                    stack,
+                    current: [].iter(),
+                });
+            }
+
+            stack.push(node.pointers[i + 1..].iter());
+            let ptr = &node.pointers[i];
+            match ptr.load_node(store)? {
+                Some(child) => node = child,
+                None => {
+                    let kvs = match ptr {
+                        Pointer::Values(kvs) => kvs,
+                        _ => unreachable!("pointer without a node must be a bucket"),
+                    };
+                    // Buckets are sorted by key.
+                    let start = kvs
+                        .iter()
+                        .position(|kv| {
+                            let k: &Q = kv.key().borrow();
+                            k >= key
+                        })
+                        .unwrap_or(kvs.len());
+                    return Ok(Iter {
+                        store,
+                        stack,
+                        current: kvs[start..].iter(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<'a, BS, V, K, H> Iterator for Iter<'a, BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    BS: Blockstore,
+{
+    type Item = Result<(&'a K, &'a V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(kv) = self.current.next() {
+                return Some(Ok((kv.key(), kv.value())));
+            }
+
+            let ptr = match self.stack.last_mut()?.next() {
+                Some(ptr) => ptr,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            match ptr {
+                Pointer::Values(kvs) => self.current = kvs.iter(),
+                _ => match ptr.load_node(self.store) {
+                    Ok(node) => self.stack.push(node.expect("not a bucket").pointers.iter()),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<'a, BS, V, K, H> FusedIterator for Iter<'a, BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    BS: Blockstore,
+{
+}
@@ -5,6 +5,7 @@ use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
 
 use cid::Cid;
+use fvm_shared::blockstore::{Blockstore, CborStore};
 use libipld_core::ipld::Ipld;
 use once_cell::unsync::OnceCell;
 use serde::de::{self, DeserializeOwned};
@@ -99,6 +100,32 @@ impl<K, V, H> Default for Pointer<K, V, H> {
     }
 }
 
+impl<K, V, H> Pointer<K, V, H>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    /// Returns the node behind a link or dirty pointer, loading (and caching) it if necessary, or
+    /// `None` for a bucket of values.
+    pub(crate) fn load_node<S: Blockstore>(
+        &self,
+        store: &S,
+    ) -> Result<Option<&Node<K, V, H>>, Error> {
+        match self {
+            Pointer::Link { cid, cache } => {
+                let node = cache.get_or_try_init(|| {
+                    store
+                        .get_cbor(cid)?
+                        .ok_or_else(|| Error::CidNotFound(cid.to_string()))
+                })?;
+                Ok(Some(&**node))
+            }
+            Pointer::Dirty(node) => Ok(Some(&**node)),
+            Pointer::Values(_) => Ok(None),
+        }
+    }
+}
+
 impl<K, V, H> Pointer<K, V, H>
 where
     K: Serialize + DeserializeOwned + Hash + PartialOrd,
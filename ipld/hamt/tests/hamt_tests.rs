@@ -6,9 +6,9 @@ use std::fmt::Display;
 use cid::multihash::Code;
 #[cfg(feature = "identity")]
 use fvm_ipld_hamt::Identity;
-use fvm_ipld_hamt::{BytesKey, Hamt};
+use fvm_ipld_hamt::{BytesKey, Change, Hamt};
 use fvm_shared::blockstore::tracking::{BSStats, TrackingBlockstore};
-use fvm_shared::blockstore::{CborStore, MemoryBlockstore};
+use fvm_shared::blockstore::{Blockstore, CborStore, MemoryBlockstore};
 use serde_bytes::ByteBuf;
 
 // Redeclaring max array size of Hamt to avoid exposing value
@@ -477,6 +477,139 @@ fn clean_child_ordering() {
     assert_eq!(*store.stats.borrow(), BSStats {r: 3, w: 11, br: 1449, bw: 1751});
 }
 
+#[test]
+fn iter() {
+    let mem = MemoryBlockstore::default();
+    let mut hamt: Hamt<_, u64, u64> = Hamt::new_with_bit_width(&mem, 5);
+    for i in 0..1000 {
+        hamt.set(i, i * 2).unwrap();
+    }
+
+    // Iterate over the dirty HAMT, then again after flushing and reloading.
+    let mut entries: Vec<(u64, u64)> = hamt
+        .iter()
+        .map(|r| r.map(|(k, v)| (*k, *v)))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    entries.sort_unstable();
+    assert_eq!(entries, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+    let root = hamt.flush().unwrap();
+    let store = TrackingBlockstore::new(&mem);
+    let hamt: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&root, &store, 5).unwrap();
+
+    // The iterator is lazy.
+    let mut iter = hamt.iter();
+    iter.next().unwrap().unwrap();
+    assert!(store.stats.borrow().r < 5);
+
+    let mut for_each = Vec::new();
+    hamt.for_each(|k, _| {
+        for_each.push(*k);
+        Ok(())
+    })
+    .unwrap();
+    let keys: Vec<u64> = (&hamt)
+        .into_iter()
+        .map(|r| r.map(|(k, _)| *k))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(keys, for_each);
+
+    // Resuming from any key yields the remaining keys, in the same order.
+    for (i, k) in keys.iter().enumerate().step_by(37) {
+        let rest: Vec<u64> = hamt
+            .iter_from(k)
+            .unwrap()
+            .map(|r| r.map(|(k, _)| *k))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rest, &keys[i..]);
+    }
+
+    // Resuming from a deleted key yields the keys after it.
+    let mut hamt: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&root, &store, 5).unwrap();
+    hamt.delete(&keys[500]).unwrap();
+    let rest: Vec<u64> = hamt
+        .iter_from(&keys[500])
+        .unwrap()
+        .map(|r| r.map(|(k, _)| *k))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rest, &keys[501..]);
+
+    // Missing blocks are reported, after which the iterator is exhausted.
+    let partial = MemoryBlockstore::default();
+    partial
+        .put_keyed(&root, &mem.get(&root).unwrap().unwrap())
+        .unwrap();
+    let broken: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&root, &partial, 5).unwrap();
+    let mut iter = broken.iter();
+    assert!(iter.find(|r| r.is_err()).is_some());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn diff() {
+    let mem = MemoryBlockstore::default();
+
+    let mut a: Hamt<_, u64, u64> = Hamt::new_with_bit_width(&mem, 5);
+    for i in 0..10_000 {
+        a.set(i, i).unwrap();
+    }
+    let a_root = a.flush().unwrap();
+
+    let mut b: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&a_root, &mem, 5).unwrap();
+    b.set(1, 100).unwrap();
+    b.delete(&2).unwrap();
+    b.set(10_000, 10_000).unwrap();
+    let b_root = b.flush().unwrap();
+
+    // Nothing changed.
+    let store = TrackingBlockstore::new(&mem);
+    let a: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&a_root, &store, 5).unwrap();
+    let same: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&a_root, &store, 5).unwrap();
+    assert!(a.diff(&same).unwrap().next().is_none());
+    assert_eq!(store.stats.borrow().r, 2);
+
+    let b: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&b_root, &store, 5).unwrap();
+    let mut changes: Vec<_> = a.diff(&b).unwrap().collect::<Result<_, _>>().unwrap();
+    changes.sort_by_key(|c| *c.key());
+    assert_eq!(
+        changes,
+        vec![
+            Change::Modified {
+                key: 1,
+                old: 1,
+                new: 100
+            },
+            Change::Removed(2, 2),
+            Change::Added(10_000, 10_000),
+        ]
+    );
+
+    // Only the paths to the changed keys are loaded.
+    let diff_reads = store.stats.borrow().r;
+    let full_store = TrackingBlockstore::new(&mem);
+    let full: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&a_root, &full_store, 5).unwrap();
+    full.for_each(|_, _| Ok(())).unwrap();
+    assert!(diff_reads * 10 < full_store.stats.borrow().r);
+
+    // And diffing the other way around reverses the changes.
+    let mut reversed: Vec<_> = b.diff(&a).unwrap().collect::<Result<_, _>>().unwrap();
+    reversed.sort_by_key(|c| *c.key());
+    assert_eq!(reversed[1], Change::Added(2, 2));
+
+    // The diff is lazy: finding the first change only loads the path to it.
+    let store = TrackingBlockstore::new(&mem);
+    let empty: Hamt<_, u64, u64> = Hamt::new_with_bit_width(&store, 5);
+    let a: Hamt<_, u64, u64> = Hamt::load_with_bit_width(&a_root, &store, 5).unwrap();
+    let mut added = empty.diff(&a).unwrap();
+    assert!(matches!(added.next(), Some(Ok(Change::Added(..)))));
+    assert!(store.stats.borrow().r < 5);
+    assert_eq!(added.count(), 9_999);
+}
+
 fn tstring(v: impl Display) -> BytesKey {
     BytesKey(v.to_string().into_bytes())
 }
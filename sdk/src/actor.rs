@@ -69,6 +69,22 @@ pub fn create_actor(actor_id: ActorID, code_cid: &Cid) -> SyscallResult<()> {
     unsafe { sys::actor::create_actor(actor_id, cid.as_ptr()) }
 }
 
+/// Installs user-deployed actor code (wasm bytecode), so actors can be created from it with
+/// [`create_actor`]. Returns the code CID. May only be called by built-in singleton actors (e.g.,
+/// the Init actor).
+pub fn install_actor(code: &[u8]) -> SyscallResult<Cid> {
+    let mut buf = [0u8; MAX_CID_LEN];
+    unsafe {
+        let len = sys::actor::install_actor(
+            code.as_ptr(),
+            code.len() as u32,
+            buf.as_mut_ptr(),
+            MAX_CID_LEN as u32,
+        )?;
+        Ok(Cid::read_bytes(&buf[..len as usize]).expect("invalid cid returned"))
+    }
+}
+
 /// Determines whether the supplied CodeCID belongs to a built-in actor type,
 /// and to which.
 pub fn resolve_builtin_actor_type(code_cid: &Cid) -> Option<actor::builtin::Type> {
@@ -1,10 +1,11 @@
 use std::convert::TryInto;
 
+use cid::Cid;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::version::NetworkVersion;
 
-use crate::sys;
+use crate::{sys, MAX_CID_LEN};
 
 pub fn curr_epoch() -> ChainEpoch {
     unsafe {
@@ -38,3 +39,17 @@ pub fn total_fil_circ_supply() -> TokenAmount {
             .into()
     }
 }
+
+/// Returns the CID of the tipset at the given epoch, or `None` if the node doesn't know it. Aborts
+/// with IllegalArgument if the epoch isn't in the past.
+pub fn tipset_cid(epoch: ChainEpoch) -> Option<Cid> {
+    let mut buf = [0u8; MAX_CID_LEN];
+    unsafe {
+        let len = sys::network::tipset_cid(epoch, buf.as_mut_ptr(), MAX_CID_LEN as u32)
+            .expect("failed to get tipset cid");
+        if len < 0 {
+            return None;
+        }
+        Some(Cid::read_bytes(&buf[..len as usize]).expect("invalid cid returned"))
+    }
+}
@@ -21,6 +21,16 @@ super::fvm_syscalls! {
     /// TODO this syscall will change to calculate the address internally.
     pub fn create_actor(actor_id: u64, typ_off: *const u8) -> Result<()>;
 
+    /// Installs user-deployed actor code (wasm bytecode), so actors can be created from it, and
+    /// writes its CID into the output buffer. Returns the length of the CID. May only be called by
+    /// built-in singleton actors.
+    pub fn install_actor(
+        code_off: *const u8,
+        code_len: u32,
+        obuf_off: *mut u8,
+        obuf_len: u32,
+    ) -> Result<u32>;
+
     /// Determines whether the specified CodeCID belongs to that of a builtin
     /// actor and which. Returns 0 if unrecognized. Can only fail due to
     /// internal errors.
@@ -15,4 +15,9 @@ super::fvm_syscalls! {
     /// tuple of u64 values to be concatenated in a u128.
     /// Note that how this value is calculated is expected to change in nv15
     pub fn total_fil_circ_supply() -> Result<fvm_shared::sys::TokenAmount>;
+
+    /// Writes the CID of the tipset at the given epoch into the output buffer, returning the
+    /// length of the CID, or -1 if the tipset is unknown. Aborts with IllegalArgument if the epoch
+    /// isn't in the past.
+    pub fn tipset_cid(epoch: i64, obuf_off: *mut u8, obuf_len: u32) -> Result<i32>;
 }
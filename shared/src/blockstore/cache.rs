@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use cid::Cid;
+
+use super::tracking::BSStats;
+use super::Blockstore;
+
+/// A least-recently-used cache of blocks, bounded by the total size of the cached blocks.
+#[derive(Debug)]
+struct Lru {
+    /// The cached blocks, and when they were last used.
+    blocks: HashMap<Cid, (Vec<u8>, u64)>,
+    /// The cached blocks' CIDs, by when they were last used.
+    order: BTreeMap<u64, Cid>,
+    tick: u64,
+    size: usize,
+    capacity: usize,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            blocks: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            capacity,
+        }
+    }
+
+    fn contains(&self, k: &Cid) -> bool {
+        self.blocks.contains_key(k)
+    }
+
+    /// Returns the block, marking it as the most recently used.
+    fn get(&mut self, k: &Cid) -> Option<&[u8]> {
+        let (data, used) = self.blocks.get_mut(k)?;
+        self.order.remove(&*used);
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, *k);
+        Some(&data[..])
+    }
+
+    /// Caches the block, evicting the least recently used blocks to make space for it. Returns the
+    /// number of evicted blocks. Blocks larger than the cache's capacity aren't cached.
+    fn insert(&mut self, k: Cid, data: &[u8]) -> usize {
+        if data.len() > self.capacity || self.get(&k).is_some() {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.size + data.len() > self.capacity {
+            let (&used, &old) = self.order.iter().next().expect("cache size is out of sync");
+            self.order.remove(&used);
+            let (old_data, _) = self
+                .blocks
+                .remove(&old)
+                .expect("cache order is out of sync");
+            self.size -= old_data.len();
+            evicted += 1;
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, k);
+        self.blocks.insert(k, (data.into(), self.tick));
+        self.size += data.len();
+        evicted
+    }
+}
+
+/// Wrapper around `Blockstore` that caches the most recently read blocks in memory, up to a total
+/// size in bytes. Writes go straight to the wrapped store, and aren't cached.
+#[derive(Debug)]
+pub struct LruBlockstore<BS> {
+    base: BS,
+    cache: Mutex<Lru>,
+}
+
+impl<BS> LruBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    /// Creates a read cache holding up to `capacity` bytes of blocks.
+    pub fn new(base: BS, capacity: usize) -> Self {
+        Self {
+            base,
+            cache: Mutex::new(Lru::new(capacity)),
+        }
+    }
+
+    pub fn consume(self) -> BS {
+        self.base
+    }
+}
+
+impl<BS> Blockstore for LruBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.cache.lock().unwrap().get(k) {
+            return Ok(Some(data.into()));
+        }
+        let data = self.base.get(k)?;
+        if let Some(data) = &data {
+            self.cache.lock().unwrap().insert(*k, data);
+        }
+        Ok(data)
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        if self.cache.lock().unwrap().contains(k) {
+            return Ok(true);
+        }
+        self.base.has(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.base.put_keyed(k, block)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        self.base.put_many_keyed(blocks)
+    }
+}
+
+/// Stats for a [`WriteThroughBlockstore`]: the reads and writes that reached the wrapped store,
+/// and how well the cache did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads and writes that reached the wrapped store.
+    pub base: BSStats,
+    /// Number of reads served from the cache.
+    pub hits: usize,
+    /// Number of reads that missed the cache.
+    pub misses: usize,
+    /// Number of blocks evicted from the cache.
+    pub evictions: usize,
+}
+
+/// Wrapper around `Blockstore` with an LRU cache that's filled on both reads and writes. Writes
+/// always go through to the wrapped store, so the cache only ever holds blocks that the wrapped
+/// store has.
+#[derive(Debug)]
+pub struct WriteThroughBlockstore<BS> {
+    base: BS,
+    cache: Mutex<Lru>,
+    stats: Mutex<CacheStats>,
+}
+
+impl<BS> WriteThroughBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    /// Creates a write-through cache holding up to `capacity` bytes of blocks.
+    pub fn new(base: BS, capacity: usize) -> Self {
+        Self {
+            base,
+            cache: Mutex::new(Lru::new(capacity)),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn consume(self) -> BS {
+        self.base
+    }
+
+    /// Returns the stats recorded since this blockstore was created.
+    pub fn stats(&self) -> CacheStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl<BS> Blockstore for WriteThroughBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.cache.lock().unwrap().get(k) {
+            self.stats.lock().unwrap().hits += 1;
+            return Ok(Some(data.into()));
+        }
+        let data = self.base.get(k)?;
+        let evictions = match &data {
+            Some(data) => self.cache.lock().unwrap().insert(*k, data),
+            None => 0,
+        };
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.misses += 1;
+        stats.evictions += evictions;
+        stats.base.r += 1;
+        stats.base.br += data.as_ref().map_or(0, Vec::len);
+        Ok(data)
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        if self.cache.lock().unwrap().contains(k) {
+            return Ok(true);
+        }
+        self.stats.lock().unwrap().base.r += 1;
+        self.base.has(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.put_many_keyed(std::iter::once((*k, block)))
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        // Only cache the blocks once they've been written.
+        let blocks: Vec<_> = blocks.into_iter().collect();
+        self.base
+            .put_many_keyed(blocks.iter().map(|(k, b)| (*k, b.as_ref())))?;
+
+        let mut cache = self.cache.lock().unwrap();
+        let mut stats = self.stats.lock().unwrap();
+        for (k, b) in &blocks {
+            stats.base.w += 1;
+            stats.base.bw += b.as_ref().len();
+            stats.evictions += cache.insert(*k, b.as_ref());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::Code;
+
+    use super::*;
+    use crate::blockstore::tracking::TrackingBlockstore;
+    use crate::blockstore::{Block, MemoryBlockstore};
+
+    #[test]
+    fn lru_read_cache() {
+        let mem = TrackingBlockstore::new(MemoryBlockstore::default());
+        let a = mem
+            .put(Code::Blake2b256, &Block::new(0x55, b"aaaa"))
+            .unwrap();
+        let b = mem
+            .put(Code::Blake2b256, &Block::new(0x55, b"bbbb"))
+            .unwrap();
+        let c = mem
+            .put(Code::Blake2b256, &Block::new(0x55, b"cccc"))
+            .unwrap();
+
+        // Room for two blocks.
+        let cache = LruBlockstore::new(&mem, 8);
+        assert_eq!(cache.get(&a).unwrap().as_deref(), Some(&b"aaaa"[..]));
+        cache.get(&b).unwrap();
+        cache.get(&a).unwrap();
+        assert_eq!(mem.stats.borrow().r, 2);
+
+        // Evicts b, the least recently used.
+        cache.get(&c).unwrap();
+        cache.get(&a).unwrap();
+        assert_eq!(mem.stats.borrow().r, 3);
+        cache.get(&b).unwrap();
+        assert_eq!(mem.stats.borrow().r, 4);
+    }
+
+    #[test]
+    fn write_through_cache() {
+        let mem = MemoryBlockstore::default();
+        let cache = WriteThroughBlockstore::new(&mem, 8);
+
+        let a = cache
+            .put(Code::Blake2b256, &Block::new(0x55, b"aaaa"))
+            .unwrap();
+        assert_eq!(mem.get(&a).unwrap().as_deref(), Some(&b"aaaa"[..]));
+        assert_eq!(cache.get(&a).unwrap().as_deref(), Some(&b"aaaa"[..]));
+
+        // Too big to cache.
+        let big = cache
+            .put(Code::Blake2b256, &Block::new(0x55, b"big block"))
+            .unwrap();
+        assert_eq!(cache.get(&big).unwrap().as_deref(), Some(&b"big block"[..]));
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                base: BSStats {
+                    r: 1,
+                    w: 2,
+                    br: 9,
+                    bw: 13,
+                },
+                hits: 1,
+                misses: 1,
+                evictions: 0,
+            }
+        );
+    }
+}
@@ -1,14 +1,16 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::RwLock;
 
 use anyhow::Result;
 use cid::Cid;
 
 use super::Blockstore;
 
-#[derive(Debug, Default, Clone)]
+/// An in-memory blockstore. It's thread-safe, so it can be shared between threads (e.g., in an
+/// `Arc`).
+#[derive(Debug, Default)]
 pub struct MemoryBlockstore {
-    blocks: RefCell<HashMap<Cid, Vec<u8>>>,
+    blocks: RwLock<HashMap<Cid, Vec<u8>>>,
 }
 
 impl MemoryBlockstore {
@@ -17,17 +19,25 @@ impl MemoryBlockstore {
     }
 }
 
+impl Clone for MemoryBlockstore {
+    fn clone(&self) -> Self {
+        Self {
+            blocks: RwLock::new(self.blocks.read().unwrap().clone()),
+        }
+    }
+}
+
 impl Blockstore for MemoryBlockstore {
     fn has(&self, k: &Cid) -> Result<bool> {
-        Ok(self.blocks.borrow().contains_key(k))
+        Ok(self.blocks.read().unwrap().contains_key(k))
     }
 
     fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
-        Ok(self.blocks.borrow().get(k).cloned())
+        Ok(self.blocks.read().unwrap().get(k).cloned())
     }
 
     fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
-        self.blocks.borrow_mut().insert(*k, block.into());
+        self.blocks.write().unwrap().insert(*k, block.into());
         Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use std::rc::Rc;
+use std::sync::Arc;
 
 use anyhow::Result;
 use cid::{multihash, Cid};
@@ -11,6 +12,12 @@ pub use cbor::CborStore;
 mod memory;
 pub use memory::MemoryBlockstore;
 
+mod cache;
+pub use cache::{CacheStats, LruBlockstore, WriteThroughBlockstore};
+
+mod overlay;
+pub use overlay::OverlayBlockstore;
+
 mod block;
 pub use block::*;
 
@@ -173,3 +180,46 @@ where
         (**self).put_many_keyed(blocks)
     }
 }
+
+impl<BS> Blockstore for Arc<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        (**self).get(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        (**self).put_keyed(k, block)
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        (**self).has(k)
+    }
+
+    fn put<D>(&self, mh_code: multihash::Code, block: &Block<D>) -> Result<Cid>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+    {
+        (**self).put(mh_code, block)
+    }
+
+    fn put_many<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (multihash::Code, Block<D>)>,
+    {
+        (**self).put_many(blocks)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        (**self).put_many_keyed(blocks)
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use cid::Cid;
+
+use super::Blockstore;
+
+/// A layered blockstore: writes go to the top store, and reads are served from the top store,
+/// falling back on the base store. The base store is never written to.
+#[derive(Debug)]
+pub struct OverlayBlockstore<T, B> {
+    top: T,
+    base: B,
+}
+
+impl<T, B> OverlayBlockstore<T, B>
+where
+    T: Blockstore,
+    B: Blockstore,
+{
+    pub fn new(top: T, base: B) -> Self {
+        Self { top, base }
+    }
+
+    /// Returns the top and base stores.
+    pub fn consume(self) -> (T, B) {
+        (self.top, self.base)
+    }
+}
+
+impl<T, B> Blockstore for OverlayBlockstore<T, B>
+where
+    T: Blockstore,
+    B: Blockstore,
+{
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        match self.top.get(k)? {
+            Some(data) => Ok(Some(data)),
+            None => self.base.get(k),
+        }
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        Ok(self.top.has(k)? || self.base.has(k)?)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.top.put_keyed(k, block)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        self.top.put_many_keyed(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::Code;
+
+    use super::*;
+    use crate::blockstore::{Block, MemoryBlockstore};
+
+    #[test]
+    fn basic_overlay_store() {
+        let base = MemoryBlockstore::default();
+        let top = MemoryBlockstore::default();
+        let below = base
+            .put(Code::Blake2b256, &Block::new(0x55, b"base"))
+            .unwrap();
+
+        let overlay = OverlayBlockstore::new(&top, &base);
+        assert_eq!(overlay.get(&below).unwrap().as_deref(), Some(&b"base"[..]));
+
+        let above = overlay
+            .put(Code::Blake2b256, &Block::new(0x55, b"top"))
+            .unwrap();
+        assert!(overlay.has(&above).unwrap());
+        assert!(top.has(&above).unwrap());
+        assert!(!base.has(&above).unwrap());
+    }
+}
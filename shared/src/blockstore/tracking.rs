@@ -1,6 +1,7 @@
 //#![cfg(feature = "tracking")]
 
 use std::cell::RefCell;
+use std::sync::Mutex;
 
 use anyhow::Result;
 use cid::multihash::{self, Code};
@@ -24,6 +25,8 @@ pub struct BSStats {
 
 /// Wrapper around `Blockstore` to tracking reads and writes for verification.
 /// This struct should only be used for testing.
+///
+/// See [SyncTrackingBlockstore] for a version that can be shared between threads.
 #[derive(Debug)]
 pub struct TrackingBlockstore<BS> {
     base: BS,
@@ -107,6 +110,103 @@ where
     }
 }
 
+/// A [TrackingBlockstore] that can be shared between threads.
+#[derive(Debug)]
+pub struct SyncTrackingBlockstore<BS> {
+    base: BS,
+    stats: Mutex<BSStats>,
+}
+
+impl<BS> SyncTrackingBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    pub fn new(base: BS) -> Self {
+        Self {
+            base,
+            stats: Default::default(),
+        }
+    }
+
+    /// Returns the stats recorded since this blockstore was created.
+    pub fn stats(&self) -> BSStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl<BS> Blockstore for SyncTrackingBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        let bytes = self.base.get(cid)?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.r += 1;
+        stats.br += bytes.as_ref().map_or(0, Vec::len);
+        Ok(bytes)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.stats.lock().unwrap().r += 1;
+        self.base.has(cid)
+    }
+
+    fn put<D>(&self, code: Code, block: &Block<D>) -> Result<Cid>
+    where
+        D: AsRef<[u8]>,
+    {
+        let cid = self.base.put(code, block)?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.w += 1;
+        stats.bw += block.as_ref().len();
+        Ok(cid)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.base.put_keyed(k, block)?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.w += 1;
+        stats.bw += block.len();
+        Ok(())
+    }
+
+    fn put_many<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (multihash::Code, Block<D>)>,
+    {
+        // Count the blocks as they're written, and only lock the stats once.
+        let (mut w, mut bw) = (0, 0);
+        self.base.put_many(blocks.into_iter().inspect(|(_, b)| {
+            w += 1;
+            bw += b.as_ref().len();
+        }))?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.w += w;
+        stats.bw += bw;
+        Ok(())
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        let (mut w, mut bw) = (0, 0);
+        self.base
+            .put_many_keyed(blocks.into_iter().inspect(|(_, b)| {
+                w += 1;
+                bw += b.as_ref().len();
+            }))?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.w += w;
+        stats.bw += bw;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,4 +240,24 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn sync_tracking_store() {
+        let mem = MemoryBlockstore::default();
+        let tr_store = SyncTrackingBlockstore::new(&mem);
+
+        // Shared between threads.
+        let block = Block::new(0x55, &b"foobar"[..]);
+        let put_cid = std::thread::scope(|s| {
+            s.spawn(|| tr_store.get(&block.cid(Code::Blake2b256)).unwrap());
+            s.spawn(|| tr_store.put(Code::Blake2b256, &block).unwrap())
+                .join()
+                .unwrap()
+        });
+        assert_eq!(tr_store.get(&put_cid).unwrap().as_deref(), Some(block.data));
+
+        let stats = tr_store.stats();
+        assert_eq!(stats.r, 2);
+        assert_eq!((stats.w, stats.bw), (1, block.len()));
+    }
 }
@@ -3,10 +3,10 @@
 
 use std::fmt::Display;
 
-use crate::encoding::repr::Serialize_repr;
+use crate::encoding::repr::{Deserialize_repr, Serialize_repr};
 
 /// Specifies the network version
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Ord, PartialOrd, Serialize_repr)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Ord, PartialOrd, Serialize_repr, Deserialize_repr)]
 #[repr(u32)]
 pub enum NetworkVersion {
     /// genesis (specs-actors v0.9.3)
@@ -41,6 +41,10 @@ pub enum NetworkVersion {
     V14,
     /// actors v7
     V15,
+    /// actors v8
+    V16,
+    /// actors v9
+    V17,
 }
 
 impl Display for NetworkVersion {
@@ -71,6 +75,8 @@ impl TryFrom<u32> for NetworkVersion {
             13 => Ok(V13),
             14 => Ok(V14),
             15 => Ok(V15),
+            16 => Ok(V16),
+            17 => Ok(V17),
             _ => Err(value),
         }
     }
@@ -6,7 +6,7 @@ use fmt::Display;
 use fvm::executor::{ApplyKind, ApplyRet, DefaultExecutor, Executor};
 use fvm::kernel::Context;
 use fvm::machine::{Engine, Machine};
-use fvm::state_tree::{ActorState, StateTree};
+use fvm::state_tree::{ActorChange, ActorState, StateTree};
 use fvm_shared::address::Protocol;
 use fvm_shared::blockstore::{CborStore, MemoryBlockstore};
 use fvm_shared::crypto::signature::SECP_SIG_LEN;
@@ -122,8 +122,7 @@ fn compare_actors(
 }
 
 /// Compares the state-root with the postcondition state-root in the test vector. If they don't
-/// match, it performs a basic actor & state-diff of the message senders and receivers in the test
-/// vector, along with all system actors.
+/// match, it diffs the two state trees and reports every actor that differs.
 fn compare_state_roots(bs: &MemoryBlockstore, root: &Cid, vector: &MessageVector) -> Result<()> {
     if root == &vector.postconditions.state_tree.root_cid {
         return Ok(());
@@ -134,6 +133,45 @@ fn compare_state_roots(bs: &MemoryBlockstore, root: &Cid, vector: &MessageVector
     let expected_st = StateTree::new_from_root(bs, &vector.postconditions.state_tree.root_cid)
         .context("failed to load expected state tree")?;
 
+    match expected_st.diff(&actual_st) {
+        Ok(changes) => {
+            for change in changes {
+                match change {
+                    ActorChange::Added { address, actor } => {
+                        compare_actors(bs, address, Some(actor), None)?
+                    }
+                    ActorChange::Removed { address, actor } => {
+                        compare_actors(bs, address, None, Some(actor))?
+                    }
+                    ActorChange::Modified { address, old, new } => {
+                        compare_actors(bs, address, Some(new), Some(old))?
+                    }
+                }
+            }
+        }
+        // The state trees in test vectors are usually incomplete, so the diff may need blocks we
+        // don't have. Fall back on comparing the actors we know about.
+        Err(err) => {
+            log::warn!("failed to diff state trees: {:#}", err);
+            compare_known_actors(bs, &actual_st, &expected_st, vector)?;
+        }
+    }
+
+    return Err(anyhow!(
+        "wrong post root cid; expected {}, but got {}",
+        &vector.postconditions.state_tree.root_cid,
+        root
+    ));
+}
+
+/// Performs a basic actor & state-diff of the message senders and receivers in the test vector,
+/// along with all system actors.
+fn compare_known_actors(
+    bs: &MemoryBlockstore,
+    actual_st: &StateTree<&MemoryBlockstore>,
+    expected_st: &StateTree<&MemoryBlockstore>,
+    vector: &MessageVector,
+) -> Result<()> {
     // We only compare system actors and the send/receiver actor as we don't know what other actors
     // might exist in the state-tree (it's usually incomplete).
 
@@ -163,11 +201,7 @@ fn compare_state_roots(bs: &MemoryBlockstore, root: &Cid, vector: &MessageVector
         )?;
     }
 
-    return Err(anyhow!(
-        "wrong post root cid; expected {}, but got {}",
-        &vector.postconditions.state_tree.root_cid,
-        root
-    ));
+    Ok(())
 }
 
 /// Represents the result from running a vector.
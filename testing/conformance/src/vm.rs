@@ -2,14 +2,14 @@ use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 use cid::Cid;
-use futures::executor::block_on;
-use fvm::call_manager::{Backtrace, CallManager, DefaultCallManager, InvocationResult};
+use fvm::call_manager::{
+    CallManager, DefaultCallManager, ExecutionEvent, ExecutionLimiter, FinishRet, InvocationResult,
+};
 use fvm::gas::{GasTracker, PriceList};
 use fvm::kernel::*;
-use fvm::machine::{DefaultMachine, Engine, Machine, MachineContext};
+use fvm::machine::{load_bundles, DefaultMachine, Engine, Machine, MachineContext};
 use fvm::state_tree::{ActorState, StateTree};
 use fvm::{Config, DefaultKernel};
-use fvm_ipld_car::load_car;
 use fvm_shared::actor::builtin::Manifest;
 use fvm_shared::address::Address;
 use fvm_shared::bigint::BigInt;
@@ -65,7 +65,7 @@ impl TestMachine<Box<DefaultMachine<MemoryBlockstore, TestExterns>>> {
         let externs = TestExterns::new(&v.randomness);
 
         // Load the builtin actors bundles into the blockstore.
-        let nv_actors = TestMachine::import_actors(&blockstore);
+        let nv_actors = TestMachine::import_actors(&blockstore, &engine);
 
         // Get the builtin actors index for the concrete network version.
         let builtin_actors = nv_actors
@@ -79,6 +79,9 @@ impl TestMachine<Box<DefaultMachine<MemoryBlockstore, TestExterns>>> {
                 initial_pages: 0,
                 max_pages: 1024,
                 debug: true, // Enable debug mode by default.
+                tracing: false,
+                gas_profiling: false,
+                ..Config::default()
             },
             engine,
             epoch,
@@ -92,7 +95,7 @@ impl TestMachine<Box<DefaultMachine<MemoryBlockstore, TestExterns>>> {
         )
         .unwrap();
 
-        let price_list = machine.context().price_list.clone();
+        let price_list = machine.context().network.price_list.clone();
 
         TestMachine::<Box<DefaultMachine<_, _>>> {
             machine: Box::new(machine),
@@ -107,16 +110,12 @@ impl TestMachine<Box<DefaultMachine<MemoryBlockstore, TestExterns>>> {
         }
     }
 
-    pub fn import_actors(blockstore: &MemoryBlockstore) -> BTreeMap<NetworkVersion, Cid> {
+    pub fn import_actors(
+        blockstore: &MemoryBlockstore,
+        engine: &Engine,
+    ) -> BTreeMap<NetworkVersion, Cid> {
         let bundles = [(NetworkVersion::V14, actors_v6::BUNDLE_CAR)];
-        bundles
-            .into_iter()
-            .map(|(nv, car)| {
-                let roots = block_on(async { load_car(blockstore, car).await.unwrap() });
-                assert_eq!(roots.len(), 1);
-                (nv, roots[0])
-            })
-            .collect()
+        load_bundles(blockstore, engine, bundles).expect("failed to load actor bundles")
     }
 }
 
@@ -167,6 +166,14 @@ where
         self.machine.transfer(from, to, value)
     }
 
+    fn begin_transaction(&mut self) {
+        self.machine.begin_transaction()
+    }
+
+    fn end_transaction(&mut self, revert: bool) -> Result<()> {
+        self.machine.end_transaction(revert)
+    }
+
     fn consume(self) -> Self::Blockstore {
         self.machine.consume()
     }
@@ -222,7 +229,7 @@ where
         })
     }
 
-    fn finish(self) -> (i64, Backtrace, Self::Machine) {
+    fn finish(self) -> (FinishRet, Self::Machine) {
         self.0.finish()
     }
 
@@ -242,6 +249,10 @@ where
         self.0.gas_tracker_mut()
     }
 
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter {
+        self.0.limiter_mut()
+    }
+
     fn origin(&self) -> Address {
         self.0.origin()
     }
@@ -307,6 +318,7 @@ where
         to: ActorID,
         method: MethodNum,
         value_received: TokenAmount,
+        metered: bool,
     ) -> Self
     where
         Self: Sized,
@@ -315,7 +327,14 @@ where
         let data = mgr.machine().data.clone();
 
         TestKernel(
-            K::new(TestCallManager(mgr), from, to, method, value_received),
+            K::new(
+                TestCallManager(mgr),
+                from,
+                to,
+                method,
+                value_received,
+                metered,
+            ),
             data,
         )
     }
@@ -343,6 +362,10 @@ where
         self.0.create_actor(code_id, actor_id)
     }
 
+    fn install_actor(&mut self, code: &[u8]) -> Result<Cid> {
+        self.0.install_actor(code)
+    }
+
     fn resolve_builtin_actor_type(&self, code_cid: &Cid) -> Option<actor::builtin::Type> {
         self.0.resolve_builtin_actor_type(code_cid)
     }
@@ -370,7 +393,7 @@ where
         self.0.block_link(id, hash_fun, hash_len)
     }
 
-    fn block_read(&self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32> {
+    fn block_read(&mut self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<u32> {
         self.0.block_read(id, offset, buf)
     }
 
@@ -378,9 +401,13 @@ where
         self.0.block_stat(id)
     }
 
-    fn block_get(&self, id: BlockId) -> Result<(u64, Vec<u8>)> {
+    fn block_get(&mut self, id: BlockId) -> Result<(u64, Vec<u8>)> {
         self.0.block_get(id)
     }
+
+    fn block_flush(&mut self, ret: BlockId) -> Result<()> {
+        self.0.block_flush(ret)
+    }
 }
 
 impl<M, C, K> CircSupplyOps for TestKernel<K>
@@ -485,6 +512,21 @@ where
     fn debug_enabled(&self) -> bool {
         self.0.debug_enabled()
     }
+
+    fn trace(&mut self, event: ExecutionEvent) {
+        self.0.trace(event)
+    }
+}
+
+impl<M, C, K> LimiterOps for TestKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = TestMachine<M>>,
+    K: Kernel<CallManager = TestCallManager<C>>,
+{
+    fn limiter_mut(&mut self) -> &mut ExecutionLimiter {
+        self.0.limiter_mut()
+    }
 }
 
 impl<M, C, K> GasOps for TestKernel<K>
@@ -496,6 +538,10 @@ where
     fn charge_gas(&mut self, name: &str, compute: i64) -> Result<()> {
         self.0.charge_gas(name, compute)
     }
+
+    fn price_list(&self) -> &PriceList {
+        self.0.price_list()
+    }
 }
 
 impl<M, C, K> MessageOps for TestKernel<K>
@@ -538,6 +584,10 @@ where
     fn network_base_fee(&self) -> &TokenAmount {
         self.0.network_base_fee()
     }
+
+    fn tipset_cid(&mut self, epoch: ChainEpoch) -> Result<Option<Cid>> {
+        self.0.tipset_cid(epoch)
+    }
 }
 
 impl<M, C, K> RandomnessOps for TestKernel<K>